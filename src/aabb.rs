@@ -1,5 +1,7 @@
-use cgmath::Vector2;
+use cgmath::{ Vector2, InnerSpace };
+use crate::ray::Ray;
 
+#[derive(Debug, Copy, Clone, PartialEq)]
 pub struct AABB {
     pub position: Vector2<f64>,
     pub size: Vector2<f64>
@@ -20,6 +22,20 @@ impl AABB {
         }
     }
 
+    pub fn from_points(points: impl IntoIterator<Item = Vector2<f64>>) -> Option<AABB> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+
+        let (min, max) = points.fold((first, first), |(min, max), point| {
+            (
+                Vector2 { x: min.x.min(point.x), y: min.y.min(point.y) },
+                Vector2 { x: max.x.max(point.x), y: max.y.max(point.y) }
+            )
+        });
+
+        Some(AABB::from_position_and_size(min, max - min))
+    }
+
     pub fn min(&self) -> Vector2<f64> {
         self.position
     }
@@ -31,5 +47,252 @@ impl AABB {
     pub fn mid(&self) -> Vector2<f64> {
         self.position + self.size * 0.5
     }
+
+    /// The four equal boxes split at `mid()`, ordered NW, NE, SW, SE. North is towards
+    /// negative y, matching the grid's top-left origin
+    pub fn quadrants(&self) -> [AABB; 4] {
+        let half = self.size * 0.5;
+        let mid = self.mid();
+        [
+            AABB::from_position_and_size(self.position, half),
+            AABB::from_position_and_size(Vector2::new(mid.x, self.position.y), half),
+            AABB::from_position_and_size(Vector2::new(self.position.x, mid.y), half),
+            AABB::from_position_and_size(mid, half)
+        ]
+    }
+
+    /// The point on or inside the box nearest to `point`
+    pub fn closest_point(&self, point: Vector2<f64>) -> Vector2<f64> {
+        Vector2 {
+            x: point.x.clamp(self.min().x, self.max().x),
+            y: point.y.clamp(self.min().y, self.max().y)
+        }
+    }
+
+    /// Clips the segment `start + (end - start) * t` for `t` in `[0, 1]` against the box,
+    /// returning the range of `t` that lies inside it
+    pub fn clip_segment(&self, start: Vector2<f64>, end: Vector2<f64>) -> Option<(f64, f64)> {
+        self.clip_parametric(start, end - start, 1.0)
+    }
+
+    /// Range of the ray parameter that lies inside the box, up to the ray's `max_distance`
+    fn clip_ray(&self, ray: &Ray) -> Option<(f64, f64)> {
+        self.clip_parametric(ray.origin, ray.direction, ray.max_distance.unwrap_or(f64::INFINITY))
+    }
+
+    /// Clips `start + delta * t` for `t` in `[0, t_max]` against the box
+    fn clip_parametric(&self, start: Vector2<f64>, delta: Vector2<f64>, t_max: f64) -> Option<(f64, f64)> {
+        let mut t_enter = 0.0_f64;
+        let mut t_exit = t_max;
+
+        for (p, d, min, max) in [
+            (start.x, delta.x, self.min().x, self.max().x),
+            (start.y, delta.y, self.min().y, self.max().y)
+        ] {
+            if d == 0.0 {
+                if p < min || p > max {
+                    return None
+                }
+                continue;
+            }
+
+            let (t1, t2) = {
+                let t1 = (min - p) / d;
+                let t2 = (max - p) / d;
+                if t1 > t2 { (t2, t1) } else { (t1, t2) }
+            };
+            t_enter = t_enter.max(t1);
+            t_exit = t_exit.min(t2);
+            if t_enter > t_exit {
+                return None
+            }
+        }
+
+        Some((t_enter, t_exit))
+    }
+
+    /// Points `step` apart along the part of the ray inside the box, from where the ray
+    /// enters the box up to where it leaves. Panics if `step` is not positive
+    pub fn march(&self, ray: &Ray, step: f64) -> impl Iterator<Item = Vector2<f64>> {
+        assert!(step > 0.0, "march step must be positive, got {}", step);
+
+        let length = ray.direction.magnitude();
+        let span = if length > 0.0 { self.clip_ray(ray) } else { None };
+        let (sample_count, entry, step_vector) = match span {
+            Some((t_enter, t_exit)) => (
+                ((t_exit - t_enter) * length / step).floor() as usize + 1,
+                ray.origin + ray.direction * t_enter,
+                ray.direction * (step / length)
+            ),
+            None => (0, ray.origin, Vector2::new(0.0, 0.0))
+        };
+
+        (0..sample_count).map(move |i| entry + step_vector * i as f64)
+    }
+
+    /// The region shared by both boxes. Boxes which only touch along an edge or at a
+    /// corner have no area in common and do not overlap
+    pub fn overlap(&self, other: &AABB) -> Option<AABB> {
+        let min = Vector2 {
+            x: self.min().x.max(other.min().x),
+            y: self.min().y.max(other.min().y)
+        };
+        let max = Vector2 {
+            x: self.max().x.min(other.max().x),
+            y: self.max().y.min(other.max().y)
+        };
+
+        if max.x <= min.x || max.y <= min.y {
+            return None
+        }
+        Some(AABB::from_position_and_size(min, max - min))
+    }
+
+    /// Interpolates position and size componentwise, returning `self` at `t = 0` and
+    /// `other` at `t = 1`. `t` is not clamped, so values outside `[0, 1]` extrapolate
+    pub fn lerp(&self, other: &AABB, t: f64) -> AABB {
+        AABB {
+            position: self.position * (1.0 - t) + other.position * t,
+            size: self.size * (1.0 - t) + other.size * t
+        }
+    }
+
+    /// Shortest distance between the segment and the box; zero when they overlap
+    pub fn distance_to_segment(&self, start: Vector2<f64>, end: Vector2<f64>) -> f64 {
+        if self.clip_segment(start, end).is_some() {
+            return 0.0
+        }
+
+        // Neither shape is crossing the other, so the closest pair involves an endpoint of
+        // the segment or a corner of the box
+        let corners = [
+            self.min(),
+            Vector2::new(self.max().x, self.min().y),
+            self.max(),
+            Vector2::new(self.min().x, self.max().y)
+        ];
+
+        let endpoint_distance = [start, end].into_iter()
+            .map(|point| (self.closest_point(point) - point).magnitude());
+        let corner_distance = corners.into_iter()
+            .map(|corner| distance_to_segment(corner, start, end));
+
+        endpoint_distance.chain(corner_distance).fold(f64::MAX, f64::min)
+    }
 }
 
+fn distance_to_segment(point: Vector2<f64>, start: Vector2<f64>, end: Vector2<f64>) -> f64 {
+    let delta = end - start;
+    let length_squared = delta.magnitude2();
+    let t = if length_squared == 0.0 {
+        0.0
+    } else {
+        ((point - start).dot(delta) / length_squared).clamp(0.0, 1.0)
+    };
+    (start + delta * t - point).magnitude()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_points() {
+        let aabb = AABB::from_points([
+            Vector2::new(1.0, 2.0),
+            Vector2::new(-3.0, 0.5),
+            Vector2::new(4.0, -1.5),
+            Vector2::new(0.0, 6.0),
+            Vector2::new(-0.5, -0.5)
+        ]).unwrap();
+
+        assert_eq!(aabb.min(), Vector2::new(-3.0, -1.5));
+        assert_eq!(aabb.max(), Vector2::new(4.0, 6.0));
+        assert_eq!(aabb.size, Vector2::new(7.0, 7.5));
+
+        let single = AABB::from_points([Vector2::new(2.0, 3.0)]).unwrap();
+        assert_eq!(single.position, Vector2::new(2.0, 3.0));
+        assert_eq!(single.size, Vector2::new(0.0, 0.0));
+
+        assert!(AABB::from_points(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn test_distance_to_segment() {
+        let aabb = AABB::from_position_and_size(Vector2::new(0.0, 0.0), Vector2::new(2.0, 2.0));
+
+        assert_eq!(aabb.distance_to_segment(Vector2::new(-1.0, 1.0), Vector2::new(3.0, 1.0)), 0.0);
+        assert_eq!(aabb.distance_to_segment(Vector2::new(-1.0, 3.0), Vector2::new(3.0, 3.0)), 1.0);
+        assert_eq!(aabb.distance_to_segment(Vector2::new(5.0, 1.0), Vector2::new(7.0, 1.0)), 3.0);
+
+        let corner_distance = aabb.distance_to_segment(Vector2::new(2.0, 4.0), Vector2::new(4.0, 2.0));
+        assert!((corner_distance - 2.0_f64.sqrt()).abs() < 1e-9);
+
+        assert_eq!(aabb.clip_segment(Vector2::new(-2.0, 1.0), Vector2::new(2.0, 1.0)), Some((0.5, 1.0)));
+        assert_eq!(aabb.clip_segment(Vector2::new(-2.0, 3.0), Vector2::new(2.0, 3.0)), None);
+    }
+
+    #[test]
+    fn test_march() {
+        let aabb = AABB::from_position_and_size(Vector2::new(0.0, 0.0), Vector2::new(4.0, 2.0));
+        let ray = Ray {
+            origin: Vector2::new(-1.0, 1.0),
+            direction: Vector2::new(2.0, 0.0),
+            max_distance: None
+        };
+
+        let samples: Vec<Vector2<f64>> = aabb.march(&ray, 0.5).collect();
+        assert_eq!(samples.len(), 9);
+        assert_eq!(samples[0], Vector2::new(0.0, 1.0));
+        assert_eq!(samples[8], Vector2::new(4.0, 1.0));
+
+        assert_eq!(aabb.march(&ray, 1.5).count(), 3);
+
+        let short = Ray { max_distance: Some(1.0), ..ray };
+        assert_eq!(aabb.march(&short, 0.5).last(), Some(Vector2::new(1.0, 1.0)));
+
+        let missing = Ray { origin: Vector2::new(-1.0, 3.0), ..ray };
+        assert_eq!(aabb.march(&missing, 0.5).count(), 0);
+    }
+
+    #[test]
+    fn test_lerp() {
+        let a = AABB::from_position_and_size(Vector2::new(0.1, -2.0), Vector2::new(1.0, 0.3));
+        let b = AABB::from_position_and_size(Vector2::new(4.0, 2.0), Vector2::new(3.0, 0.7));
+
+        assert_eq!(a.lerp(&b, 0.0), a);
+        assert_eq!(a.lerp(&b, 1.0), b);
+
+        let mid = AABB::from_position_and_size(Vector2::new(0.0, 0.0), Vector2::new(2.0, 2.0))
+            .lerp(&AABB::from_position_and_size(Vector2::new(4.0, -2.0), Vector2::new(4.0, 1.0)), 0.5);
+        assert_eq!(mid, AABB::from_position_and_size(Vector2::new(2.0, -1.0), Vector2::new(3.0, 1.5)));
+
+        let beyond = AABB::new().lerp(&AABB::from_position_and_size(Vector2::new(1.0, 1.0), Vector2::new(2.0, 2.0)), 2.0);
+        assert_eq!(beyond.position, Vector2::new(2.0, 2.0));
+    }
+
+    #[test]
+    fn test_quadrants_tile_parent() {
+        let parent = AABB::from_position_and_size(Vector2::new(-2.0, 1.0), Vector2::new(6.0, 4.0));
+        let quadrants = parent.quadrants();
+
+        let [north_west, north_east, south_west, south_east] = quadrants;
+        assert_eq!(north_west.min(), parent.min());
+        assert_eq!(north_east.min(), Vector2::new(1.0, 1.0));
+        assert_eq!(south_west.min(), Vector2::new(-2.0, 3.0));
+        assert_eq!(south_east.max(), parent.max());
+
+        let union = AABB::from_points(quadrants.iter().flat_map(|q| [q.min(), q.max()])).unwrap();
+        assert_eq!(union, parent);
+
+        let area = |aabb: &AABB| aabb.size.x * aabb.size.y;
+        assert_eq!(quadrants.iter().map(area).sum::<f64>(), area(&parent));
+
+        for (i, a) in quadrants.iter().enumerate() {
+            for b in &quadrants[i + 1..] {
+                assert!(a.overlap(b).is_none());
+            }
+        }
+    }
+}
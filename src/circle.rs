@@ -0,0 +1,15 @@
+use cgmath::Vector2;
+
+pub struct Circle {
+    pub centre: Vector2<f64>,
+    pub radius: f64
+}
+
+impl Circle {
+    pub fn new(centre: Vector2<f64>, radius: f64) -> Circle {
+        Circle {
+            centre,
+            radius
+        }
+    }
+}
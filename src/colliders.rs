@@ -1,3 +1,83 @@
 pub use crate::aabb::AABB;
+#[allow(unused_imports)]
+pub use crate::quadtree::Quadtree;
 pub use crate::ray::Ray;
 pub use crate::collision::Collidable;
+
+/// Index pairs `(low, high)` of overlapping boxes. Boxes are sorted along x so each is
+/// only tested against those whose x extent it reaches, rather than every other box
+pub fn sweep_and_prune(boxes: &[AABB]) -> Vec<(usize, usize)> {
+    let mut order: Vec<usize> = (0..boxes.len()).collect();
+    order.sort_by(|&a, &b| boxes[a].position.x.total_cmp(&boxes[b].position.x));
+
+    let mut active: Vec<usize> = Vec::new();
+    let mut pairs = Vec::new();
+    for index in order {
+        let aabb = &boxes[index];
+        active.retain(|&other| boxes[other].position.x + boxes[other].size.x >= aabb.position.x);
+
+        for &other in &active {
+            if aabb.does_collide(&boxes[other]) {
+                pairs.push((other.min(index), other.max(index)));
+            }
+        }
+        active.push(index);
+    }
+    pairs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use cgmath::Vector2;
+    use crate::test_support::Lcg;
+
+    fn aabb(x: f64, y: f64, width: f64, height: f64) -> AABB {
+        AABB {
+            position: Vector2::new(x, y),
+            size: Vector2::new(width, height)
+        }
+    }
+
+    fn brute_force(boxes: &[AABB]) -> HashSet<(usize, usize)> {
+        let mut pairs = HashSet::new();
+        for a in 0..boxes.len() {
+            for b in (a + 1)..boxes.len() {
+                if boxes[a].does_collide(&boxes[b]) {
+                    pairs.insert((a, b));
+                }
+            }
+        }
+        pairs
+    }
+
+    #[test]
+    fn test_sweep_and_prune() {
+        let boxes = [
+            aabb(0.0, 0.0, 2.0, 2.0),
+            aabb(1.0, 1.0, 2.0, 2.0),
+            aabb(10.0, 0.0, 1.0, 1.0),
+            aabb(-5.0, 0.5, 5.5, 1.0)
+        ];
+        let pairs: HashSet<_> = sweep_and_prune(&boxes).into_iter().collect();
+        assert_eq!(pairs, HashSet::from([(0, 1), (0, 3)]));
+        assert!(sweep_and_prune(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_sweep_and_prune_matches_brute_force() {
+        let mut rng = Lcg::new(0x2545_f491_4f6c_dd1d);
+        let mut next = || rng.next_f64();
+
+        let boxes: Vec<AABB> = (0..300)
+            .map(|_| aabb(next() * 100.0, next() * 100.0, next() * 10.0, next() * 10.0))
+            .collect();
+
+        let pairs = sweep_and_prune(&boxes);
+        let unique: HashSet<_> = pairs.iter().copied().collect();
+        assert_eq!(unique.len(), pairs.len());
+        assert_eq!(unique, brute_force(&boxes));
+        assert!(!unique.is_empty());
+    }
+}
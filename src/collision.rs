@@ -1,12 +1,25 @@
-use cgmath::Vector2;
+use cgmath::{ Vector2, InnerSpace };
 use crate::ray::Ray;
 use crate::aabb::AABB;
+use crate::circle::Circle;
 
-pub struct IntersectInfo {
-    pub position: Vector2<f64>,
+/// Where two shapes first meet, as a point of their shared dimension
+pub struct IntersectInfo<P = Vector2<f64>> {
+    pub position: P,
 }
 
-pub trait Collidable<T> {
+/// How far a shape has penetrated a box. `normal` is the unit direction the shape must
+/// move along to leave the box and `depth` how far it must move
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ContactInfo {
+    pub normal: Vector2<f64>,
+    pub depth: f64
+}
+
+/// Tests between two shapes of the same dimension, which share a `Bounds` type. A 2D
+/// shape can only be tested against other 2D shapes and points
+pub trait Collidable<T>: Shape where
+    T: Shape<Bounds = <Self as Shape>::Bounds> {
     type IntersectReturn;
     type CollisionReturn;
 
@@ -15,6 +28,58 @@ pub trait Collidable<T> {
     fn does_collide(&self, other: &T) -> Self::CollisionReturn;
 }
 
+/// A collider that broadphase structures can index by its bounds. `Bounds` is the box
+/// type of the shape's dimension, so 2D shapes are bounded by an `AABB`
+pub trait Shape {
+    type Bounds;
+
+    fn bounding_box(&self) -> Self::Bounds;
+}
+
+/// Any 2D collider, for collections holding a mix of shapes
+pub type Shape2D = dyn Shape<Bounds = AABB>;
+
+impl Shape for AABB {
+    type Bounds = AABB;
+
+    fn bounding_box(&self) -> AABB {
+        *self
+    }
+}
+
+impl Shape for Circle {
+    type Bounds = AABB;
+
+    fn bounding_box(&self) -> AABB {
+        let radius = Vector2::new(self.radius, self.radius);
+        AABB::from_position_and_size(self.centre - radius, radius * 2.0)
+    }
+}
+
+/// A point is bounded by an empty box at its position
+impl Shape for Vector2<f64> {
+    type Bounds = AABB;
+
+    fn bounding_box(&self) -> AABB {
+        AABB::from_position_and_size(*self, Vector2::new(0.0, 0.0))
+    }
+}
+
+/// Rays without a `max_distance` are bounded as reaching `f64::MAX` along each axis they
+/// travel in
+impl Shape for Ray {
+    type Bounds = AABB;
+
+    fn bounding_box(&self) -> AABB {
+        let reach = self.direction * self.max_distance.unwrap_or(f64::MAX);
+        let end = Vector2::new(
+            (self.origin.x + reach.x).clamp(f64::MIN, f64::MAX),
+            (self.origin.y + reach.y).clamp(f64::MIN, f64::MAX)
+        );
+        AABB::from_points([self.origin, end]).unwrap()
+    }
+}
+
 impl Collidable<Ray> for AABB {
     type IntersectReturn = Option<IntersectInfo>;
     type CollisionReturn = Self::IntersectReturn;
@@ -49,7 +114,7 @@ impl Collidable<Ray> for AABB {
             };
 
             tmin = tmin.max(t1);
-            tmax = tmax.max(t2);
+            tmax = tmax.min(t2);
 
             tmin <= tmax
         };
@@ -136,3 +201,332 @@ impl Collidable<AABB> for AABB {
         self.does_intersect(other) || other.does_intersect(self)
     }
 }
+
+impl AABB {
+    pub fn circle_contact(&self, circle: &Circle) -> Option<ContactInfo> {
+        let closest = self.closest_point(circle.centre);
+        let offset = circle.centre - closest;
+        let distance2 = offset.magnitude2();
+
+        if distance2 > 0.0 {
+            if distance2 >= circle.radius * circle.radius {
+                return None
+            }
+
+            let distance = distance2.sqrt();
+            return Some(ContactInfo {
+                normal: offset / distance,
+                depth: circle.radius - distance
+            })
+        }
+
+        // centre is inside the box, so push out through the nearest face
+        let min = self.min();
+        let max = self.max();
+        let faces = [
+            (circle.centre.x - min.x, Vector2::new(-1.0, 0.0)),
+            (max.x - circle.centre.x, Vector2::new(1.0, 0.0)),
+            (circle.centre.y - min.y, Vector2::new(0.0, -1.0)),
+            (max.y - circle.centre.y, Vector2::new(0.0, 1.0))
+        ];
+        let (face_distance, normal) = faces.into_iter()
+            .min_by(|(a, _), (b, _)| a.total_cmp(b))
+            .unwrap();
+
+        Some(ContactInfo {
+            normal,
+            depth: circle.radius + face_distance
+        })
+    }
+
+    /// Minimum translation which moves this box out of `other`, pushing along whichever
+    /// axis has the least penetration. Boxes that only touch have zero penetration and
+    /// return `None`
+    pub fn resolve(&self, other: &AABB) -> Option<Vector2<f64>> {
+        let overlap = self.overlap(other)?;
+        let away_x = if self.mid().x < other.mid().x { -1.0 } else { 1.0 };
+        let away_y = if self.mid().y < other.mid().y { -1.0 } else { 1.0 };
+
+        if overlap.size.x < overlap.size.y {
+            Some(Vector2::new(overlap.size.x * away_x, 0.0))
+        } else {
+            Some(Vector2::new(0.0, overlap.size.y * away_y))
+        }
+    }
+}
+
+impl Collidable<Circle> for AABB {
+    type IntersectReturn = Option<ContactInfo>;
+    type CollisionReturn = Self::IntersectReturn;
+
+    fn does_intersect(&self, circle: &Circle) -> Self::IntersectReturn {
+        self.circle_contact(circle)
+    }
+
+    fn does_contain(&self, circle: &Circle) -> bool {
+        let radius = Vector2::new(circle.radius, circle.radius);
+        let min = circle.centre - radius;
+        let max = circle.centre + radius;
+        min.x >= self.min().x && min.y >= self.min().y &&
+        max.x <= self.max().x && max.y <= self.max().y
+    }
+
+    fn does_collide(&self, circle: &Circle) -> Self::CollisionReturn {
+        self.does_intersect(circle)
+    }
+}
+
+impl Circle {
+    /// Entry and exit points of the ray through the circle ordered by distance along the
+    /// ray. Both points are clamped to the portion of the ray between its origin and
+    /// `max_distance`
+    pub fn intersect_ray_both(&self, ray: &Ray) -> Option<(Vector2<f64>, Vector2<f64>)> {
+        let (t_entry, t_exit) = self.ray_interval(ray)?;
+        Some((ray.origin + ray.direction * t_entry, ray.origin + ray.direction * t_exit))
+    }
+
+    fn ray_interval(&self, ray: &Ray) -> Option<(f64, f64)> {
+        let relative_origin = ray.origin - self.centre;
+        let a = ray.direction.magnitude2();
+        let b = 2.0 * relative_origin.dot(ray.direction);
+        let c = relative_origin.magnitude2() - self.radius * self.radius;
+
+        let discriminant = b * b - 4.0 * a * c;
+        if a == 0.0 || discriminant < 0.0 {
+            return None
+        }
+
+        let discriminant_root = discriminant.sqrt();
+        let t_entry = (-b - discriminant_root) / (2.0 * a);
+        let t_exit = (-b + discriminant_root) / (2.0 * a);
+
+        let max_distance = ray.max_distance.unwrap_or(f64::MAX);
+        if t_exit < 0.0 || t_entry > max_distance {
+            return None
+        }
+
+        Some((t_entry.max(0.0), t_exit.min(max_distance)))
+    }
+}
+
+impl Collidable<Ray> for Circle {
+    type IntersectReturn = Option<IntersectInfo>;
+    type CollisionReturn = Self::IntersectReturn;
+
+    fn does_intersect(&self, ray: &Ray) -> Self::IntersectReturn {
+        self.ray_interval(ray).map(|(t_entry, _)| IntersectInfo {
+            position: ray.origin + ray.direction * t_entry
+        })
+    }
+
+    fn does_contain(&self, ray: &Ray) -> bool {
+        let Some(max_distance) = ray.max_distance else {
+            return false
+        };
+        let end = ray.origin + ray.direction * max_distance;
+        (ray.origin - self.centre).magnitude2() <= self.radius * self.radius &&
+        (end - self.centre).magnitude2() <= self.radius * self.radius
+    }
+
+    fn does_collide(&self, ray: &Ray) -> Self::CollisionReturn {
+        self.does_intersect(ray)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-9;
+
+    fn assert_close(a: Vector2<f64>, b: Vector2<f64>) {
+        assert!((a - b).magnitude() < EPSILON, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_circle_ray_secant() {
+        let circle = Circle::new(Vector2::new(5.0, 0.0), 2.0);
+        let ray = Ray {
+            origin: Vector2::new(0.0, 0.0),
+            direction: Vector2::new(1.0, 0.0),
+            max_distance: None
+        };
+
+        let (entry, exit) = circle.intersect_ray_both(&ray).unwrap();
+        assert_close(entry, Vector2::new(3.0, 0.0));
+        assert_close(exit, Vector2::new(7.0, 0.0));
+        assert_close(circle.does_intersect(&ray).unwrap().position, entry);
+
+        let short_ray = Ray { max_distance: Some(4.0), ..ray };
+        let (entry, exit) = circle.intersect_ray_both(&short_ray).unwrap();
+        assert_close(entry, Vector2::new(3.0, 0.0));
+        assert_close(exit, Vector2::new(4.0, 0.0));
+    }
+
+    #[test]
+    fn test_circle_ray_tangent() {
+        let circle = Circle::new(Vector2::new(5.0, 2.0), 2.0);
+        let ray = Ray {
+            origin: Vector2::new(0.0, 0.0),
+            direction: Vector2::new(1.0, 0.0),
+            max_distance: None
+        };
+
+        let (entry, exit) = circle.intersect_ray_both(&ray).unwrap();
+        assert_close(entry, Vector2::new(5.0, 0.0));
+        assert_close(exit, Vector2::new(5.0, 0.0));
+    }
+
+    #[test]
+    fn test_circle_ray_miss() {
+        let circle = Circle::new(Vector2::new(5.0, 3.0), 2.0);
+        let ray = Ray {
+            origin: Vector2::new(0.0, 0.0),
+            direction: Vector2::new(1.0, 0.0),
+            max_distance: None
+        };
+        assert!(circle.intersect_ray_both(&ray).is_none());
+        assert!(circle.does_intersect(&ray).is_none());
+
+        let behind = Ray {
+            origin: Vector2::new(10.0, 3.0),
+            direction: Vector2::new(1.0, 0.0),
+            max_distance: None
+        };
+        assert!(circle.intersect_ray_both(&behind).is_none());
+    }
+
+    fn unit_box() -> AABB {
+        AABB::from_position_and_size(Vector2::new(0.0, 0.0), Vector2::new(2.0, 2.0))
+    }
+
+    #[test]
+    fn test_aabb_ray() {
+        let aabb = AABB::from_position_and_size(Vector2::new(0.0, 0.0), Vector2::new(1.0, 1.0));
+        let ray = Ray {
+            origin: Vector2::new(-2.0, 0.5),
+            direction: Vector2::new(1.0, 0.0),
+            max_distance: None
+        };
+        assert_close(aabb.does_intersect(&ray).unwrap().position, Vector2::new(0.0, 0.5));
+
+        // Stops before reaching the box
+        let short_ray = Ray { max_distance: Some(1.0), ..ray };
+        assert!(aabb.does_intersect(&short_ray).is_none());
+
+        // Crosses the x and y slabs at disjoint ray parameters, passing by the corner
+        let diagonal = Ray {
+            origin: Vector2::new(0.5, -2.0),
+            direction: Vector2::new(1.0, 1.0),
+            max_distance: None
+        };
+        assert!(aabb.does_intersect(&diagonal).is_none());
+    }
+
+    #[test]
+    fn test_circle_contact_side() {
+        let contact = unit_box().circle_contact(&Circle::new(Vector2::new(2.5, 1.0), 1.0)).unwrap();
+        assert_close(contact.normal, Vector2::new(1.0, 0.0));
+        assert!((contact.depth - 0.5).abs() < EPSILON);
+
+        assert!(unit_box().circle_contact(&Circle::new(Vector2::new(3.5, 1.0), 1.0)).is_none());
+    }
+
+    #[test]
+    fn test_circle_contact_corner() {
+        let circle = Circle::new(Vector2::new(2.5, 2.5), 1.0);
+        let contact = unit_box().circle_contact(&circle).unwrap();
+
+        let diagonal = Vector2::new(1.0, 1.0).normalize();
+        assert_close(contact.normal, diagonal);
+        assert!((contact.depth - (1.0 - 0.5_f64.sqrt())).abs() < EPSILON);
+
+        // inside the corner's bounding square but outside its radius
+        assert!(unit_box().circle_contact(&Circle::new(Vector2::new(2.8, 2.8), 1.0)).is_none());
+    }
+
+    #[test]
+    fn test_circle_contact_inside_box() {
+        let circle = Circle::new(Vector2::new(1.0, 0.25), 0.5);
+        let contact = unit_box().circle_contact(&circle).unwrap();
+        assert_close(contact.normal, Vector2::new(0.0, -1.0));
+        assert!((contact.depth - 0.75).abs() < EPSILON);
+
+        assert!(!unit_box().does_contain(&circle));
+        assert!(unit_box().does_contain(&Circle::new(Vector2::new(1.0, 1.0), 0.5)));
+    }
+
+    #[test]
+    fn test_resolve_deep_overlap() {
+        let wall = unit_box();
+        let mover = AABB::from_position_and_size(Vector2::new(1.5, 0.5), Vector2::new(2.0, 1.0));
+
+        let push = mover.resolve(&wall).unwrap();
+        assert_close(push, Vector2::new(0.5, 0.0));
+
+        let moved = AABB::from_position_and_size(mover.position + push, mover.size);
+        assert!(moved.resolve(&wall).is_none());
+
+        let above = AABB::from_position_and_size(Vector2::new(0.5, -0.75), Vector2::new(1.0, 1.0));
+        assert_close(above.resolve(&wall).unwrap(), Vector2::new(0.0, -0.25));
+    }
+
+    #[test]
+    fn test_resolve_touching() {
+        let wall = unit_box();
+        let beside = AABB::from_position_and_size(Vector2::new(2.0, 0.0), Vector2::new(1.0, 1.0));
+        let diagonal = AABB::from_position_and_size(Vector2::new(2.0, 2.0), Vector2::new(1.0, 1.0));
+
+        assert!(beside.resolve(&wall).is_none());
+        assert!(diagonal.resolve(&wall).is_none());
+        assert!(wall.overlap(&beside).is_none());
+    }
+
+    #[test]
+    fn test_collidable_same_dimension() {
+        // Written once against the trait, for any pair of shapes sharing a dimension
+        fn contained<S, T>(shape: &S, others: &[T]) -> usize where
+            S: Collidable<T>,
+            T: Shape<Bounds = S::Bounds> {
+            others.iter().filter(|other| shape.does_contain(*other)).count()
+        }
+
+        let points = [Vector2::new(0.5, 0.5), Vector2::new(3.0, 0.5)];
+        assert_eq!(contained(&unit_box(), &points), 1);
+        let boxes = [
+            AABB::from_position_and_size(Vector2::new(0.5, 0.5), Vector2::new(1.0, 1.0)),
+            AABB::from_position_and_size(Vector2::new(5.0, 5.0), Vector2::new(1.0, 1.0))
+        ];
+        assert_eq!(contained(&unit_box(), &boxes), 1);
+        assert_eq!(Vector2::new(2.0, 3.0).bounding_box(), AABB::from_position_and_size(Vector2::new(2.0, 3.0), Vector2::new(0.0, 0.0)));
+    }
+
+    #[test]
+    fn test_mixed_shape_bounds() {
+        let shapes: Vec<Box<Shape2D>> = vec![
+            Box::new(unit_box()),
+            Box::new(Circle::new(Vector2::new(5.0, 5.0), 1.5)),
+            Box::new(Ray {
+                origin: Vector2::new(3.0, 1.0),
+                direction: Vector2::new(-1.0, 2.0),
+                max_distance: Some(2.0)
+            })
+        ];
+
+        let bounds: Vec<AABB> = shapes.iter().map(|shape| shape.bounding_box()).collect();
+        assert_eq!(bounds, vec![
+            unit_box(),
+            AABB::from_position_and_size(Vector2::new(3.5, 3.5), Vector2::new(3.0, 3.0)),
+            AABB::from_position_and_size(Vector2::new(1.0, 1.0), Vector2::new(2.0, 4.0))
+        ]);
+
+        let unbounded = Ray {
+            origin: Vector2::new(1.0, 1.0),
+            direction: Vector2::new(2.0, 0.0),
+            max_distance: None
+        }.bounding_box();
+        assert_eq!(unbounded.min(), Vector2::new(1.0, 1.0));
+        assert_eq!(unbounded.max().x, f64::MAX);
+        assert_eq!(unbounded.size.y, 0.0);
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::{ Instant, Duration };
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Redraw {
+    Now,
+    WaitUntil(Instant)
+}
+
+pub fn frame_interval(target_fps: u32) -> Duration {
+    Duration::from_secs(1) / target_fps.max(1)
+}
+
+/// Whether a frame should be drawn now, or when the next one is due if the frame rate
+/// is capped
+pub fn next_redraw(last_redraw: Instant, now: Instant, target_fps: Option<u32>) -> Redraw {
+    let Some(target_fps) = target_fps else {
+        return Redraw::Now
+    };
+
+    let due = last_redraw + frame_interval(target_fps);
+    if now >= due {
+        Redraw::Now
+    } else {
+        Redraw::WaitUntil(due)
+    }
+}
+
+/// Most fixed updates run in one frame. Time beyond this is dropped so a slow frame
+/// can't cause a backlog that makes every following frame slower
+pub const MAX_FIXED_UPDATES_PER_FRAME: u32 = 8;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct FixedUpdates {
+    /// Number of fixed updates to run this frame
+    pub steps: u32,
+    /// Time carried over to the next frame
+    pub accumulator: Duration,
+    /// How far between the last update and the next the frame is, from 0 to 1, for
+    /// rendering to interpolate by
+    pub alpha: f64
+}
+
+/// Adds `elapsed` frame time to `accumulator` and splits it into whole steps of `step`
+pub fn fixed_updates(accumulator: Duration, elapsed: Duration, step: Duration) -> FixedUpdates {
+    let mut accumulator = accumulator + elapsed;
+    let mut steps = 0;
+    while accumulator >= step && steps < MAX_FIXED_UPDATES_PER_FRAME {
+        accumulator -= step;
+        steps += 1;
+    }
+
+    if steps == MAX_FIXED_UPDATES_PER_FRAME {
+        accumulator = accumulator.min(step);
+    }
+
+    FixedUpdates {
+        steps,
+        accumulator,
+        alpha: (accumulator.as_secs_f64() / step.as_secs_f64()).min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next_redraw() {
+        let last_redraw = Instant::now();
+        let interval = frame_interval(50);
+        assert_eq!(interval, Duration::from_millis(20));
+
+        assert_eq!(next_redraw(last_redraw, last_redraw, None), Redraw::Now);
+        assert_eq!(
+            next_redraw(last_redraw, last_redraw + Duration::from_millis(5), Some(50)),
+            Redraw::WaitUntil(last_redraw + interval)
+        );
+        assert_eq!(next_redraw(last_redraw, last_redraw + interval, Some(50)), Redraw::Now);
+        assert_eq!(
+            next_redraw(last_redraw, last_redraw + Duration::from_millis(35), Some(50)),
+            Redraw::Now
+        );
+    }
+
+    #[test]
+    fn test_fixed_updates() {
+        let step = frame_interval(50);
+
+        let updates = fixed_updates(Duration::ZERO, Duration::from_millis(5), step);
+        assert_eq!((updates.steps, updates.accumulator), (0, Duration::from_millis(5)));
+        assert!((updates.alpha - 0.25).abs() < 1e-9);
+
+        // Leftover time from the last frame counts towards this one
+        let updates = fixed_updates(updates.accumulator, Duration::from_millis(50), step);
+        assert_eq!((updates.steps, updates.accumulator), (2, Duration::from_millis(15)));
+        assert!((updates.alpha - 0.75).abs() < 1e-9);
+
+        let updates = fixed_updates(Duration::ZERO, step, step);
+        assert_eq!((updates.steps, updates.accumulator, updates.alpha), (1, Duration::ZERO, 0.0));
+
+        // A long stall runs a bounded number of updates and drops the rest
+        let updates = fixed_updates(Duration::ZERO, Duration::from_secs(10), step);
+        assert_eq!(updates.steps, MAX_FIXED_UPDATES_PER_FRAME);
+        assert!(updates.accumulator <= step);
+    }
+}
@@ -1,12 +1,92 @@
 use crate::voxel::Voxel;
+use crate::render::QuadVertex;
 use crate::colliders::*;
 use cgmath::{ Vector2, InnerSpace };
 use std::hash::{ Hash, Hasher };
+use std::collections::{ BinaryHeap, HashMap, HashSet };
+use std::cmp::Reverse;
+use thiserror::Error;
 
 const VOXEL_COUNT_X: usize = 10;
 const VOXEL_COUNT_Y: usize = 10;
 const VOXEL_COUNT: usize = VOXEL_COUNT_X * VOXEL_COUNT_Y;
 
+const RLE_EMPTY: u8 = 0;
+const RLE_FILLED: u8 = 1;
+
+/// Maps a cell's coordinates to where it moves when the grid is rotated or mirrored
+type CellTransform = fn(u64, u64) -> (u64, u64);
+
+#[derive(Debug, Error, PartialEq)]
+pub enum DecodeError {
+    #[error("Input ended in the middle of a run")]
+    UnexpectedEnd,
+    #[error("Run has an unknown cell tag {0}")]
+    InvalidTag(u8),
+    #[error("Run has a length of zero")]
+    EmptyRun,
+    #[error("Runs describe {found} cells but a grid has {expected}")]
+    CellCountMismatch {
+        expected: usize,
+        found: usize
+    }
+}
+
+/// How `Grid::merge_from` resolves a filled cell landing on a filled cell
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MergePolicy {
+    /// The incoming voxel replaces the existing one
+    Overwrite,
+    /// The existing voxel is kept; incoming voxels only fill empty cells
+    KeepExisting,
+    /// Nothing is merged unless every incoming voxel lands on an empty cell
+    OnlyEmpty
+}
+
+/// Column and row of a cell in a `Grid`, with the origin in the top left
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct GridCoord(pub u64, pub u64);
+
+/// A position in world space. `SpatialGrid` converts between these and `GridCoord`s
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct WorldPos(pub Vector2<f64>);
+
+/// Set of the 4 faces of a voxel. The grid's y axis points down, so `UP` faces the
+/// row above
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct FaceMask(pub u8);
+
+impl FaceMask {
+    pub const NONE: FaceMask = FaceMask(0);
+    pub const UP: FaceMask = FaceMask(1 << 0);
+    pub const DOWN: FaceMask = FaceMask(1 << 1);
+    pub const LEFT: FaceMask = FaceMask(1 << 2);
+    pub const RIGHT: FaceMask = FaceMask(1 << 3);
+    pub const ALL: FaceMask = FaceMask(0b1111);
+
+    pub fn contains(&self, faces: FaceMask) -> bool {
+        self.0 & faces.0 == faces.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl std::ops::BitOr for FaceMask {
+    type Output = FaceMask;
+
+    fn bitor(self, rhs: FaceMask) -> FaceMask {
+        FaceMask(self.0 | rhs.0)
+    }
+}
+
+impl std::ops::BitOrAssign for FaceMask {
+    fn bitor_assign(&mut self, rhs: FaceMask) {
+        self.0 |= rhs.0;
+    }
+}
+
 pub struct Grid {
     elements: [Option<Voxel>; VOXEL_COUNT],
     hash: u128
@@ -14,14 +94,21 @@ pub struct Grid {
 
 impl Grid {
     pub fn new() -> Grid {
-        let elements = [None; VOXEL_COUNT];
-        Grid {
-            hash: elements.iter().enumerate().map(|(i, v)| {
-                let (x, y) = Grid::get_coords_from_index(i);
-                Grid::hash_for_voxel(x, y, v.unwrap_or(Voxel::default()).element_id)
-            }).sum(),
-            elements,
-        }
+        let mut grid = Grid {
+            elements: [None; VOXEL_COUNT],
+            hash: 0
+        };
+        grid.hash = grid.compute_hash_from_scratch();
+        grid
+    }
+
+    /// Hash of every cell, ignoring the incrementally updated `hash`. The two must always
+    /// agree
+    pub fn compute_hash_from_scratch(&self) -> u128 {
+        self.elements.iter().enumerate().map(|(i, v)| {
+            let (x, y) = Grid::get_coords_from_index(i);
+            Grid::hash_for_voxel(x, y, v.unwrap_or(Voxel::default()).element_id)
+        }).sum()
     }
 
     const fn get_index_from_coords(x: u64, y: u64) -> usize {
@@ -40,52 +127,332 @@ impl Grid {
         (x as u128 * P1) ^ (y as u128 * P2) ^ (element_id as u128 * P3)
     }
 
-    pub fn set(&mut self, x: u64, y: u64, voxel: Voxel) {
+    pub fn set(&mut self, GridCoord(x, y): GridCoord, voxel: Voxel) {
         let previous_element = self.elements[Grid::get_index_from_coords(x, y)].unwrap_or(Default::default());
         let previous_hash = Grid::hash_for_voxel(x, y, previous_element.element_id);
         let new_hash = Grid::hash_for_voxel(x, y, voxel.element_id);
 
         self.elements[Grid::get_index_from_coords(x, y)] = Some(voxel);
-        self.hash = self.hash - previous_hash + new_hash
+        self.hash = self.hash - previous_hash + new_hash;
+        debug_assert_eq!(self.hash, self.compute_hash_from_scratch(), "incremental grid hash drifted");
+    }
+
+    /// Empties the cell, returning the voxel that was there
+    pub fn remove(&mut self, GridCoord(x, y): GridCoord) -> Option<Voxel> {
+        let removed = self.elements[Grid::get_index_from_coords(x, y)].take();
+        if let Some(voxel) = removed {
+            let previous_hash = Grid::hash_for_voxel(x, y, voxel.element_id);
+            let new_hash = Grid::hash_for_voxel(x, y, Voxel::default().element_id);
+            self.hash = self.hash - previous_hash + new_hash;
+        }
+        debug_assert_eq!(self.hash, self.compute_hash_from_scratch(), "incremental grid hash drifted");
+        removed
     }
 
-    pub fn get_all_orientation_hashes(&self) -> [u128; 4] {
-        let mut hashes = [0; 4];
+    pub fn get(&self, GridCoord(x, y): GridCoord) -> Option<Voxel> {
+        self.elements[Grid::get_index_from_coords(x, y)]
+    }
+
+    /// Each row from top to bottom, with cells yielded left to right
+    pub fn rows(&self) -> impl Iterator<Item = impl Iterator<Item = Option<Voxel>> + '_> + '_ {
+        self.elements.chunks(VOXEL_COUNT_X).map(|row| row.iter().copied())
+    }
+
+    /// Each column from left to right, with cells yielded top to bottom
+    pub fn columns(&self) -> impl Iterator<Item = impl Iterator<Item = Option<Voxel>> + '_> + '_ {
+        (0..VOXEL_COUNT_X as u64).map(move |x| {
+            (0..VOXEL_COUNT_Y as u64).map(move |y| self.get(GridCoord(x, y)))
+        })
+    }
 
-        hashes[0] = self.hash;
-        hashes[1] = self.elements.iter()
+    /// Every cell in row-major order, including empty ones
+    pub fn iter_all(&self) -> impl Iterator<Item = (u64, u64, Option<Voxel>)> + '_ {
+        self.elements.iter()
             .enumerate()
-            .map(|(i, v)| { (Grid::get_coords_from_index(i), v.unwrap_or(Voxel::default()).element_id) })
-            .map(|((x, y), e)| {
-                (VOXEL_COUNT_X as u64 - x, y, e)
+            .map(|(i, voxel)| {
+                let (x, y) = Grid::get_coords_from_index(i);
+                (x, y, *voxel)
             })
-            .map(|(x, y, e)| Grid::hash_for_voxel(x, y, e))
-            .sum();
+    }
+
+    /// Every filled cell in row-major order
+    pub fn iter_filled(&self) -> impl Iterator<Item = (u64, u64, Voxel)> + '_ {
+        self.iter_all().filter_map(|(x, y, voxel)| voxel.map(|voxel| (x, y, voxel)))
+    }
+
+    /// Faces of the voxel at `(x, y)` that border an empty cell or the edge of the grid.
+    /// Empty cells have no faces
+    pub fn exposed_faces(&self, x: u64, y: u64) -> FaceMask {
+        if self.get(GridCoord(x, y)).is_none() {
+            return FaceMask::NONE
+        }
+
+        let is_open = |x: Option<u64>, y: Option<u64>| match (x, y) {
+            (Some(x), Some(y)) if x < VOXEL_COUNT_X as u64 && y < VOXEL_COUNT_Y as u64 => self.get(GridCoord(x, y)).is_none(),
+            _ => true
+        };
 
-        hashes[2] = self.elements.iter()
+        let mut faces = FaceMask::NONE;
+        for (neighbour_x, neighbour_y, face) in [
+            (Some(x), y.checked_sub(1), FaceMask::UP),
+            (Some(x), y.checked_add(1), FaceMask::DOWN),
+            (x.checked_sub(1), Some(y), FaceMask::LEFT),
+            (x.checked_add(1), Some(y), FaceMask::RIGHT)
+        ] {
+            if is_open(neighbour_x, neighbour_y) {
+                faces |= face;
+            }
+        }
+        faces
+    }
+
+    /// Exposed faces of every filled voxel with at least one, in row-major order
+    pub fn all_exposed_faces(&self) -> Vec<(u64, u64, FaceMask)> {
+        self.iter_filled()
+            .map(|(x, y, _)| (x, y, self.exposed_faces(x, y)))
+            .filter(|(_, _, faces)| !faces.is_empty())
+            .collect()
+    }
+
+    /// Two triangles per filled voxel, with cell `(x, y)` covering `cell_size` units from
+    /// `(x, y) * cell_size` and coloured by the voxel
+    pub fn to_quad_vertices(&self, cell_size: f32) -> Vec<QuadVertex> {
+        let mut vertices = Vec::with_capacity(self.iter_filled().count() * 6);
+        for (x, y, voxel) in self.iter_filled() {
+            let colour = voxel.colour.map(|channel| channel as f32 / 255.0);
+            let (left, top) = (x as f32 * cell_size, y as f32 * cell_size);
+            let (right, bottom) = (left + cell_size, top + cell_size);
+
+            vertices.extend([
+                [left, top], [right, top], [right, bottom],
+                [left, top], [right, bottom], [left, bottom]
+            ].map(|position| QuadVertex { position, colour }));
+        }
+        vertices
+    }
+
+    /// Number of filled cells holding each element
+    pub fn count_by_element(&self) -> HashMap<u16, usize> {
+        let mut counts = HashMap::new();
+        for voxel in self.elements.iter().flatten() {
+            *counts.entry(voxel.element_id).or_insert(0) += 1;
+        }
+        counts
+    }
+
+    pub fn count_of(&self, element_id: u16) -> usize {
+        self.elements.iter()
+            .flatten()
+            .filter(|voxel| voxel.element_id == element_id)
+            .count()
+    }
+
+    /// Inclusive `(min_x, min_y, max_x, max_y)` of all filled cells, or `None` when empty
+    pub fn occupied_bounds(&self) -> Option<(u64, u64, u64, u64)> {
+        self.elements.iter()
             .enumerate()
-            .map(|(i, v)| { (Grid::get_coords_from_index(i), v.unwrap_or(Voxel::default()).element_id) })
-            .map(|((x, y), e)| {
-                (x, VOXEL_COUNT_Y as u64 - y, e)
+            .filter(|(_, v)| v.is_some())
+            .map(|(i, _)| Grid::get_coords_from_index(i))
+            .fold(None, |bounds, (x, y)| match bounds {
+                None => Some((x, y, x, y)),
+                Some((min_x, min_y, max_x, max_y)) => Some((min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y)))
             })
-            .map(|(x, y, e)| Grid::hash_for_voxel(x, y, e))
-            .sum();
+    }
+
+    /// Filled cells shifted so the occupied region starts at (0, 0), in row-major order
+    fn normalized_pattern(&self) -> Vec<(u64, u64, u16)> {
+        let Some((min_x, min_y, _, _)) = self.occupied_bounds() else {
+            return Vec::new()
+        };
+
+        self.elements.iter()
+            .enumerate()
+            .filter_map(|(i, v)| v.map(|v| (Grid::get_coords_from_index(i), v.element_id)))
+            .map(|((x, y), e)| (x - min_x, y - min_y, e))
+            .collect()
+    }
+
+    pub fn same_pattern_ignoring_translation(&self, other: &Grid) -> bool {
+        self.normalized_pattern() == other.normalized_pattern()
+    }
 
-        hashes[3] = self.elements.iter()
+    /// Every cell that differs between the grids as `(x, y, old, new)`, where `self` is
+    /// the old grid. Every cell is compared, since the grid hash only covers element ids
+    pub fn diff(&self, other: &Grid) -> Vec<(u64, u64, Option<Voxel>, Option<Voxel>)> {
+        self.elements.iter()
+            .zip(other.elements.iter())
             .enumerate()
-            .map(|(i, v)| { (Grid::get_coords_from_index(i), v.unwrap_or(Voxel::default()).element_id) })
+            .filter(|(_, (old, new))| old != new)
+            .map(|(i, (old, new))| {
+                let (x, y) = Grid::get_coords_from_index(i);
+                (x, y, *old, *new)
+            })
+            .collect()
+    }
+
+    /// Stamps the filled cells of `other` onto this grid, moved by `offset`. Cells that
+    /// land outside the grid are skipped. Returns whether the merge was applied, which is
+    /// only ever false for `MergePolicy::OnlyEmpty`
+    pub fn merge_from(&mut self, other: &Grid, offset: (i64, i64), policy: MergePolicy) -> bool {
+        let placed: Vec<(u64, u64, Voxel)> = other.elements.iter()
+            .enumerate()
+            .filter_map(|(i, v)| v.map(|v| (Grid::get_coords_from_index(i), v)))
+            .filter_map(|((x, y), v)| {
+                let x = u64::try_from(x as i64 + offset.0).ok()?;
+                let y = u64::try_from(y as i64 + offset.1).ok()?;
+                (x < VOXEL_COUNT_X as u64 && y < VOXEL_COUNT_Y as u64).then_some((x, y, v))
+            })
+            .collect();
+
+        if policy == MergePolicy::OnlyEmpty && placed.iter().any(|(x, y, _)| self.get(GridCoord(*x, *y)).is_some()) {
+            return false
+        }
+
+        for (x, y, voxel) in placed {
+            if policy == MergePolicy::KeepExisting && self.get(GridCoord(x, y)).is_some() {
+                continue;
+            }
+            self.set(GridCoord(x, y), voxel);
+        }
+        true
+    }
+
+    /// A new grid with every filled cell moved to `transform(x, y)`
+    fn transformed(&self, transform: impl Fn(u64, u64) -> (u64, u64)) -> Grid {
+        let mut grid = Grid::new();
+        for (i, voxel) in self.elements.iter().enumerate() {
+            if let Some(voxel) = voxel {
+                let (x, y) = Grid::get_coords_from_index(i);
+                let (x, y) = transform(x, y);
+                grid.set(GridCoord(x, y), *voxel);
+            }
+        }
+        grid
+    }
+
+    pub fn rotated_90_cw(&self) -> Grid {
+        self.transformed(|x, y| (VOXEL_COUNT_Y as u64 - 1 - y, x))
+    }
+
+    pub fn rotated_180(&self) -> Grid {
+        self.transformed(|x, y| (VOXEL_COUNT_X as u64 - 1 - x, VOXEL_COUNT_Y as u64 - 1 - y))
+    }
+
+    pub fn rotated_270_cw(&self) -> Grid {
+        self.transformed(|x, y| (y, VOXEL_COUNT_X as u64 - 1 - x))
+    }
+
+    /// Mirrored across the vertical axis
+    pub fn flipped_x(&self) -> Grid {
+        self.transformed(|x, y| (VOXEL_COUNT_X as u64 - 1 - x, y))
+    }
+
+    /// Mirrored across the horizontal axis
+    pub fn flipped_y(&self) -> Grid {
+        self.transformed(|x, y| (x, VOXEL_COUNT_Y as u64 - 1 - y))
+    }
+
+    /// Hash the grid would have after `transform` moved each of its cells
+    fn transformed_hash(&self, transform: CellTransform) -> u128 {
+        self.elements.iter()
+            .enumerate()
+            .map(|(i, v)| (Grid::get_coords_from_index(i), v.unwrap_or(Voxel::default()).element_id))
             .map(|((x, y), e)| {
-                (VOXEL_COUNT_X as u64 - x, VOXEL_COUNT_Y as u64 - y, e)
+                let (x, y) = transform(x, y);
+                Grid::hash_for_voxel(x, y, e)
             })
-            .map(|(x, y, e)| Grid::hash_for_voxel(x, y, e))
-            .sum();
+            .sum()
+    }
+
+    /// Hashes of the grid in each of its 8 orientations: the 4 rotations, and the 4
+    /// rotations of its mirror image
+    pub fn get_all_orientation_hashes(&self) -> [u128; 8] {
+        const LAST_X: u64 = VOXEL_COUNT_X as u64 - 1;
+        const LAST_Y: u64 = VOXEL_COUNT_Y as u64 - 1;
+        const ORIENTATIONS: [CellTransform; 8] = [
+            |x, y| (x, y),
+            |x, y| (LAST_Y - y, x),
+            |x, y| (LAST_X - x, LAST_Y - y),
+            |x, y| (y, LAST_X - x),
+            |x, y| (LAST_X - x, y),
+            |x, y| (x, LAST_Y - y),
+            |x, y| (y, x),
+            |x, y| (LAST_Y - y, LAST_X - x)
+        ];
 
-        hashes
+        ORIENTATIONS.map(|transform| self.transformed_hash(transform))
     }
 
     pub fn is_orientation_of(&self, other: &Grid) -> bool {
         other.get_all_orientation_hashes().iter().any(|h| *h == self.hash)
     }
+
+    /// Run-length encodes the cells in row-major order. Each run is a length byte and a
+    /// tag byte; filled runs follow the tag with the little-endian element id and the
+    /// colour
+    pub fn to_rle(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut cells = self.elements.iter().peekable();
+
+        while let Some(cell) = cells.next() {
+            let mut length: u8 = 1;
+            while length < u8::MAX && cells.peek() == Some(&cell) {
+                cells.next();
+                length += 1;
+            }
+
+            bytes.push(length);
+            match cell {
+                None => bytes.push(RLE_EMPTY),
+                Some(voxel) => {
+                    bytes.push(RLE_FILLED);
+                    bytes.extend_from_slice(&voxel.element_id.to_le_bytes());
+                    bytes.extend_from_slice(&voxel.colour);
+                }
+            }
+        }
+        bytes
+    }
+
+    pub fn from_rle(bytes: &[u8]) -> Result<Grid, DecodeError> {
+        let mut grid = Grid::new();
+        let mut index = 0;
+        let mut bytes = bytes.iter().copied();
+
+        while let Some(length) = bytes.next() {
+            if length == 0 {
+                return Err(DecodeError::EmptyRun)
+            }
+
+            let mut next = || bytes.next().ok_or(DecodeError::UnexpectedEnd);
+            let cell = match next()? {
+                RLE_EMPTY => None,
+                RLE_FILLED => Some(Voxel {
+                    element_id: u16::from_le_bytes([next()?, next()?]),
+                    colour: [next()?, next()?, next()?, next()?]
+                }),
+                tag => return Err(DecodeError::InvalidTag(tag))
+            };
+
+            let run_end = index + length as usize;
+            if run_end > VOXEL_COUNT {
+                return Err(DecodeError::CellCountMismatch { expected: VOXEL_COUNT, found: run_end })
+            }
+
+            if let Some(voxel) = cell {
+                for i in index..run_end {
+                    let (x, y) = Grid::get_coords_from_index(i);
+                    grid.set(GridCoord(x, y), voxel);
+                }
+            }
+            index = run_end;
+        }
+
+        if index != VOXEL_COUNT {
+            return Err(DecodeError::CellCountMismatch { expected: VOXEL_COUNT, found: index })
+        }
+        Ok(grid)
+    }
 }
 
 pub struct SpatialGrid {
@@ -93,6 +460,98 @@ pub struct SpatialGrid {
     /// Origin of grid: based in top left corner
     pub origin: Vector2<f64>,
     pub voxel_side_length: f64,
+    /// Rays leaving one edge re-enter from the opposite edge instead of stopping, as on a
+    /// toroidal map. Rays without a `max_distance` stop once they have crossed the grid's
+    /// full width or height
+    pub wrap: bool,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct VoxelHit {
+    pub voxel: Voxel,
+    pub x: u64,
+    pub y: u64,
+    /// Ray parameter at which the ray enters the voxel
+    pub t: f64
+}
+
+/// A filled cell's coordinates and contents
+pub type CellVoxel = (u64, u64, Voxel);
+
+/// The first solid voxel struck by a ray
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct RaycastHit {
+    pub voxel: Voxel,
+    pub x: u64,
+    pub y: u64,
+    /// Ray parameter at which the ray enters the voxel
+    pub t: f64,
+    /// World-space point where the ray enters the voxel
+    pub position: Vector2<f64>
+}
+
+/// Incremental DDA walk over grid cells, yielding each cell with the ray parameters at
+/// which the ray enters and leaves it
+struct CellTraversal {
+    cell: Vector2<i64>,
+    step: Vector2<i64>,
+    t_delta: Vector2<f64>,
+    /// Ray parameter at which the next x or y cell boundary is crossed
+    t_max: Vector2<f64>,
+    t_cell: f64,
+    max_distance: f64,
+    /// Step off one edge onto the opposite edge rather than finishing
+    wrap: bool,
+    finished: bool
+}
+
+impl Iterator for CellTraversal {
+    type Item = (i64, i64, f64, f64);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished || self.t_cell > self.max_distance {
+            return None
+        }
+
+        let current = (self.cell.x, self.cell.y, self.t_cell, self.t_max.x.min(self.t_max.y));
+        if self.t_max.x < self.t_max.y {
+            self.t_cell = self.t_max.x;
+            self.t_max.x += self.t_delta.x;
+            self.cell.x += self.step.x;
+            if self.wrap {
+                self.cell.x = self.cell.x.rem_euclid(VOXEL_COUNT_X as i64);
+            } else {
+                self.finished = self.cell.x < 0 || self.cell.x as usize >= VOXEL_COUNT_X;
+            }
+        } else {
+            self.t_cell = self.t_max.y;
+            self.t_max.y += self.t_delta.y;
+            self.cell.y += self.step.y;
+            if self.wrap {
+                self.cell.y = self.cell.y.rem_euclid(VOXEL_COUNT_Y as i64);
+            } else {
+                self.finished = self.cell.y < 0 || self.cell.y as usize >= VOXEL_COUNT_Y;
+            }
+        }
+        Some(current)
+    }
+}
+
+/// Cell containing `position` in an unbounded grid of `cell_side_length` cells starting at
+/// `origin`
+pub fn cell_at(origin: Vector2<f64>, cell_side_length: f64, position: Vector2<f64>) -> (i64, i64) {
+    let local = (position - origin) / cell_side_length;
+    (local.x.floor() as i64, local.y.floor() as i64)
+}
+
+/// Every candidate which is the target rotated or mirrored, in order
+pub fn find_orientation_matches<'a>(target: &Grid, candidates: impl Iterator<Item = &'a Grid>) -> Vec<&'a Grid> {
+    let orientations: HashSet<u128> = target.get_all_orientation_hashes().into_iter().collect();
+    candidates.filter(|candidate| orientations.contains(&candidate.hash)).collect()
+}
+
+pub fn sort_by_distance(hits: &mut [VoxelHit]) {
+    hits.sort_by(|a, b| a.t.total_cmp(&b.t));
 }
 
 pub enum IntersectType {
@@ -105,10 +564,22 @@ impl SpatialGrid {
         SpatialGrid {
             grid: Grid::new(),
             origin: Vector2::new(0.0, 0.0),
-            voxel_side_length
+            voxel_side_length,
+            wrap: false
         }
     }
 
+    /// Rescales the grid in world space. Panics if `len` is not a positive, finite length
+    pub fn set_voxel_side_length(&mut self, len: f64) {
+        assert!(len > 0.0 && len.is_finite(), "voxel side length must be positive, got {}", len);
+        self.voxel_side_length = len;
+    }
+
+    /// Moves the top left corner of the grid in world space
+    pub fn set_origin(&mut self, origin: Vector2<f64>) {
+        self.origin = origin;
+    }
+
     pub fn bounds(&self) -> AABB {
         AABB::from_position_and_size(self.origin, Vector2 {
             x: VOXEL_COUNT_X as f64 * self.voxel_side_length,
@@ -116,107 +587,407 @@ impl SpatialGrid {
         })
     }
 
-    pub fn walk_grid_across_ray(&self, ray: Ray, on_voxel_hit: &mut dyn FnMut(Voxel) -> bool) {
-        let ray = Ray {
-            origin: {
-                let grid_aabb = self.bounds();
-                let intersect_pos = if grid_aabb.does_contain(&ray.origin) {
-                    ray.origin
-                } else if let Some(intersect) = grid_aabb.does_intersect(&ray) {
-                    intersect.position + ray.direction * 0.001
-                } else {
-                    return
+    /// World-space box covered by the cell at `coord`
+    pub fn cell_aabb(&self, GridCoord(x, y): GridCoord) -> AABB {
+        AABB::from_position_and_size(
+            self.origin + Vector2::new(x as f64, y as f64) * self.voxel_side_length,
+            Vector2::new(self.voxel_side_length, self.voxel_side_length)
+        )
+    }
+
+    /// Cell containing `position`, or `None` if it lies outside the grid
+    pub fn to_grid(&self, WorldPos(position): WorldPos) -> Option<GridCoord> {
+        let (x, y) = cell_at(self.origin, self.voxel_side_length, position);
+        if x < 0 || y < 0 || x >= VOXEL_COUNT_X as i64 || y >= VOXEL_COUNT_Y as i64 {
+            return None
+        }
+        Some(GridCoord(x as u64, y as u64))
+    }
+
+    /// Centre of the cell at `coord`
+    pub fn to_world(&self, coord: GridCoord) -> WorldPos {
+        WorldPos(self.cell_aabb(coord).mid())
+    }
+
+    /// Inclusive range of cells overlapping the world-space box, or `None` if the box
+    /// lies outside the grid
+    fn cell_range(&self, aabb: &AABB) -> Option<(u64, u64, u64, u64)> {
+        let local_min = (aabb.min() - self.origin) / self.voxel_side_length;
+        let local_max = (aabb.max() - self.origin) / self.voxel_side_length;
+        if local_max.x < 0.0 || local_max.y < 0.0 ||
+            local_min.x >= VOXEL_COUNT_X as f64 || local_min.y >= VOXEL_COUNT_Y as f64 {
+            return None
+        }
+
+        Some((
+            local_min.x.floor().max(0.0) as u64,
+            local_min.y.floor().max(0.0) as u64,
+            (local_max.x.floor() as u64).min(VOXEL_COUNT_X as u64 - 1),
+            (local_max.y.floor() as u64).min(VOXEL_COUNT_Y as u64 - 1)
+        ))
+    }
+
+    /// Visits every filled voxel within `radius` of the segment from `start` to `end`,
+    /// which is the area swept by a circle of that radius. Returning false from the
+    /// callback stops the walk
+    pub fn walk_capsule(
+        &self,
+        start: Vector2<f64>,
+        end: Vector2<f64>,
+        radius: f64,
+        on_hit: &mut dyn FnMut(u64, u64, Voxel) -> bool
+    ) {
+        let swept_bounds = AABB::from_points([
+            start - Vector2::new(radius, radius),
+            end + Vector2::new(radius, radius),
+            start + Vector2::new(radius, radius),
+            end - Vector2::new(radius, radius)
+        ]).unwrap();
+
+        let Some((min_x, min_y, max_x, max_y)) = self.cell_range(&swept_bounds) else {
+            return
+        };
+
+        for y in min_y..=max_y {
+            for x in min_x..=max_x {
+                let Some(voxel) = self.grid.get(GridCoord(x, y)) else {
+                    continue
                 };
 
-                intersect_pos - self.origin
-            },
-            direction: ray.direction,
-            max_distance: ray.max_distance
+                if self.cell_aabb(GridCoord(x, y)).distance_to_segment(start, end) <= radius && !on_hit(x, y, voxel) {
+                    return
+                }
+            }
+        }
+    }
+
+    /// World-space point where the ray enters the grid, which is its origin if it starts
+    /// inside, or `None` if it misses the grid entirely
+    pub fn ray_enters(&self, ray: &Ray) -> Option<Vector2<f64>> {
+        let grid_aabb = self.bounds();
+        if grid_aabb.does_contain(&ray.origin) {
+            return Some(ray.origin)
+        }
+        grid_aabb.does_intersect(ray).map(|intersect| intersect.position)
+    }
+
+    /// DDA state for the cells the ray passes through inside the grid, or `None` if the
+    /// ray misses the grid or its direction is zero or not finite. If the grid wraps and
+    /// the ray has no `max_distance`, the walk stops once the ray has crossed the grid's
+    /// full width or height from its entry
+    fn cell_traversal(&self, ray: &Ray) -> Option<CellTraversal> {
+        if ray.direction.magnitude2() == 0.0 || !ray.direction.x.is_finite() || !ray.direction.y.is_finite() {
+            return None
+        }
+
+        let entry = self.ray_enters(ray)?;
+        let t_entry = (entry - ray.origin).dot(ray.direction) / ray.direction.magnitude2();
+
+        let local_entry = ray.origin + ray.direction * t_entry - self.origin;
+
+        let cell = Vector2 {
+            x: ((local_entry.x / self.voxel_side_length).floor() as i64).clamp(0, VOXEL_COUNT_X as i64 - 1),
+            y: ((local_entry.y / self.voxel_side_length).floor() as i64).clamp(0, VOXEL_COUNT_Y as i64 - 1)
         };
 
         let step = Vector2 {
-            x: (ray.direction.x >= 0.0) as i64 * 2 - 1,
-            y: (ray.direction.y >= 0.0) as i64 * 2 - 1
+            x: ray.direction.x.signum() as i64,
+            y: ray.direction.y.signum() as i64
         };
 
-        let t_delta = self.voxel_side_length * {
-            let magnitude = ray.direction.magnitude();
-            Vector2 { 
-                x: magnitude / ray.direction.x,
-                y: magnitude / ray.direction.y
-            }
+        let t_delta = Vector2 {
+            x: self.voxel_side_length / ray.direction.x.abs(),
+            y: self.voxel_side_length / ray.direction.y.abs()
         };
-        let mut t_max = {
-            let min = self.voxel_side_length * Vector2 {
-                x: (ray.origin.x / self.voxel_side_length).floor(),
-                y: (ray.origin.y / self.voxel_side_length).floor()
-            };
-            let max = min + Vector2::new(self.voxel_side_length, self.voxel_side_length);
 
-            let scalar = {
-                let x = if ray.direction.x >= 0.0 {
-                    max.x - ray.origin.x
-                } else {
-                    ray.origin.x - min.x
-                };
+        // Ray parameter at which the next x or y cell boundary is crossed
+        let boundary_t = |position: f64, direction: f64, cell: i64| -> f64 {
+            if direction > 0.0 {
+                t_entry + ((cell + 1) as f64 * self.voxel_side_length - position) / direction
+            } else if direction < 0.0 {
+                t_entry + (cell as f64 * self.voxel_side_length - position) / direction
+            } else {
+                f64::INFINITY
+            }
+        };
+        let t_max = Vector2 {
+            x: boundary_t(local_entry.x, ray.direction.x, cell.x),
+            y: boundary_t(local_entry.y, ray.direction.y, cell.y)
+        };
 
-                let y = if ray.direction.y >= 0.0 {
-                    max.y - ray.origin.y
-                } else {
-                    ray.origin.y - min.y
-                };
+        let max_distance = match ray.max_distance {
+            Some(max_distance) => max_distance,
+            None if self.wrap => {
+                let size = self.bounds().size;
+                t_entry + (size.x / ray.direction.x.abs()).min(size.y / ray.direction.y.abs())
+            },
+            None => f64::MAX
+        };
 
-                Vector2{ x, y }
-            };
+        Some(CellTraversal {
+            cell,
+            step,
+            t_delta,
+            t_max,
+            t_cell: t_entry,
+            max_distance,
+            wrap: self.wrap,
+            finished: false
+        })
+    }
 
-            Vector2 {
-                x: scalar.x * t_delta.x,
-                y: scalar.y * t_delta.y
+    /// Walks every cell the ray passes through inside the grid, in order, along with the
+    /// ray parameter at which the ray enters the cell. Returning false from the callback
+    /// stops the walk
+    fn walk_cells(&self, ray: &Ray, on_cell: &mut dyn FnMut(i64, i64, f64) -> bool) {
+        for (x, y, t, _) in self.cell_traversal(ray).into_iter().flatten() {
+            if !on_cell(x, y, t) {
+                break;
             }
-        };
+        }
+    }
 
-        let mut grid_pos = Vector2 {
-            x: ray.origin.x.floor() as i64,
-            y: ray.origin.y.floor() as i64,
+    /// Every cell the ray passes through inside the grid, nearest first. Cells are
+    /// visited lazily, so the walk stops as soon as the caller does
+    pub fn ray_cells(&self, ray: &Ray) -> impl Iterator<Item = (i64, i64)> {
+        self.cell_traversal(ray).into_iter()
+            .flatten()
+            .map(|(x, y, _, _)| (x, y))
+    }
+
+    /// Every cell the traversal visits for the ray, filled or not, for drawing the path
+    /// it takes. This is `ray_cells` collected into a `Vec`; use `ray_cells` directly
+    /// where the walk can stop early
+    pub fn debug_traversal(&self, ray: &Ray) -> Vec<(i64, i64)> {
+        self.ray_cells(ray).collect()
+    }
+
+    /// Every voxel for which `is_solid` holds that the segment from `start` to `end`
+    /// passes through, nearest first, with the fractions along the segment at which it
+    /// enters and leaves the voxel. A zero-length segment passes through nothing
+    pub fn segment_hits(
+        &self,
+        start: Vector2<f64>,
+        end: Vector2<f64>,
+        is_solid: impl Fn(Voxel) -> bool
+    ) -> Vec<(GridCoord, Voxel, f64, f64)> {
+        if start == end {
+            return Vec::new()
+        }
+
+        let ray = Ray {
+            origin: start,
+            direction: end - start,
+            max_distance: Some(1.0)
         };
 
-        loop {
-            let voxel = self.grid.elements[Grid::get_index_from_coords(grid_pos.x as u64, grid_pos.y as u64)];
-            if let Some(v) = voxel {
-                on_voxel_hit(v);
+        self.cell_traversal(&ray).into_iter()
+            .flatten()
+            .filter_map(|(x, y, t_entry, t_exit)| {
+                let coord = GridCoord(x as u64, y as u64);
+                self.grid.get(coord)
+                    .filter(|voxel| is_solid(*voxel))
+                    .map(|voxel| (coord, voxel, t_entry, t_exit.min(1.0)))
+            })
+            .collect()
+    }
+
+    /// Calls `on_hit` for every filled voxel along the ray, nearest first. Returning false
+    /// from the callback stops the walk
+    pub fn walk_hits(&self, ray: &Ray, on_hit: &mut dyn FnMut(VoxelHit) -> bool) {
+        self.walk_cells(ray, &mut |x, y, t| {
+            match self.grid.get(GridCoord(x as u64, y as u64)) {
+                Some(voxel) => on_hit(VoxelHit { voxel, x: x as u64, y: y as u64, t }),
+                None => true
             }
+        });
+    }
 
-            if t_max.x < t_max.y {
-                t_max.x += t_delta.x;
-                grid_pos.x += step.x;
-                if grid_pos.x < 0 || grid_pos.x as usize >= VOXEL_COUNT_X {
-                    break;
-                }
-            } else {
-                t_max.y += t_delta.y;
-                grid_pos.y += step.y;
-                if grid_pos.y < 0 || grid_pos.y as usize >= VOXEL_COUNT_Y {
-                    break;
-                }
+    /// The first voxel along the ray for which `is_solid` holds
+    pub fn raycast(&self, ray: &Ray, is_solid: impl Fn(Voxel) -> bool) -> Option<RaycastHit> {
+        let mut first_hit = None;
+        self.walk_hits(ray, &mut |hit| {
+            if !is_solid(hit.voxel) {
+                return true
             }
+
+            first_hit = Some(RaycastHit {
+                voxel: hit.voxel,
+                x: hit.x,
+                y: hit.y,
+                t: hit.t,
+                position: ray.origin + ray.direction * hit.t
+            });
+            false
+        });
+        first_hit
+    }
+
+    /// World-space distance from the ray origin to where it enters the first voxel for
+    /// which `is_solid` holds
+    pub fn distance_to_first_solid(&self, ray: &Ray, is_solid: impl Fn(Voxel) -> bool) -> Option<f64> {
+        let mut distance = None;
+        self.walk_hits(ray, &mut |hit| {
+            if !is_solid(hit.voxel) {
+                return true
+            }
+
+            distance = Some(hit.t * ray.direction.magnitude());
+            false
+        });
+        distance
+    }
+
+    /// The first voxel along the ray whose element is not in `ignore`, letting the ray
+    /// pass through see-through elements
+    pub fn raycast_ignoring(&self, ray: &Ray, ignore: &[u16]) -> Option<RaycastHit> {
+        self.raycast(ray, |voxel| !ignore.contains(&voxel.element_id))
+    }
+
+    /// Every filled voxel whose cell overlaps the world-space box
+    pub fn voxels_in_aabb(&self, aabb: &AABB) -> Vec<CellVoxel> {
+        let Some((min_x, min_y, max_x, max_y)) = self.cell_range(aabb) else {
+            return Vec::new()
+        };
+
+        (min_y..=max_y)
+            .flat_map(|y| (min_x..=max_x).map(move |x| (x, y)))
+            .filter_map(|(x, y)| self.grid.get(GridCoord(x, y)).map(|voxel| (x, y, voxel)))
+            .collect()
+    }
+
+    /// Fraction of the cells overlapping the world-space box which are filled. Cells off
+    /// the grid are not counted, and a box with no area or no overlap has a density of 0
+    pub fn density_in_aabb(&self, aabb: &AABB) -> f64 {
+        if aabb.size.x <= 0.0 || aabb.size.y <= 0.0 {
+            return 0.0
         }
+        let Some((min_x, min_y, max_x, max_y)) = self.cell_range(aabb) else {
+            return 0.0
+        };
+
+        let cell_count = (max_x - min_x + 1) * (max_y - min_y + 1);
+        self.voxels_in_aabb(aabb).len() as f64 / cell_count as f64
+    }
+
+    /// The first solid hit along the ray, along with every filled voxel within `radius`
+    /// world units of the hit position
+    pub fn raycast_with_splash(
+        &self,
+        ray: &Ray,
+        radius: f64,
+        is_solid: impl Fn(Voxel) -> bool
+    ) -> Option<(RaycastHit, Vec<CellVoxel>)> {
+        let hit = self.raycast(ray, is_solid)?;
+        let splash_bounds = AABB::from_position_and_size(
+            hit.position - Vector2::new(radius, radius),
+            Vector2::new(2.0 * radius, 2.0 * radius)
+        );
+
+        let splashed = self.voxels_in_aabb(&splash_bounds).into_iter()
+            .filter(|(x, y, _)| {
+                let closest = self.cell_aabb(GridCoord(*x, *y)).closest_point(hit.position);
+                (closest - hit.position).magnitude() <= radius
+            })
+            .collect();
+
+        Some((hit, splashed))
+    }
+
+    pub fn walk_grid_across_ray(&self, ray: Ray, on_voxel_hit: &mut dyn FnMut(Voxel) -> bool) {
+        self.walk_hits(&ray, &mut |hit| on_voxel_hit(hit.voxel));
     }
 
     pub fn get_intersections(&self, ray: Ray, intersect: IntersectType) -> Vec<Voxel> {
+        self.get_intersections_sorted(ray, intersect).iter()
+            .map(|hit| hit.voxel)
+            .collect()
+    }
+
+    pub fn get_intersections_sorted(&self, ray: Ray, intersect: IntersectType) -> Vec<VoxelHit> {
         let mut voxels_hit = Vec::new();
-        if let IntersectType::First = intersect {
-            self.walk_grid_across_ray(ray, &mut |v| {
-                voxels_hit.push(v);
-                false
-            });
-        } else {
-            self.walk_grid_across_ray(ray, &mut |v| {
-                voxels_hit.push(v);
-                true
-            });
+        let keep_walking = matches!(intersect, IntersectType::All);
+        self.walk_hits(&ray, &mut |hit| {
+            voxels_hit.push(hit);
+            keep_walking
+        });
+        sort_by_distance(&mut voxels_hit);
+        voxels_hit
+    }
+
+    /// The `n` hits nearest to the ray origin, sorted by distance
+    pub fn closest_n(&self, ray: Ray, n: usize) -> Vec<VoxelHit> {
+        let mut voxels_hit = Vec::new();
+        if n == 0 {
+            return voxels_hit
         }
+
+        self.walk_hits(&ray, &mut |hit| {
+            voxels_hit.push(hit);
+            voxels_hit.len() < n
+        });
+        sort_by_distance(&mut voxels_hit);
         voxels_hit
     }
+
+    /// Shortest 4-connected path of cells from `start` to `goal`, both included, moving
+    /// only through cells for which `is_walkable` holds. `None` if the goal is unreachable
+    pub fn find_path(
+        &self,
+        start: (u64, u64),
+        goal: (u64, u64),
+        is_walkable: impl Fn(Option<Voxel>) -> bool
+    ) -> Option<Vec<(u64, u64)>> {
+        let in_bounds = |(x, y): (u64, u64)| x < VOXEL_COUNT_X as u64 && y < VOXEL_COUNT_Y as u64;
+        if !in_bounds(start) || !in_bounds(goal) {
+            return None
+        }
+
+        let heuristic = |(x, y): (u64, u64)| x.abs_diff(goal.0) + y.abs_diff(goal.1);
+        let mut came_from: HashMap<(u64, u64), (u64, u64)> = HashMap::new();
+        let mut best_cost: HashMap<(u64, u64), u64> = HashMap::from([(start, 0)]);
+        let mut open = BinaryHeap::from([Reverse((heuristic(start), 0, start))]);
+
+        while let Some(Reverse((_, cost, cell))) = open.pop() {
+            if cell == goal {
+                let mut path = vec![goal];
+                while let Some(previous) = came_from.get(path.last().unwrap()) {
+                    path.push(*previous);
+                }
+                path.reverse();
+                return Some(path)
+            }
+
+            if cost > best_cost[&cell] {
+                continue;
+            }
+
+            let (x, y) = cell;
+            let neighbours = [
+                x.checked_sub(1).map(|x| (x, y)),
+                Some((x + 1, y)),
+                y.checked_sub(1).map(|y| (x, y)),
+                Some((x, y + 1))
+            ];
+
+            for neighbour in neighbours.into_iter().flatten() {
+                if !in_bounds(neighbour) || !is_walkable(self.grid.get(GridCoord(neighbour.0, neighbour.1))) {
+                    continue;
+                }
+
+                let neighbour_cost = cost + 1;
+                if best_cost.get(&neighbour).is_some_and(|best| *best <= neighbour_cost) {
+                    continue;
+                }
+
+                best_cost.insert(neighbour, neighbour_cost);
+                came_from.insert(neighbour, cell);
+                open.push(Reverse((neighbour_cost + heuristic(neighbour), neighbour_cost, neighbour)));
+            }
+        }
+
+        None
+    }
 }
 
 impl PartialEq for Grid {
@@ -232,3 +1003,825 @@ impl Hash for Grid {
         self.hash.hash(state);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Lcg;
+    use crate::voxel::VoxelBuilder;
+
+    fn prefab() -> Grid {
+        let mut prefab = Grid::new();
+        prefab.set(GridCoord(0, 0), Voxel::new(7));
+        prefab.set(GridCoord(1, 0), Voxel::new(7));
+        prefab.set(GridCoord(1, 1), Voxel::new(8));
+        prefab
+    }
+
+    fn rebuilt(grid: &Grid) -> Grid {
+        let mut copy = Grid::new();
+        for (x, y, _, voxel) in Grid::new().diff(grid) {
+            copy.set(GridCoord(x, y), voxel.unwrap());
+        }
+        copy
+    }
+
+    #[test]
+    fn test_merge_overwrite() {
+        let mut grid = Grid::new();
+        grid.set(GridCoord(4, 3), Voxel::new(1));
+        grid.set(GridCoord(0, 0), Voxel::new(2));
+
+        assert!(grid.merge_from(&prefab(), (3, 3), MergePolicy::Overwrite));
+        assert_eq!(grid.get(GridCoord(3, 3)), Some(Voxel::new(7)));
+        assert_eq!(grid.get(GridCoord(4, 3)), Some(Voxel::new(7)));
+        assert_eq!(grid.get(GridCoord(4, 4)), Some(Voxel::new(8)));
+        assert_eq!(grid.get(GridCoord(0, 0)), Some(Voxel::new(2)));
+        assert_eq!(grid.count_by_element().values().sum::<usize>(), 4);
+        assert_eq!(grid.hash, rebuilt(&grid).hash);
+    }
+
+    #[test]
+    fn test_merge_keep_existing() {
+        let mut grid = Grid::new();
+        grid.set(GridCoord(4, 3), Voxel::new(1));
+
+        assert!(grid.merge_from(&prefab(), (3, 3), MergePolicy::KeepExisting));
+        assert_eq!(grid.get(GridCoord(3, 3)), Some(Voxel::new(7)));
+        assert_eq!(grid.get(GridCoord(4, 3)), Some(Voxel::new(1)));
+        assert_eq!(grid.get(GridCoord(4, 4)), Some(Voxel::new(8)));
+        assert_eq!(grid.hash, rebuilt(&grid).hash);
+    }
+
+    #[test]
+    fn test_merge_only_empty() {
+        let mut grid = Grid::new();
+        grid.set(GridCoord(4, 3), Voxel::new(1));
+        let before = grid.hash;
+
+        assert!(!grid.merge_from(&prefab(), (3, 3), MergePolicy::OnlyEmpty));
+        assert_eq!(grid.get(GridCoord(3, 3)), None);
+        assert_eq!(grid.hash, before);
+
+        assert!(grid.merge_from(&prefab(), (5, 5), MergePolicy::OnlyEmpty));
+        assert_eq!(grid.get(GridCoord(6, 6)), Some(Voxel::new(8)));
+        assert_eq!(grid.hash, rebuilt(&grid).hash);
+    }
+
+    #[test]
+    fn test_merge_out_of_bounds() {
+        let mut grid = Grid::new();
+        assert!(grid.merge_from(&prefab(), (-1, 9), MergePolicy::Overwrite));
+        assert_eq!(grid.get(GridCoord(0, 9)), Some(Voxel::new(7)));
+        assert_eq!(grid.count_by_element().values().sum::<usize>(), 1);
+
+        // Cells off the grid cannot conflict with anything
+        let mut grid = Grid::new();
+        grid.set(GridCoord(0, 0), Voxel::new(1));
+        assert!(grid.merge_from(&prefab(), (8, -1), MergePolicy::OnlyEmpty));
+        assert_eq!(grid.get(GridCoord(9, 0)), Some(Voxel::new(8)));
+        assert_eq!(grid.count_by_element().values().sum::<usize>(), 2);
+    }
+
+    #[test]
+    fn test_rle_sparse_round_trip() {
+        let mut grid = Grid::new();
+        grid.set(GridCoord(0, 0), Voxel::new(3));
+        grid.set(GridCoord(9, 9), crate::voxel::VoxelBuilder::voxel().element(500).colour([1, 2, 3, 4]).build());
+        grid.set(GridCoord(4, 6), Voxel::new(0));
+
+        let bytes = grid.to_rle();
+        assert!(bytes.len() < VOXEL_COUNT);
+
+        let decoded = Grid::from_rle(&bytes).unwrap();
+        assert!(grid == decoded);
+        assert!(grid.diff(&decoded).is_empty());
+        assert_eq!(decoded.hash, rebuilt(&grid).hash);
+        assert_eq!(decoded.get(GridCoord(9, 9)).unwrap().colour, [1, 2, 3, 4]);
+
+        assert!(Grid::from_rle(&Grid::new().to_rle()).unwrap() == Grid::new());
+    }
+
+    #[test]
+    fn test_rle_dense_round_trip() {
+        let mut grid = Grid::new();
+        for y in 0..VOXEL_COUNT_Y as u64 {
+            for x in 0..VOXEL_COUNT_X as u64 {
+                grid.set(GridCoord(x, y), Voxel::new(((x + y) % 3) as u16));
+            }
+        }
+
+        let decoded = Grid::from_rle(&grid.to_rle()).unwrap();
+        assert!(grid == decoded);
+        assert_eq!(decoded.elements, grid.elements);
+    }
+
+    #[test]
+    fn test_rle_malformed() {
+        let mut grid = Grid::new();
+        grid.set(GridCoord(2, 0), Voxel::new(1));
+        let bytes = grid.to_rle();
+
+        assert_eq!(Grid::from_rle(&bytes[..bytes.len() - 2]).err(), Some(DecodeError::CellCountMismatch {
+            expected: VOXEL_COUNT,
+            found: 3
+        }));
+        assert_eq!(Grid::from_rle(&bytes[..4]).err(), Some(DecodeError::UnexpectedEnd));
+        assert_eq!(Grid::from_rle(&[0, RLE_EMPTY]).err(), Some(DecodeError::EmptyRun));
+        assert_eq!(Grid::from_rle(&[1, 7]).err(), Some(DecodeError::InvalidTag(7)));
+        assert_eq!(Grid::from_rle(&[100, RLE_EMPTY, 1, RLE_EMPTY]).err(), Some(DecodeError::CellCountMismatch {
+            expected: VOXEL_COUNT,
+            found: 101
+        }));
+    }
+
+    #[test]
+    fn test_iter_all() {
+        let mut grid = Grid::new();
+        grid.set(GridCoord(3, 1), Voxel::new(2));
+        grid.set(GridCoord(0, 9), Voxel::new(5));
+
+        let cells: Vec<(u64, u64, Option<Voxel>)> = grid.iter_all().collect();
+        assert_eq!(cells.len(), VOXEL_COUNT_X * VOXEL_COUNT_Y);
+        assert_eq!(cells[0], (0, 0, None));
+        assert_eq!(cells[1], (1, 0, None));
+        assert_eq!(cells[VOXEL_COUNT_X + 3], (3, 1, Some(Voxel::new(2))));
+        assert_eq!(cells.iter().filter(|(_, _, voxel)| voxel.is_none()).count(), VOXEL_COUNT - 2);
+
+        let filled: Vec<(u64, u64, Voxel)> = grid.iter_filled().collect();
+        assert_eq!(filled, vec![(3, 1, Voxel::new(2)), (0, 9, Voxel::new(5))]);
+    }
+
+    #[test]
+    fn test_exposed_faces() {
+        let mut grid = Grid::new();
+        grid.set(GridCoord(2, 2), Voxel::new(1));
+        assert_eq!(grid.exposed_faces(2, 2), FaceMask::ALL);
+        assert_eq!(grid.exposed_faces(3, 3), FaceMask::NONE);
+
+        for (x, y) in [(5, 4), (4, 5), (6, 5), (5, 6), (5, 5)] {
+            grid.set(GridCoord(x, y), Voxel::new(1));
+        }
+        assert_eq!(grid.exposed_faces(5, 5), FaceMask::NONE);
+        assert_eq!(grid.exposed_faces(5, 4), FaceMask::UP | FaceMask::LEFT | FaceMask::RIGHT);
+
+        // The grid edge counts as empty
+        grid.set(GridCoord(0, 9), Voxel::new(1));
+        grid.set(GridCoord(1, 9), Voxel::new(1));
+        grid.set(GridCoord(0, 8), Voxel::new(1));
+        assert_eq!(grid.exposed_faces(0, 9), FaceMask::DOWN | FaceMask::LEFT);
+
+        let all = grid.all_exposed_faces();
+        assert_eq!(all.len(), 8);
+        assert!(all.iter().all(|(x, y, _)| (*x, *y) != (5, 5)));
+        assert!(all.contains(&(2, 2, FaceMask::ALL)));
+    }
+
+    #[test]
+    fn test_to_quad_vertices() {
+        let mut grid = Grid::new();
+        assert!(grid.to_quad_vertices(1.0).is_empty());
+
+        grid.set(GridCoord(3, 1), crate::voxel::VoxelBuilder::voxel().colour([255, 0, 0, 255]).build());
+        grid.set(GridCoord(0, 9), Voxel::new(1));
+        grid.set(GridCoord(7, 7), Voxel::new(2));
+
+        let vertices = grid.to_quad_vertices(2.0);
+        assert_eq!(vertices.len(), grid.iter_filled().count() * 6);
+
+        // Row-major order puts (3, 1) first
+        let positions: Vec<[f32; 2]> = vertices[..6].iter().map(|vertex| vertex.position).collect();
+        assert_eq!(positions, vec![[6.0, 2.0], [8.0, 2.0], [8.0, 4.0], [6.0, 2.0], [8.0, 4.0], [6.0, 4.0]]);
+        assert!(vertices[..6].iter().all(|vertex| vertex.colour == [1.0, 0.0, 0.0, 1.0]));
+        assert_eq!(bytemuck::cast_slice::<QuadVertex, u8>(&vertices).len(), vertices.len() * 24);
+    }
+
+    #[test]
+    fn test_find_orientation_matches() {
+        // An L tetromino, which looks different in each of its 8 orientations
+        let mut target = Grid::new();
+        for (x, y) in [(1, 1), (1, 2), (1, 3), (2, 3)] {
+            target.set(GridCoord(x, y), Voxel::new(1));
+        }
+
+        let orientations = [
+            target.rotated_90_cw(),
+            target.rotated_180(),
+            target.rotated_270_cw(),
+            target.flipped_x(),
+            target.flipped_y(),
+            target.rotated_90_cw().flipped_x()
+        ];
+        assert!(orientations.iter().all(|grid| grid.is_orientation_of(&target)));
+
+        let mut other_shape = Grid::new();
+        for (x, y) in [(1, 1), (1, 2), (2, 2), (2, 3)] {
+            other_shape.set(GridCoord(x, y), Voxel::new(1));
+        }
+        let mut other_element = target.rotated_180().rotated_180();
+        other_element.set(GridCoord(2, 3), Voxel::new(2));
+
+        let candidates = [
+            target.rotated_90_cw(),
+            other_shape,
+            target.flipped_x(),
+            other_element,
+            target.rotated_90_cw().flipped_x()
+        ];
+        let matches = find_orientation_matches(&target, candidates.iter());
+        assert_eq!(matches.len(), 3);
+        assert!(std::ptr::eq(matches[0], &candidates[0]));
+        assert!(std::ptr::eq(matches[1], &candidates[2]));
+        assert!(std::ptr::eq(matches[2], &candidates[4]));
+    }
+
+    #[test]
+    fn test_hash_matches_from_scratch() {
+        let mut grid = Grid::new();
+
+        let mut rng = Lcg::new(0x9E3779B97F4A7C15);
+        let mut next = || rng.next_u31();
+
+        for _ in 0..2000 {
+            let coord = GridCoord(next() % VOXEL_COUNT_X as u64, next() % VOXEL_COUNT_Y as u64);
+            if next() % 3 == 0 {
+                grid.remove(coord);
+                assert_eq!(grid.get(coord), None);
+            } else {
+                grid.set(coord, Voxel::new((next() % 4) as u16));
+            }
+            assert_eq!(grid.hash, grid.compute_hash_from_scratch());
+        }
+
+        assert_eq!(grid.hash, rebuilt(&grid).hash);
+    }
+
+    #[test]
+    fn test_count_by_element() {
+        let mut grid = Grid::new();
+        grid.set(GridCoord(0, 0), Voxel::new(1));
+        grid.set(GridCoord(1, 0), Voxel::new(1));
+        grid.set(GridCoord(5, 5), Voxel::new(1));
+        grid.set(GridCoord(2, 3), Voxel::new(4));
+        grid.set(GridCoord(9, 9), Voxel::new(0));
+
+        let counts = grid.count_by_element();
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts[&1], 3);
+        assert_eq!(counts[&4], 1);
+        assert_eq!(counts[&0], 1);
+        assert_eq!(counts.values().sum::<usize>(), 5);
+
+        assert_eq!(grid.count_of(1), 3);
+        assert_eq!(grid.count_of(7), 0);
+        assert!(Grid::new().count_by_element().is_empty());
+    }
+
+    #[test]
+    fn test_rows_and_columns() {
+        let mut grid = Grid::new();
+        grid.set(GridCoord(0, 0), Voxel::new(1));
+        grid.set(GridCoord(3, 0), Voxel::new(2));
+        grid.set(GridCoord(0, 4), Voxel::new(3));
+
+        let mut expected_row = vec![None; VOXEL_COUNT_X];
+        expected_row[0] = Some(Voxel::new(1));
+        expected_row[3] = Some(Voxel::new(2));
+        assert_eq!(grid.rows().next().unwrap().collect::<Vec<_>>(), expected_row);
+        assert_eq!(grid.rows().count(), VOXEL_COUNT_Y);
+        assert!(grid.rows().all(|row| row.count() == VOXEL_COUNT_X));
+
+        let mut expected_column = vec![None; VOXEL_COUNT_Y];
+        expected_column[0] = Some(Voxel::new(1));
+        expected_column[4] = Some(Voxel::new(3));
+        assert_eq!(grid.columns().next().unwrap().collect::<Vec<_>>(), expected_column);
+        assert_eq!(grid.columns().count(), VOXEL_COUNT_X);
+    }
+
+    #[test]
+    fn test_occupied_bounds() {
+        let mut grid = Grid::new();
+        assert_eq!(grid.occupied_bounds(), None);
+
+        grid.set(GridCoord(6, 1), Voxel::new(1));
+        grid.set(GridCoord(2, 5), Voxel::new(2));
+        grid.set(GridCoord(4, 8), Voxel::new(3));
+        assert_eq!(grid.occupied_bounds(), Some((2, 1, 6, 8)));
+    }
+
+    #[test]
+    fn test_same_pattern_ignoring_translation() {
+        let mut pattern = Grid::new();
+        pattern.set(GridCoord(0, 0), Voxel::new(1));
+        pattern.set(GridCoord(1, 0), Voxel::new(1));
+        pattern.set(GridCoord(1, 1), Voxel::new(2));
+
+        let mut translated = Grid::new();
+        translated.set(GridCoord(5, 3), Voxel::new(1));
+        translated.set(GridCoord(6, 3), Voxel::new(1));
+        translated.set(GridCoord(6, 4), Voxel::new(2));
+        assert!(pattern.same_pattern_ignoring_translation(&translated));
+        assert!(translated.same_pattern_ignoring_translation(&pattern));
+
+        let mut different = Grid::new();
+        different.set(GridCoord(5, 3), Voxel::new(1));
+        different.set(GridCoord(6, 3), Voxel::new(1));
+        different.set(GridCoord(5, 4), Voxel::new(2));
+        assert!(!pattern.same_pattern_ignoring_translation(&different));
+
+        assert!(Grid::new().same_pattern_ignoring_translation(&Grid::new()));
+        assert!(!Grid::new().same_pattern_ignoring_translation(&pattern));
+    }
+
+    #[test]
+    fn test_rotations() {
+        let mut grid = Grid::new();
+        grid.set(GridCoord(0, 0), Voxel::new(1));
+        grid.set(GridCoord(3, 1), Voxel::new(2));
+        grid.set(GridCoord(7, 6), Voxel::new(3));
+
+        let rotated = grid.rotated_90_cw();
+        assert_eq!(rotated.get(GridCoord(9, 0)), Some(Voxel::new(1)));
+        assert_eq!(rotated.get(GridCoord(8, 3)), Some(Voxel::new(2)));
+        assert_eq!(rotated.get(GridCoord(3, 7)), Some(Voxel::new(3)));
+
+        let full_turn = rotated.rotated_90_cw().rotated_90_cw().rotated_90_cw();
+        assert!(full_turn.elements == grid.elements);
+        assert_eq!(full_turn.hash, grid.hash);
+
+        assert!(grid.rotated_180().elements == rotated.rotated_90_cw().elements);
+        assert!(grid.rotated_270_cw().elements == grid.rotated_180().rotated_90_cw().elements);
+
+        assert_eq!(grid.flipped_x().get(GridCoord(9, 0)), Some(Voxel::new(1)));
+        assert_eq!(grid.flipped_y().get(GridCoord(0, 9)), Some(Voxel::new(1)));
+        assert!(grid.flipped_x().flipped_x().elements == grid.elements);
+        assert!(grid.flipped_x().flipped_y().elements == grid.rotated_180().elements);
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut old = Grid::new();
+        old.set(GridCoord(2, 3), Voxel::new(1));
+        old.set(GridCoord(4, 4), Voxel::new(2));
+
+        let mut new = Grid::new();
+        new.set(GridCoord(2, 3), Voxel::new(1));
+        new.set(GridCoord(4, 4), Voxel::new(2));
+        assert!(old.diff(&new).is_empty());
+
+        new.set(GridCoord(4, 4), Voxel::new(5));
+        assert_eq!(old.diff(&new), vec![
+            (4, 4, Some(Voxel::new(2)), Some(Voxel::new(5)))
+        ]);
+    }
+
+    #[test]
+    fn test_diff_changes_outside_hash() {
+        // An empty cell and a default voxel hash the same
+        let old = Grid::new();
+        let mut new = Grid::new();
+        new.set(GridCoord(1, 1), Voxel::default());
+        assert_eq!(old.hash, new.hash);
+        assert_eq!(old.diff(&new), vec![(1, 1, None, Some(Voxel::default()))]);
+
+        // The hash only covers element ids, not colour
+        let mut old = Grid::new();
+        old.set(GridCoord(2, 3), Voxel::new(1));
+        let mut new = Grid::new();
+        let recoloured = VoxelBuilder::voxel().element(1).colour([255, 0, 0, 255]).build();
+        new.set(GridCoord(2, 3), recoloured);
+        assert_eq!(old.hash, new.hash);
+        assert_eq!(old.diff(&new), vec![(2, 3, Some(Voxel::new(1)), Some(recoloured))]);
+    }
+
+    #[test]
+    fn test_walk_capsule() {
+        let mut grid = SpatialGrid::new(1.0);
+        grid.grid.set(GridCoord(5, 5), Voxel::new(1));
+
+        // Passes 0.2 / sqrt(2) from the solid voxel's corner at (5, 6)
+        let start = Vector2::new(3.0, 4.2);
+        let end = Vector2::new(7.0, 8.2);
+
+        let mut hits = Vec::new();
+        grid.walk_capsule(start, end, 0.3, &mut |x, y, _| {
+            hits.push((x, y));
+            true
+        });
+        assert_eq!(hits, vec![(5, 5)]);
+
+        hits.clear();
+        grid.walk_capsule(start, end, 0.1, &mut |x, y, _| {
+            hits.push((x, y));
+            true
+        });
+        assert!(hits.is_empty());
+    }
+
+    #[test]
+    fn test_raycast_with_splash() {
+        let mut grid = SpatialGrid::new(1.0);
+        grid.grid.set(GridCoord(2, 5), Voxel::new(9));
+        grid.grid.set(GridCoord(5, 5), Voxel::new(1));
+        grid.grid.set(GridCoord(5, 4), Voxel::new(2));
+        grid.grid.set(GridCoord(6, 6), Voxel::new(3));
+        grid.grid.set(GridCoord(5, 7), Voxel::new(4));
+        grid.grid.set(GridCoord(8, 8), Voxel::new(5));
+
+        let ray = Ray {
+            origin: Vector2::new(0.0, 5.5),
+            direction: Vector2::new(1.0, 0.0),
+            max_distance: None
+        };
+
+        // Element 9 is see-through, so the ray stops at (5, 5)
+        let (hit, mut splashed) = grid.raycast_with_splash(&ray, 1.2, |v| v.element_id != 9).unwrap();
+        assert_eq!((hit.x, hit.y), (5, 5));
+        assert_eq!(hit.position, Vector2::new(5.0, 5.5));
+
+        splashed.sort_by_key(|(x, y, _)| (*x, *y));
+        let cells: Vec<(u64, u64)> = splashed.iter().map(|(x, y, _)| (*x, *y)).collect();
+        assert_eq!(cells, vec![(5, 4), (5, 5), (6, 6)]);
+    }
+
+    fn empty_is_walkable(voxel: Option<Voxel>) -> bool {
+        voxel.is_none()
+    }
+
+    fn assert_valid_path(path: &[(u64, u64)], start: (u64, u64), goal: (u64, u64)) {
+        assert_eq!(path.first(), Some(&start));
+        assert_eq!(path.last(), Some(&goal));
+        for step in path.windows(2) {
+            let (a, b) = (step[0], step[1]);
+            assert_eq!(a.0.abs_diff(b.0) + a.1.abs_diff(b.1), 1);
+        }
+    }
+
+    #[test]
+    fn test_find_path_open_grid() {
+        let grid = SpatialGrid::new(1.0);
+        let path = grid.find_path((1, 2), (6, 8), empty_is_walkable).unwrap();
+
+        assert_valid_path(&path, (1, 2), (6, 8));
+        assert_eq!(path.len(), 5 + 6 + 1);
+        assert_eq!(grid.find_path((3, 3), (3, 3), empty_is_walkable), Some(vec![(3, 3)]));
+    }
+
+    #[test]
+    fn test_find_path_walled_off() {
+        let mut grid = SpatialGrid::new(1.0);
+        for (x, y) in [(7, 7), (8, 7), (9, 7), (7, 8), (7, 9)] {
+            grid.grid.set(GridCoord(x, y), Voxel::new(1));
+        }
+
+        assert!(grid.find_path((0, 0), (9, 9), empty_is_walkable).is_none());
+        assert!(grid.find_path((0, 0), (7, 7), empty_is_walkable).is_none());
+        assert!(grid.find_path((0, 0), (VOXEL_COUNT_X as u64, 0), empty_is_walkable).is_none());
+
+        // Element 1 is passable, so the wall no longer blocks
+        let path = grid.find_path((0, 0), (9, 9), |voxel| voxel.is_none_or(|v| v.element_id == 1)).unwrap();
+        assert_eq!(path.len(), 19);
+    }
+
+    #[test]
+    fn test_find_path_maze() {
+        // Walls along x = 2 and x = 5 with gaps at opposite ends force a zig-zag
+        let mut grid = SpatialGrid::new(1.0);
+        for y in 0..VOXEL_COUNT_Y as u64 {
+            if y != 9 {
+                grid.grid.set(GridCoord(2, y), Voxel::new(1));
+            }
+            if y != 0 {
+                grid.grid.set(GridCoord(5, y), Voxel::new(1));
+            }
+        }
+
+        let path = grid.find_path((0, 0), (9, 9), empty_is_walkable).unwrap();
+        assert_valid_path(&path, (0, 0), (9, 9));
+        assert!(path.contains(&(2, 9)));
+        assert!(path.contains(&(5, 0)));
+        assert!(path.iter().all(|(x, y)| grid.grid.get(GridCoord(*x, *y)).is_none()));
+        // down to the first gap, up to the second, then down to the goal
+        assert_eq!(path.len(), (2 + 9) + (3 + 9) + (4 + 9) + 1);
+    }
+
+    #[test]
+    fn test_ray_enters() {
+        let mut grid = SpatialGrid::new(2.0);
+        grid.set_origin(Vector2::new(10.0, 10.0));
+
+        let ray = Ray {
+            origin: Vector2::new(0.0, 15.0),
+            direction: Vector2::new(1.0, 0.0),
+            max_distance: None
+        };
+        assert_eq!(grid.ray_enters(&ray), Some(Vector2::new(10.0, 15.0)));
+
+        let inside = Ray { origin: Vector2::new(12.0, 12.0), ..ray };
+        assert_eq!(grid.ray_enters(&inside), Some(Vector2::new(12.0, 12.0)));
+
+        let away = Ray { direction: Vector2::new(-1.0, 0.0), ..ray };
+        assert_eq!(grid.ray_enters(&away), None);
+
+        let above = Ray { origin: Vector2::new(0.0, 5.0), ..ray };
+        assert_eq!(grid.ray_enters(&above), None);
+        assert_eq!(grid.ray_cells(&above).count(), 0);
+
+        let short = Ray { max_distance: Some(5.0), ..ray };
+        assert_eq!(grid.ray_enters(&short), None);
+    }
+
+    #[test]
+    fn test_distance_to_first_solid() {
+        let mut grid = SpatialGrid::new(2.5);
+        for y in 0..10 {
+            grid.grid.set(GridCoord(6, y), Voxel::new(1));
+        }
+        grid.grid.set(GridCoord(3, 1), Voxel::new(9));
+
+        // The direction is not unit length, so the distance is not the ray parameter
+        let ray = Ray {
+            origin: Vector2::new(1.0, 3.0),
+            direction: Vector2::new(2.0, 0.0),
+            max_distance: None
+        };
+        let distance = grid.distance_to_first_solid(&ray, |voxel| voxel.element_id != 9).unwrap();
+        assert!((distance - 14.0).abs() < 1e-9);
+
+        let distance = grid.distance_to_first_solid(&ray, |_| true).unwrap();
+        assert!((distance - 6.5).abs() < 1e-9);
+
+        let away = Ray { direction: Vector2::new(-1.0, 0.0), ..ray };
+        assert_eq!(grid.distance_to_first_solid(&away, |_| true), None);
+    }
+
+    #[test]
+    fn test_segment_hits() {
+        let mut grid = SpatialGrid::new(2.0);
+        for i in 0..10 {
+            grid.grid.set(GridCoord(i, i), Voxel::new(1));
+        }
+        grid.grid.set(GridCoord(2, 3), Voxel::new(2));
+        grid.grid.set(GridCoord(4, 4), Voxel::new(9));
+
+        let start = Vector2::new(1.0, 1.5);
+        let end = Vector2::new(13.0, 13.5);
+        let hits = grid.segment_hits(start, end, |voxel| voxel.element_id != 9);
+
+        let cells: Vec<(u64, u64)> = hits.iter().map(|(GridCoord(x, y), _, _, _)| (*x, *y)).collect();
+        assert_eq!(cells, vec![(0, 0), (1, 1), (2, 2), (2, 3), (3, 3), (5, 5), (6, 6)]);
+
+        for (_, _, t_entry, t_exit) in &hits {
+            assert!(0.0 <= *t_entry && t_entry < t_exit && *t_exit <= 1.0);
+        }
+        for pair in hits.windows(2) {
+            assert!(pair[0].3 <= pair[1].2);
+        }
+
+        // The segment stops partway through (6, 6)
+        let (_, _, t_entry, t_exit) = hits.last().unwrap();
+        assert!((start + (end - start) * *t_entry - Vector2::new(12.0, 12.5)).magnitude() < 1e-9);
+        assert_eq!(*t_exit, 1.0);
+
+        assert!(grid.segment_hits(start, start, |_| true).is_empty());
+    }
+
+    #[test]
+    fn test_density_in_aabb() {
+        let mut grid = SpatialGrid::new(2.0);
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (5, 5)] {
+            grid.grid.set(GridCoord(x, y), Voxel::new(1));
+        }
+
+        // Covers cells (0..=1, 0..=1), the rest of the box is off the grid
+        let corner = AABB::from_position_and_size(Vector2::new(-10.0, -10.0), Vector2::new(13.0, 13.0));
+        assert_eq!(grid.density_in_aabb(&corner), 0.75);
+
+        let whole = AABB::from_position_and_size(Vector2::new(-1.0, -1.0), Vector2::new(30.0, 30.0));
+        assert_eq!(grid.density_in_aabb(&whole), 0.04);
+
+        let off_grid = AABB::from_position_and_size(Vector2::new(-10.0, -10.0), Vector2::new(5.0, 5.0));
+        assert_eq!(grid.density_in_aabb(&off_grid), 0.0);
+
+        let flat = AABB::from_position_and_size(Vector2::new(0.5, 0.5), Vector2::new(3.0, 0.0));
+        assert_eq!(grid.density_in_aabb(&flat), 0.0);
+    }
+
+    #[test]
+    fn test_debug_traversal() {
+        let mut grid = SpatialGrid::new(1.5);
+        grid.grid.set(GridCoord(2, 8), Voxel::new(1));
+
+        let ray = Ray {
+            origin: Vector2::new(0.2, 13.9),
+            direction: Vector2::new(0.8, -0.35),
+            max_distance: None
+        };
+        let path = grid.debug_traversal(&ray);
+
+        assert_eq!(path.first(), Some(&(0, 9)));
+        assert!(path.iter().all(|(x, y)| (0..10).contains(x) && (0..10).contains(y)));
+        assert_eq!(path.last().unwrap().0, 9);
+        for step in path.windows(2) {
+            let (dx, dy) = (step[1].0 - step[0].0, step[1].1 - step[0].1);
+            // Each step moves to a neighbouring cell along the ray: +x, or -y since it
+            // heads towards lower row indices
+            assert!((dx, dy) == (1, 0) || (dx, dy) == (0, -1), "{:?} -> {:?}", step[0], step[1]);
+        }
+
+        // The walk carries on through filled cells and includes empty ones
+        assert!(path.contains(&(2, 8)));
+        assert!(path.iter().any(|(x, y)| grid.grid.get(GridCoord(*x as u64, *y as u64)).is_none()));
+    }
+
+    #[test]
+    fn test_raycast_ignoring() {
+        let mut grid = SpatialGrid::new(1.0);
+        grid.grid.set(GridCoord(2, 3), Voxel::new(7));
+        grid.grid.set(GridCoord(4, 3), Voxel::new(8));
+        grid.grid.set(GridCoord(6, 3), Voxel::new(1));
+
+        let ray = Ray {
+            origin: Vector2::new(0.0, 3.5),
+            direction: Vector2::new(1.0, 0.0),
+            max_distance: None
+        };
+
+        let hit = grid.raycast_ignoring(&ray, &[7, 8]).unwrap();
+        assert_eq!((hit.x, hit.y), (6, 3));
+        assert_eq!(hit.voxel.element_id, 1);
+
+        let hit = grid.raycast_ignoring(&ray, &[]).unwrap();
+        assert_eq!((hit.x, hit.y), (2, 3));
+
+        assert!(grid.raycast_ignoring(&ray, &[1, 7, 8]).is_none());
+    }
+
+    #[test]
+    fn test_sorted_intersections() {
+        let mut grid = SpatialGrid::new(2.0);
+        grid.origin = Vector2::new(-4.0, 1.0);
+        for x in [1, 4, 7] {
+            grid.grid.set(GridCoord(x, 3), Voxel::new(x as u16));
+        }
+        grid.grid.set(GridCoord(5, 5), Voxel::new(100));
+
+        let ray = Ray {
+            origin: Vector2::new(-10.0, 8.0),
+            direction: Vector2::new(1.0, 0.0),
+            max_distance: None
+        };
+
+        let hits = grid.get_intersections_sorted(ray, IntersectType::All);
+        assert_eq!(hits.iter().map(|hit| hit.x).collect::<Vec<_>>(), vec![1, 4, 7]);
+        assert!(hits.windows(2).all(|pair| pair[0].t < pair[1].t));
+        assert_eq!(hits[0].t, 8.0);
+
+        let first = grid.get_intersections(ray, IntersectType::First);
+        assert_eq!(first, vec![Voxel::new(1)]);
+
+        let closest = grid.closest_n(ray, 2);
+        assert_eq!(closest, hits[..2].to_vec());
+
+        let mut shuffled = vec![hits[2], hits[0], hits[1]];
+        sort_by_distance(&mut shuffled);
+        assert_eq!(shuffled, hits);
+    }
+
+    #[test]
+    fn test_reprojection() {
+        let mut grid = SpatialGrid::new(1.0);
+        assert_eq!(grid.bounds().size, Vector2::new(10.0, 10.0));
+
+        grid.set_voxel_side_length(2.5);
+        grid.set_origin(Vector2::new(-5.0, 3.0));
+        let bounds = grid.bounds();
+        assert_eq!(bounds.position, Vector2::new(-5.0, 3.0));
+        assert_eq!(bounds.size, Vector2::new(25.0, 25.0));
+    }
+
+    #[test]
+    fn test_cell_aabb() {
+        let mut grid = SpatialGrid::new(2.5);
+        grid.set_origin(Vector2::new(-4.0, 3.0));
+
+        let cell = grid.cell_aabb(GridCoord(2, 3));
+        assert_eq!(cell.position, Vector2::new(1.0, 10.5));
+        assert_eq!(cell.size, Vector2::new(2.5, 2.5));
+        assert!(grid.bounds().overlap(&cell).is_some());
+    }
+
+    #[test]
+    fn test_coordinate_round_trip() {
+        let mut grid = SpatialGrid::new(2.5);
+        grid.set_origin(Vector2::new(-4.0, 3.0));
+
+        for coord in [GridCoord(0, 0), GridCoord(3, 7), GridCoord(9, 9)] {
+            assert_eq!(grid.to_grid(grid.to_world(coord)), Some(coord));
+        }
+
+        assert_eq!(grid.to_world(GridCoord(2, 3)), WorldPos(Vector2::new(2.25, 11.75)));
+        assert_eq!(grid.to_grid(WorldPos(Vector2::new(-4.0, 3.0))), Some(GridCoord(0, 0)));
+        assert_eq!(grid.to_grid(WorldPos(Vector2::new(-4.1, 3.0))), None);
+        assert_eq!(grid.to_grid(WorldPos(Vector2::new(21.0, 3.0))), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_non_positive_side_length() {
+        SpatialGrid::new(1.0).set_voxel_side_length(0.0);
+    }
+
+    #[test]
+    fn test_ray_cells() {
+        let grid = SpatialGrid::new(1.0);
+        let ray = Ray {
+            origin: Vector2::new(0.5, 0.0),
+            direction: Vector2::new(2.0, 1.0),
+            max_distance: None
+        };
+
+        // y = (x - 0.5) / 2 crosses y = 1 at x = 2.5 and y = 2 at x = 4.5
+        let cells: Vec<(i64, i64)> = grid.ray_cells(&ray).take(7).collect();
+        assert_eq!(cells, vec![(0, 0), (1, 0), (2, 0), (2, 1), (3, 1), (4, 1), (4, 2)]);
+
+        let all: Vec<(i64, i64)> = grid.ray_cells(&ray).collect();
+        assert_eq!(all.last(), Some(&(9, 4)));
+
+        let missing = Ray {
+            origin: Vector2::new(-5.0, -5.0),
+            direction: Vector2::new(-1.0, 0.0),
+            max_distance: None
+        };
+        assert_eq!(grid.ray_cells(&missing).count(), 0);
+    }
+
+    #[test]
+    fn test_wrapping_ray() {
+        let mut grid = SpatialGrid::new(1.0);
+        grid.wrap = true;
+        grid.grid.set(GridCoord(1, 4), Voxel::new(7));
+
+        let ray = Ray {
+            origin: Vector2::new(7.5, 4.5),
+            direction: Vector2::new(1.0, 0.0),
+            max_distance: Some(5.0)
+        };
+
+        let cells: Vec<(i64, i64)> = grid.ray_cells(&ray).collect();
+        assert_eq!(cells, vec![(7, 4), (8, 4), (9, 4), (0, 4), (1, 4), (2, 4)]);
+
+        let hit = grid.raycast(&ray, |_| true).unwrap();
+        assert_eq!((hit.x, hit.y), (1, 4));
+
+        grid.wrap = false;
+        assert_eq!(grid.ray_cells(&ray).count(), 3);
+        assert!(grid.raycast(&ray, |_| true).is_none());
+    }
+
+    #[test]
+    fn test_wrapping_ray_without_max_distance() {
+        let mut grid = SpatialGrid::new(1.0);
+        grid.wrap = true;
+
+        // Stops after one full grid width, back in the half of the starting cell it
+        // had not yet crossed
+        let ray = Ray {
+            origin: Vector2::new(7.5, 4.5),
+            direction: Vector2::new(1.0, 0.0),
+            max_distance: None
+        };
+        let cells: Vec<(i64, i64)> = grid.ray_cells(&ray).collect();
+        assert_eq!(cells.len(), VOXEL_COUNT_X + 1);
+        assert_eq!(cells.first(), Some(&(7, 4)));
+        assert_eq!(cells.last(), Some(&(7, 4)));
+
+        grid.grid.set(GridCoord(3, 4), Voxel::new(7));
+        let hit = grid.raycast(&ray, |_| true).unwrap();
+        assert_eq!((hit.x, hit.y), (3, 4));
+
+        let vertical = Ray {
+            origin: Vector2::new(2.5, 0.5),
+            direction: Vector2::new(0.0, -2.0),
+            max_distance: None
+        };
+        assert_eq!(grid.ray_cells(&vertical).count(), VOXEL_COUNT_Y + 1);
+    }
+
+    #[test]
+    fn test_degenerate_ray_direction() {
+        let mut grid = SpatialGrid::new(1.0);
+        grid.wrap = true;
+        grid.grid.set(GridCoord(3, 4), Voxel::new(7));
+
+        for direction in [Vector2::new(0.0, 0.0), Vector2::new(f64::NAN, 1.0), Vector2::new(f64::INFINITY, 0.0)] {
+            let ray = Ray {
+                origin: Vector2::new(3.5, 4.5),
+                direction,
+                max_distance: None
+            };
+            assert_eq!(grid.ray_cells(&ray).count(), 0);
+            assert!(grid.raycast(&ray, |_| true).is_none());
+        }
+    }
+}
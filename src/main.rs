@@ -1,4 +1,6 @@
 mod aabb;
+mod circle;
+mod quadtree;
 mod collision;
 mod colliders;
 mod grid;
@@ -10,8 +12,12 @@ mod render_engine;
 mod render_graph;
 mod render;
 mod sparse_set;
+mod spatial_hash;
+mod frame_timing;
+#[cfg(test)]
+mod test_support;
 
 fn main() {
     env_logger::init();
-    //pollster::block_on(window::Window::new()).run();
+    //pollster::block_on(window::Window::new(&Default::default(), &Default::default())).unwrap().run();
 }
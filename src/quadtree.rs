@@ -0,0 +1,240 @@
+use cgmath::Vector2;
+use crate::aabb::AABB;
+use crate::ray::Ray;
+use crate::collision::{ Collidable, Shape };
+
+/// Closed-interval overlap, so boxes that share an edge are counted as touching
+fn touches(a: &AABB, b: &AABB) -> bool {
+    a.min().x <= b.max().x && b.min().x <= a.max().x &&
+    a.min().y <= b.max().y && b.min().y <= a.max().y
+}
+
+fn encloses(outer: &AABB, inner: &AABB) -> bool {
+    outer.min().x <= inner.min().x && inner.max().x <= outer.max().x &&
+    outer.min().y <= inner.min().y && inner.max().y <= outer.max().y
+}
+
+struct Node<T> {
+    bounds: AABB,
+    depth: usize,
+    /// Items that do not fit entirely inside one child
+    items: Vec<(AABB, T)>,
+    children: Option<Box<[Node<T>; 4]>>
+}
+
+impl<T> Node<T> {
+    fn new(bounds: AABB, depth: usize) -> Node<T> {
+        Node {
+            bounds,
+            depth,
+            items: Vec::new(),
+            children: None
+        }
+    }
+
+    fn child_for(&mut self, aabb: &AABB) -> Option<&mut Node<T>> {
+        self.children.as_mut()?
+            .iter_mut()
+            .find(|child| encloses(&child.bounds, aabb))
+    }
+
+    fn insert(&mut self, aabb: AABB, item: T, capacity: usize, max_depth: usize) {
+        if let Some(child) = self.child_for(&aabb) {
+            child.insert(aabb, item, capacity, max_depth);
+            return
+        }
+
+        self.items.push((aabb, item));
+        if self.children.is_none() && self.items.len() > capacity && self.depth < max_depth {
+            self.subdivide(capacity, max_depth);
+        }
+    }
+
+    fn subdivide(&mut self, capacity: usize, max_depth: usize) {
+        let depth = self.depth + 1;
+        self.children = Some(Box::new(self.bounds.quadrants().map(|quadrant| Node::new(quadrant, depth))));
+
+        for (aabb, item) in std::mem::take(&mut self.items) {
+            self.insert(aabb, item, capacity, max_depth);
+        }
+    }
+
+    fn visit<'a>(&'a self, enters: &dyn Fn(&AABB) -> bool, found: &mut Vec<&'a T>) {
+        found.extend(self.items.iter().filter(|(aabb, _)| enters(aabb)).map(|(_, item)| item));
+
+        for child in self.children.iter().flat_map(|children| children.iter()) {
+            if enters(&child.bounds) {
+                child.visit(enters, found);
+            }
+        }
+    }
+}
+
+/// Broadphase index of items by their bounding boxes. Nodes split into quadrants once
+/// they hold more than `capacity` items, and items straddling a split stay in the parent
+pub struct Quadtree<T> {
+    root: Node<T>,
+    capacity: usize,
+    max_depth: usize,
+    len: usize
+}
+
+impl<T> Quadtree<T> {
+    const DEFAULT_CAPACITY: usize = 8;
+    const DEFAULT_MAX_DEPTH: usize = 8;
+
+    pub fn new(bounds: AABB) -> Quadtree<T> {
+        Quadtree::with_capacity(bounds, Self::DEFAULT_CAPACITY, Self::DEFAULT_MAX_DEPTH)
+    }
+
+    pub fn with_capacity(bounds: AABB, capacity: usize, max_depth: usize) -> Quadtree<T> {
+        Quadtree {
+            root: Node::new(bounds, 0),
+            capacity,
+            max_depth,
+            len: 0
+        }
+    }
+
+    /// Adds an item. Items outside the tree's bounds are kept in the root and are still
+    /// found by queries
+    pub fn insert(&mut self, aabb: AABB, item: T) {
+        self.root.insert(aabb, item, self.capacity, self.max_depth);
+        self.len += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Every item whose box overlaps or touches `region`
+    pub fn query(&self, region: &AABB) -> Vec<&T> {
+        let mut found = Vec::new();
+        self.root.visit(&|aabb| touches(aabb, region), &mut found);
+        found
+    }
+
+    /// Every item whose box contains `point`, including on its edge
+    pub fn query_point(&self, point: Vector2<f64>) -> Vec<&T> {
+        self.query(&AABB::from_position_and_size(point, Vector2::new(0.0, 0.0)))
+    }
+
+    /// Every item whose box the ray passes through
+    pub fn query_ray(&self, ray: &Ray) -> Vec<&T> {
+        let mut found = Vec::new();
+        self.root.visit(&|aabb| aabb.does_intersect(ray).is_some(), &mut found);
+        found
+    }
+}
+
+impl<S: Shape<Bounds = AABB> + ?Sized> Quadtree<Box<S>> {
+    /// Adds a shape indexed by its own bounding box
+    pub fn insert_shape(&mut self, shape: Box<S>) {
+        self.insert(shape.bounding_box(), shape);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit_box(x: f64, y: f64) -> AABB {
+        AABB::from_position_and_size(Vector2::new(x, y), Vector2::new(1.0, 1.0))
+    }
+
+    /// A half-unit box centred in each cell of a 16x16 area, labelled by cell
+    fn populated_tree() -> Quadtree<(u32, u32)> {
+        let bounds = AABB::from_position_and_size(Vector2::new(0.0, 0.0), Vector2::new(16.0, 16.0));
+        let mut tree = Quadtree::with_capacity(bounds, 4, 6);
+        for y in 0..16 {
+            for x in 0..16 {
+                let position = Vector2::new(x as f64 + 0.25, y as f64 + 0.25);
+                tree.insert(AABB::from_position_and_size(position, Vector2::new(0.5, 0.5)), (x, y));
+            }
+        }
+        tree
+    }
+
+    fn sorted(mut items: Vec<&(u32, u32)>) -> Vec<(u32, u32)> {
+        items.sort();
+        items.into_iter().copied().collect()
+    }
+
+    #[test]
+    fn test_subdivides_past_capacity() {
+        let tree = populated_tree();
+        assert_eq!(tree.len(), 256);
+        assert!(tree.root.children.is_some());
+        assert!(tree.root.items.len() <= 4);
+    }
+
+    #[test]
+    fn test_point_query() {
+        let tree = populated_tree();
+        assert_eq!(sorted(tree.query_point(Vector2::new(3.5, 7.5))), vec![(3, 7)]);
+        assert!(tree.query_point(Vector2::new(3.9, 7.9)).is_empty());
+        assert!(tree.query_point(Vector2::new(-5.0, 2.0)).is_empty());
+    }
+
+    #[test]
+    fn test_region_query() {
+        let tree = populated_tree();
+        let region = AABB::from_position_and_size(Vector2::new(2.0, 2.0), Vector2::new(2.0, 1.0));
+        assert_eq!(sorted(tree.query(&region)), vec![(2, 2), (3, 2)]);
+
+        let everything = AABB::from_position_and_size(Vector2::new(-1.0, -1.0), Vector2::new(20.0, 20.0));
+        assert_eq!(tree.query(&everything).len(), 256);
+    }
+
+    #[test]
+    fn test_ray_query() {
+        let tree = populated_tree();
+        let ray = Ray {
+            origin: Vector2::new(-1.0, 5.5),
+            direction: Vector2::new(1.0, 0.0),
+            max_distance: Some(4.0)
+        };
+        assert_eq!(sorted(tree.query_ray(&ray)), vec![(0, 5), (1, 5), (2, 5)]);
+    }
+
+    #[test]
+    fn test_straddling_item() {
+        let bounds = AABB::from_position_and_size(Vector2::new(0.0, 0.0), Vector2::new(8.0, 8.0));
+        let mut tree = Quadtree::with_capacity(bounds, 1, 4);
+        tree.insert(unit_box(1.0, 1.0), "small");
+        tree.insert(AABB::from_position_and_size(Vector2::new(3.0, 3.0), Vector2::new(2.0, 2.0)), "centre");
+        tree.insert(unit_box(6.0, 6.0), "corner");
+
+        assert_eq!(tree.query_point(Vector2::new(4.0, 4.0)), vec![&"centre"]);
+        assert_eq!(tree.query_point(Vector2::new(6.5, 6.5)), vec![&"corner"]);
+    }
+
+    #[test]
+    fn test_mixed_shapes() {
+        use crate::circle::Circle;
+        use crate::collision::Shape2D;
+
+        let bounds = AABB::from_position_and_size(Vector2::new(0.0, 0.0), Vector2::new(16.0, 16.0));
+        let mut tree: Quadtree<Box<Shape2D>> = Quadtree::with_capacity(bounds, 1, 4);
+        tree.insert_shape(Box::new(unit_box(1.0, 1.0)));
+        tree.insert_shape(Box::new(Circle::new(Vector2::new(12.0, 12.0), 1.0)));
+        tree.insert_shape(Box::new(Ray {
+            origin: Vector2::new(2.0, 12.0),
+            direction: Vector2::new(1.0, 0.0),
+            max_distance: Some(3.0)
+        }));
+        assert_eq!(tree.len(), 3);
+
+        let near_circle = tree.query_point(Vector2::new(12.5, 11.5));
+        assert_eq!(near_circle.len(), 1);
+        assert_eq!(near_circle[0].bounding_box().mid(), Vector2::new(12.0, 12.0));
+
+        let along_ray = tree.query_point(Vector2::new(4.0, 12.0));
+        assert_eq!(along_ray.len(), 1);
+        assert_eq!(along_ray[0].bounding_box().size, Vector2::new(3.0, 0.0));
+    }
+}
@@ -1,7 +1,62 @@
-use cgmath::Vector2;
+use cgmath::{ Vector2, InnerSpace };
 
+#[derive(Debug, Copy, Clone)]
 pub struct Ray {
     pub origin: Vector2<f64>,
     pub direction: Vector2<f64>,
     pub max_distance: Option<f64>
 }
+
+impl Ray {
+    /// Distance a reflected ray is moved off the surface so it doesn't hit it again
+    const REFLECTION_OFFSET: f64 = 0.00001;
+
+    /// Bounce this ray off a surface with `normal` at `hit_point`
+    pub fn reflect(&self, hit_point: Vector2<f64>, normal: Vector2<f64>) -> Ray {
+        let normal = normal.normalize();
+        Ray {
+            origin: hit_point + normal * Ray::REFLECTION_OFFSET,
+            direction: self.direction - 2.0 * self.direction.dot(normal) * normal,
+            max_distance: self.max_distance
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f64 = 1e-9;
+
+    fn assert_close(a: Vector2<f64>, b: Vector2<f64>) {
+        assert!((a - b).magnitude() < EPSILON, "{:?} != {:?}", a, b);
+    }
+
+    fn diagonal_ray() -> Ray {
+        Ray {
+            origin: Vector2::new(0.0, 0.0),
+            direction: Vector2::new(1.0, 1.0).normalize(),
+            max_distance: None
+        }
+    }
+
+    #[test]
+    fn test_reflect_vertical_wall() {
+        let hit = Vector2::new(2.0, 2.0);
+        let reflected = diagonal_ray().reflect(hit, Vector2::new(-1.0, 0.0));
+
+        assert_close(reflected.direction, Vector2::new(-1.0, 1.0).normalize());
+        assert!(reflected.origin.x < hit.x);
+        assert_eq!(reflected.origin.y, hit.y);
+    }
+
+    #[test]
+    fn test_reflect_horizontal_wall() {
+        let hit = Vector2::new(2.0, 2.0);
+        let reflected = diagonal_ray().reflect(hit, Vector2::new(0.0, -1.0));
+
+        assert_close(reflected.direction, Vector2::new(1.0, -1.0).normalize());
+        assert_eq!(reflected.origin.x, hit.x);
+        assert!(reflected.origin.y < hit.y);
+    }
+}
@@ -20,9 +20,18 @@ pub struct PipelineLayout<'layout> {
 
 impl PipelineLayout<'_> {
     pub fn create(&mut self, device: &wgpu::Device) -> wgpu::PipelineLayout {
+        self.create_with(device, None)
+    }
+
+    /// Creates the layout with `extra` as one more bind group after the layout's own
+    pub fn create_with(&mut self, device: &wgpu::Device, extra: Option<&BindingGroupLayout>) -> wgpu::PipelineLayout {
+        self.bind_group_layouts_cache.clear();
         if let Some(binding_group) = &self.binding_group {
             self.bind_group_layouts_cache.push(binding_group.create(device))
         }
+        if let Some(extra) = extra {
+            self.bind_group_layouts_cache.push(extra.create(device))
+        }
 
         let bind_group_refs: Vec<&wgpu::BindGroupLayout> = self.bind_group_layouts_cache.iter().map(|l| l).collect();
         device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
@@ -38,7 +47,194 @@ pub enum Attachment {
 
 }
 
-pub enum Queue {
-    Compute(wgpu::Queue),
-    Render(wgpu::Queue)
+pub enum Queue<Q = wgpu::Queue> {
+    Compute(Q),
+    Render(Q),
+    /// Accepts both compute and render work, which is how wgpu exposes its queue
+    Universal(Q)
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PassType {
+    Compute,
+    Render
+}
+
+impl<Q> Queue<Q> {
+    pub fn supports_compute(&self) -> bool {
+        matches!(self, Queue::Compute(_) | Queue::Universal(_))
+    }
+
+    pub fn supports_render(&self) -> bool {
+        matches!(self, Queue::Render(_) | Queue::Universal(_))
+    }
+
+    pub fn supports(&self, pass_type: PassType) -> bool {
+        match pass_type {
+            PassType::Compute => self.supports_compute(),
+            PassType::Render => self.supports_render()
+        }
+    }
+
+    pub fn inner(&self) -> &Q {
+        match self {
+            Queue::Compute(queue) => queue,
+            Queue::Render(queue) => queue,
+            Queue::Universal(queue) => queue
+        }
+    }
+}
+
+/// The first queue that can accept work for the given pass type
+pub fn find_queue<Q>(queues: &[Queue<Q>], pass_type: PassType) -> Option<&Q> {
+    queues.iter()
+        .find(|queue| queue.supports(pass_type))
+        .map(|queue| queue.inner())
+}
+
+/// A GPU buffer holding a single `T` for use as a shader uniform
+pub struct UniformBuffer<T: bytemuck::Pod> {
+    buffer: wgpu::Buffer,
+    _uniform_phantom: std::marker::PhantomData<T>
+}
+
+impl<T: bytemuck::Pod> UniformBuffer<T> {
+    pub fn new(device: &wgpu::Device, label: Option<&str>, initial: &T) -> UniformBuffer<T> {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label,
+            size: Self::size(),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: true
+        });
+        buffer.slice(..).get_mapped_range_mut().copy_from_slice(bytemuck::bytes_of(initial));
+        buffer.unmap();
+
+        UniformBuffer {
+            buffer,
+            _uniform_phantom: std::marker::PhantomData
+        }
+    }
+
+    pub fn size() -> wgpu::BufferAddress {
+        std::mem::size_of::<T>() as wgpu::BufferAddress
+    }
+
+    /// Binding type matching this buffer, for `BindGroupLayoutBuilder::add_binding`
+    pub fn binding_type() -> wgpu::BindingType {
+        Self::buffer_binding_type(false)
+    }
+
+    /// Binding type for a buffer holding several `T`s, one of which is picked by the
+    /// offset given when the bind group is set
+    pub fn dynamic_binding_type() -> wgpu::BindingType {
+        Self::buffer_binding_type(true)
+    }
+
+    fn buffer_binding_type(has_dynamic_offset: bool) -> wgpu::BindingType {
+        wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset,
+            min_binding_size: wgpu::BufferSize::new(Self::size())
+        }
+    }
+
+    /// Queues a write of `value` which takes effect at the next submission
+    pub fn update(&self, queue: &wgpu::Queue, value: &T) {
+        queue.write_buffer(&self.buffer, 0, bytemuck::bytes_of(value));
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Bind group with this buffer at `binding`, using a layout built with
+    /// `BindGroupLayoutBuilder::add_uniform_buffer`
+    pub fn bind_group(&self, device: &wgpu::Device, layout: &wgpu::BindGroupLayout, binding: u32) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Uniform Bind Group"),
+            layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding,
+                resource: self.buffer.as_entire_binding()
+            }]
+        })
+    }
+}
+
+/// A coloured 2D vertex as laid out in a vertex buffer
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct QuadVertex {
+    pub position: [f32; 2],
+    pub colour: [f32; 4]
+}
+
+// Safety: `QuadVertex` is `repr(C)`, has no implicit padding and is all floats
+unsafe impl bytemuck::Zeroable for QuadVertex {}
+unsafe impl bytemuck::Pod for QuadVertex {}
+
+impl QuadVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x2, 1 => Float32x4];
+
+    /// Buffer layout with the position at location 0 and the colour at location 1
+    pub fn layout() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<QuadVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C)]
+    #[derive(Copy, Clone)]
+    struct ViewUniform {
+        view_projection: [[f32; 4]; 4],
+        time: f32,
+        _padding: [f32; 3]
+    }
+
+    // Safety: `ViewUniform` is `repr(C)`, has no implicit padding and is all floats
+    unsafe impl bytemuck::Zeroable for ViewUniform {}
+    unsafe impl bytemuck::Pod for ViewUniform {}
+
+    #[test]
+    fn test_uniform_buffer_layout() {
+        assert_eq!(UniformBuffer::<ViewUniform>::size(), 80);
+
+        let wgpu::BindingType::Buffer { ty, has_dynamic_offset, min_binding_size } =
+            UniformBuffer::<ViewUniform>::binding_type() else {
+            panic!("Uniform buffers must bind as buffers");
+        };
+        assert_eq!(ty, wgpu::BufferBindingType::Uniform);
+        assert!(!has_dynamic_offset);
+        assert_eq!(min_binding_size.map(|size| size.get()), Some(80));
+
+        let uniform = ViewUniform {
+            view_projection: [[1.0, 0.0, 0.0, 0.0]; 4],
+            time: 2.5,
+            _padding: [0.0; 3]
+        };
+        let bytes = bytemuck::bytes_of(&uniform);
+        assert_eq!(bytes.len() as wgpu::BufferAddress, UniformBuffer::<ViewUniform>::size());
+        assert_eq!(&bytes[64..68], &2.5_f32.to_ne_bytes());
+    }
+
+    #[test]
+    fn test_universal_queue_selection() {
+        let queues = [Queue::Universal(0)];
+        assert_eq!(find_queue(&queues, PassType::Render), Some(&0));
+        assert_eq!(find_queue(&queues, PassType::Compute), Some(&0));
+
+        let split_queues = [Queue::Compute(1), Queue::Render(2)];
+        assert_eq!(find_queue(&split_queues, PassType::Render), Some(&2));
+        assert_eq!(find_queue(&split_queues, PassType::Compute), Some(&1));
+
+        let render_only = [Queue::Render(3)];
+        assert_eq!(find_queue(&render_only, PassType::Compute), None);
+    }
 }
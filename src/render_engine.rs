@@ -1,13 +1,101 @@
 pub mod api;
-mod texture;
+pub mod texture;
 mod window;
 
-use crate::render::Queue;
+use crate::render::{ self, Queue, PassType };
 use crate::resource::{ ResourceHandler, ResourceManager, ResourceMetaData, ResourceLifetime };
+use crate::render_graph::{ RenderGraph, CompiledGraph, CompileError };
+use crate::render_graph::resource::ResourceHandle;
+use crate::render_graph::shader_builder::{ ShaderBuilder, ShaderHandle, ShaderSource };
 use window::Window;
+use std::collections::HashMap;
 use wgpu::{
     Device, Adapter
 };
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum RenderEngineError {
+    #[error("No adapter matches the requested power preference and backends")]
+    NoAdapter,
+    #[error("Adapter could not provide a device: {0}")]
+    DeviceRequestFailed(#[from] wgpu::RequestDeviceError),
+    #[error("Could not create a window: {0}")]
+    WindowCreationFailed(#[from] winit::error::OsError),
+    #[error("Could not create a surface for the window: {0}")]
+    SurfaceCreationFailed(#[from] wgpu::CreateSurfaceError),
+    #[error("Surface does not support any texture format on this adapter")]
+    NoSupportedFormat,
+    #[error("Surface does not support the texture usages {0:?} on this adapter")]
+    UnsupportedSurfaceUsage(wgpu::TextureUsages),
+    #[error("Adapter does not support {0}x multisampling for offscreen targets")]
+    UnsupportedSampleCount(u32),
+    #[error("Render graph failed to compile: {0}")]
+    Compile(#[from] CompileError),
+    #[error("Offscreen target must not be empty, got {0}x{1}")]
+    EmptyTarget(u32, u32),
+    #[error("Could not map the readback buffer: {0}")]
+    ReadbackFailed(#[from] wgpu::BufferAsyncError)
+}
+
+/// Which GPU and graphics backends the engine is allowed to pick
+#[derive(Debug, Copy, Clone)]
+pub struct AdapterOptions {
+    pub power_preference: wgpu::PowerPreference,
+    pub backends: wgpu::Backends,
+    /// Only accept a software adapter, failing if there is none
+    pub force_fallback_adapter: bool
+}
+
+impl Default for AdapterOptions {
+    fn default() -> AdapterOptions {
+        AdapterOptions {
+            power_preference: wgpu::PowerPreference::default(),
+            backends: wgpu::Backends::all(),
+            force_fallback_adapter: false
+        }
+    }
+}
+
+/// How the window surface is configured
+#[derive(Debug, Copy, Clone)]
+pub struct SurfaceOptions {
+    /// Usages the surface is configured with, e.g. `COPY_SRC` for screenshots. Each must
+    /// be supported by the surface on the chosen adapter
+    pub usage: wgpu::TextureUsages
+}
+
+impl Default for SurfaceOptions {
+    fn default() -> SurfaceOptions {
+        SurfaceOptions {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+        }
+    }
+}
+
+impl AdapterOptions {
+    pub fn create_instance(&self) -> wgpu::Instance {
+        wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: self.backends,
+            dx12_shader_compiler: Default::default()
+        })
+    }
+
+    /// An adapter matching these options which can present to `surface`, if given
+    pub async fn request_adapter(
+        &self,
+        instance: &wgpu::Instance,
+        surface: Option<&wgpu::Surface>
+    ) -> Result<Adapter, RenderEngineError> {
+        instance.request_adapter(
+            &wgpu::RequestAdapterOptions {
+                power_preference: self.power_preference,
+                compatible_surface: surface,
+                force_fallback_adapter: self.force_fallback_adapter,
+            },
+        ).await.ok_or(RenderEngineError::NoAdapter)
+    }
+}
 
 pub struct DeviceState {
     device: Device,
@@ -16,14 +104,14 @@ pub struct DeviceState {
 }
 
 impl DeviceState {
-    async fn new(instance: &wgpu::Instance, surface: &wgpu::Surface) -> DeviceState {
-        let adapter = instance.request_adapter(
-            &wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            },
-        ).await.unwrap();
+    /// Device on an adapter matching `options`, which can present to `surface` if given.
+    /// Without a surface the device can only render offscreen
+    pub async fn new(
+        instance: &wgpu::Instance,
+        surface: Option<&wgpu::Surface>,
+        options: &AdapterOptions
+    ) -> Result<DeviceState, RenderEngineError> {
+        let adapter = options.request_adapter(instance, surface).await?;
 
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
@@ -32,53 +120,262 @@ impl DeviceState {
                 label: None,
             },
             None
-        ).await.unwrap();
+        ).await?;
 
-        DeviceState {
+        Ok(DeviceState {
             device,
             adapter,
-            queues: Box::new([Queue::Render(queue)])
+            queues: Box::new([Queue::Universal(queue)])
+        })
+    }
+
+    fn render_queue(&self) -> &wgpu::Queue {
+        render::find_queue(&self.queues, PassType::Render).expect("Device has no render queue")
+    }
+
+    /// Renders `graph` into an offscreen RGBA8 texture and reads it back to the CPU as
+    /// tightly packed rows. The texture is attached in place of `target`, usually the
+    /// graph's surface resource, and starts cleared to transparent black. When
+    /// `sample_count` is above 1 the graph draws to a multisampled texture which is
+    /// resolved into the one read back, so its pipelines must use the same sample count.
+    /// `compiled` must submit to this device's render queue. Fails without creating
+    /// anything if `width` or `height` is zero
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_to_buffer<'graph, S>(
+        &self,
+        compiled: &mut CompiledGraph<'graph>,
+        graph: &'graph RenderGraph,
+        target: ResourceHandle,
+        shaders: &HashMap<ShaderHandle, &ShaderBuilder<'graph, S>>,
+        vertex_buffer_layout: &'graph [wgpu::VertexBufferLayout],
+        colour_target_state: &'graph [Option<wgpu::ColorTargetState>],
+        vertex_buffer_attachments: &HashMap<ResourceHandle, wgpu::BufferSlice>,
+        width: u32,
+        height: u32,
+        sample_count: u32
+    ) -> Result<Vec<u8>, RenderEngineError> where
+        S: Clone + std::fmt::Debug + ShaderSource<'graph> {
+        let device = &self.device;
+        let queue = self.render_queue();
+
+        if width == 0 || height == 0 {
+            return Err(RenderEngineError::EmptyTarget(width, height))
         }
+        check_sample_count(
+            self.adapter.get_texture_format_features(OFFSCREEN_FORMAT),
+            sample_count
+        )?;
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1
+        };
+        let (readback_desc, multisampled_desc) = offscreen_descriptors(size, sample_count);
+        let texture = device.create_texture(&readback_desc);
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let multisampled = multisampled_desc.map(|desc| device.create_texture(&desc));
+        let multisampled_view = multisampled.as_ref()
+            .map(|multisampled| multisampled.create_view(&wgpu::TextureViewDescriptor::default()));
+
+        let ops = wgpu::Operations {
+            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+            store: true
+        };
+        let attachment = match &multisampled_view {
+            Some(multisampled_view) => wgpu::RenderPassColorAttachment {
+                view: multisampled_view,
+                resolve_target: Some(&view),
+                ops
+            },
+            None => wgpu::RenderPassColorAttachment {
+                view: &view,
+                resolve_target: None,
+                ops
+            }
+        };
+        compiled.render(
+            graph,
+            device,
+            shaders,
+            vertex_buffer_layout,
+            colour_target_state,
+            vertex_buffer_attachments,
+            &HashMap::from([(target, attachment)])
+        )?;
+
+        let bytes_per_row = padded_bytes_per_row(width);
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen Readback Buffer"),
+            size: (bytes_per_row * height) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false
+        });
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Offscreen Readback Encoder")
+        });
+        encoder.copy_texture_to_buffer(
+            wgpu::ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All
+            },
+            wgpu::ImageCopyBuffer {
+                buffer: &readback_buffer,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: Some(height)
+                }
+            },
+            size
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let readback_slice = readback_buffer.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        readback_slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).unwrap();
+        });
+        device.poll(wgpu::Maintain::Wait);
+        // A callback dropped without running never mapped the buffer either
+        receiver.recv().map_err(|_| wgpu::BufferAsyncError)??;
+
+        let pixels = unpad_rows(&readback_slice.get_mapped_range(), width, height);
+        readback_buffer.unmap();
+        Ok(pixels)
+    }
+}
+
+/// The first sRGB format in `formats`, otherwise the first format. Surfaces list their
+/// preferred format first
+pub fn surface_format(formats: &[wgpu::TextureFormat]) -> Result<wgpu::TextureFormat, RenderEngineError> {
+    formats.iter()
+        .copied()
+        .find(|f| f.is_srgb())
+        .or(formats.first().copied())
+        .ok_or(RenderEngineError::NoSupportedFormat)
+}
+
+/// Configuration for a `width` by `height` surface, using its preferred present and
+/// alpha modes. Fails if the surface does not support every usage in `options`
+pub fn surface_configuration(
+    surface_caps: &wgpu::SurfaceCapabilities,
+    format: wgpu::TextureFormat,
+    width: u32,
+    height: u32,
+    options: &SurfaceOptions
+) -> Result<wgpu::SurfaceConfiguration, RenderEngineError> {
+    let unsupported = options.usage.difference(surface_caps.usages);
+    if !unsupported.is_empty() {
+        return Err(RenderEngineError::UnsupportedSurfaceUsage(unsupported))
+    }
+
+    Ok(wgpu::SurfaceConfiguration {
+        usage: options.usage,
+        format,
+        width,
+        height,
+        present_mode: surface_caps.present_modes[0],
+        alpha_mode: surface_caps.alpha_modes[0],
+        view_formats: vec![]
+    })
+}
+
+const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+const OFFSCREEN_BYTES_PER_PIXEL: u32 = 4;
+
+/// Texture to buffer copies must have rows aligned to `COPY_BYTES_PER_ROW_ALIGNMENT`
+fn padded_bytes_per_row(width: u32) -> u32 {
+    let unpadded = width * OFFSCREEN_BYTES_PER_PIXEL;
+    let alignment = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+    unpadded.div_ceil(alignment) * alignment
+}
+
+/// Descriptors for an offscreen render: the single sampled texture which is read back,
+/// and the multisampled texture passes draw into when `sample_count` is above 1
+fn offscreen_descriptors(
+    size: wgpu::Extent3d,
+    sample_count: u32
+) -> (wgpu::TextureDescriptor<'static>, Option<wgpu::TextureDescriptor<'static>>) {
+    let readback = wgpu::TextureDescriptor {
+        label: Some("Offscreen Render Target"),
+        size,
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: OFFSCREEN_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[]
+    };
+
+    let multisampled = (sample_count > 1).then_some(wgpu::TextureDescriptor {
+        label: Some("Offscreen Multisampled Target"),
+        sample_count,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+        ..readback
+    });
+
+    (readback, multisampled)
+}
+
+fn check_sample_count(
+    features: wgpu::TextureFormatFeatures,
+    sample_count: u32
+) -> Result<(), RenderEngineError> {
+    if features.flags.sample_count_supported(sample_count) {
+        Ok(())
+    } else {
+        Err(RenderEngineError::UnsupportedSampleCount(sample_count))
     }
 }
 
+fn unpad_rows(padded: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let unpadded_row = (width * OFFSCREEN_BYTES_PER_PIXEL) as usize;
+    padded.chunks(padded_bytes_per_row(width) as usize)
+        .take(height as usize)
+        .flat_map(|row| &row[..unpadded_row])
+        .copied()
+    .collect()
+}
+
 pub struct RenderEngine<'engine> {
     instance: wgpu::Instance,
+    device_state: &'engine DeviceState,
     texture_handler: ResourceManager<texture::Texture, texture::TextureHandler<'engine>>,
-    window: Window
+    window: Window,
+    surface_format: wgpu::TextureFormat
 }
 
 impl RenderEngine<'_> {
-    pub fn new<'engine>(device: &'engine DeviceState) -> RenderEngine<'engine> {
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            dx12_shader_compiler: Default::default()
-        });
+    pub fn new<'engine>(
+        device: &'engine DeviceState,
+        options: &AdapterOptions,
+        surface_options: &SurfaceOptions
+    ) -> Result<RenderEngine<'engine>, RenderEngineError> {
+        let instance = options.create_instance();
 
-        let window = Window::new(&instance);
+        let window = Window::new(&instance)?;
         let surface_caps = window.surface.get_capabilities(&device.adapter);
-        let surface_format = surface_caps.formats.iter()
-            .copied()
-            .find(|f| f.is_srgb())
-            .unwrap_or(surface_caps.formats[0]);
+        let surface_format = surface_format(&surface_caps.formats)?;
 
         let size = window.window.inner_size();
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![]
-        };
+        let config = surface_configuration(
+            &surface_caps,
+            surface_format,
+            size.width,
+            size.height,
+            surface_options
+        )?;
         window.surface.configure(&device.device, &config);
 
         let mut texture_handler = ResourceManager::new::<1024>(
             texture::TextureHandler::new(&device)
         );
 
-        let surface_uuid = texture_handler.handler.set_surface(&window.surface);
+        let surface_uuid = texture_handler.handler.set_surface(&window.surface, surface_format);
         texture_handler.handler.create(&ResourceMetaData {
             uuid: surface_uuid,
             lifetime: ResourceLifetime::Forever,
@@ -86,10 +383,285 @@ impl RenderEngine<'_> {
             path: None
         });
 
-        RenderEngine {
+        Ok(RenderEngine {
             instance,
+            device_state: device,
             texture_handler,
-            window
+            window,
+            surface_format
+        })
+    }
+
+    /// Tears the engine down in an order wgpu accepts:
+    /// 1. every texture is destroyed, including the engine's hold on the surface texture
+    /// 2. the surface is dropped, then the window it was created from
+    /// 3. the instance is dropped
+    ///
+    /// The device is borrowed from a `DeviceState`, so it can only be dropped by the
+    /// caller after this returns
+    pub fn shutdown(self) {
+        let RenderEngine { instance, mut texture_handler, window, .. } = self;
+
+        texture_handler.clear();
+        texture_handler.handler.release_surface();
+        drop(texture_handler);
+
+        window.close();
+        drop(instance);
+    }
+
+    /// Format the window surface was configured with. Colour targets rendering to the
+    /// surface must match it
+    pub fn surface_format(&self) -> wgpu::TextureFormat {
+        self.surface_format
+    }
+
+    /// Renders `graph` into an offscreen RGBA8 texture instead of the window surface and
+    /// reads it back to the CPU. See `DeviceState::render_to_buffer`
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_to_buffer<'graph, S>(
+        &mut self,
+        compiled: &mut CompiledGraph<'graph>,
+        graph: &'graph RenderGraph,
+        target: ResourceHandle,
+        shaders: &HashMap<ShaderHandle, &ShaderBuilder<'graph, S>>,
+        vertex_buffer_layout: &'graph [wgpu::VertexBufferLayout],
+        colour_target_state: &'graph [Option<wgpu::ColorTargetState>],
+        vertex_buffer_attachments: &HashMap<ResourceHandle, wgpu::BufferSlice>,
+        width: u32,
+        height: u32,
+        sample_count: u32
+    ) -> Result<Vec<u8>, RenderEngineError> where
+        S: Clone + std::fmt::Debug + ShaderSource<'graph> {
+        self.device_state.render_to_buffer(
+            compiled,
+            graph,
+            target,
+            shaders,
+            vertex_buffer_layout,
+            colour_target_state,
+            vertex_buffer_attachments,
+            width,
+            height,
+            sample_count
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_graph::pass_builder::{ PassResource, RenderPassBuilder };
+    use crate::render_graph::pipeline_builder::{ MultisampleBuilder, PipelineLayoutBuilder };
+    use crate::render_graph::resource::Resource;
+    use crate::render_graph::shader_builder::{ ShaderRepresentation, WgslBuilder };
+
+    #[test]
+    fn test_render_graph_to_buffer() {
+        let options = AdapterOptions::default();
+        let instance = options.create_instance();
+        let Ok(device_state) = pollster::block_on(DeviceState::new(&instance, None, &options)) else { return };
+
+        let triangle = ShaderBuilder::shader(WgslBuilder::from_buffer(include_str!("triangle.wgsl")));
+        let targets = [Some(wgpu::ColorTargetState {
+            format: OFFSCREEN_FORMAT,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL
+        })];
+        let (width, height) = (8, 8);
+
+        for sample_count in [1, 4] {
+            let mut graph = RenderGraph::new();
+            let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+            let pipeline = graph.add_pipeline(
+                PipelineLayoutBuilder::layout().multisample(MultisampleBuilder::multisample(sample_count)),
+                shader,
+                Some(shader),
+                None
+            );
+            let surface = graph.add_resource(Resource::persistent_with_name("Surface"));
+            graph.add_render_pass(
+                RenderPassBuilder::render_pass(pipeline)
+                    .add_colour_attachment(PassResource::InputAndOutput(surface.handle))
+            );
+            let shaders = HashMap::from([(shader, &triangle)]);
+            let queues: Vec<&Queue> = device_state.queues.iter().collect();
+            let mut compiled = CompiledGraph::new(&queues);
+
+            let pixels = match device_state.render_to_buffer(
+                &mut compiled,
+                &graph,
+                surface.handle,
+                &shaders,
+                &[],
+                &targets,
+                &HashMap::new(),
+                width,
+                height,
+                sample_count
+            ) {
+                Err(RenderEngineError::UnsupportedSampleCount(_)) => continue,
+                result => result.unwrap()
+            };
+
+            assert_eq!(pixels.len(), (width * height * OFFSCREEN_BYTES_PER_PIXEL) as usize);
+
+            let empty = device_state.render_to_buffer(
+                &mut compiled,
+                &graph,
+                surface.handle,
+                &shaders,
+                &[],
+                &targets,
+                &HashMap::new(),
+                0,
+                height,
+                sample_count
+            );
+            assert!(matches!(empty, Err(RenderEngineError::EmptyTarget(0, 8))));
+            let pixel = |x: u32, y: u32| {
+                let offset = ((y * width + x) * OFFSCREEN_BYTES_PER_PIXEL) as usize;
+                &pixels[offset..offset + OFFSCREEN_BYTES_PER_PIXEL as usize]
+            };
+
+            // The triangle covers the centre in its fragment colour and leaves the corners
+            // as cleared
+            assert_eq!(pixel(0, 0), [0, 0, 0, 0]);
+            let expected = [0.3, 0.2, 0.1, 1.0].map(|channel: f32| channel * 255.0);
+            for (channel, expected) in pixel(width / 2, height / 2).iter().zip(expected) {
+                assert!((*channel as f32 - expected).abs() <= 1.0, "{:?}", pixel(width / 2, height / 2));
+            }
+        }
+    }
+
+    #[test]
+    fn test_surface_configuration() {
+        let surface_caps = wgpu::SurfaceCapabilities {
+            formats: vec![wgpu::TextureFormat::Bgra8UnormSrgb],
+            present_modes: vec![wgpu::PresentMode::Fifo],
+            alpha_modes: vec![wgpu::CompositeAlphaMode::Opaque],
+            usages: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC
+        };
+        let options = SurfaceOptions {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC
+        };
+
+        let config = surface_configuration(&surface_caps, wgpu::TextureFormat::Bgra8UnormSrgb, 800, 600, &options).unwrap();
+        assert_eq!(config.usage, options.usage);
+        assert_eq!((config.width, config.height), (800, 600));
+        assert_eq!(config.present_mode, wgpu::PresentMode::Fifo);
+
+        assert_eq!(SurfaceOptions::default().usage, wgpu::TextureUsages::RENDER_ATTACHMENT);
+    }
+
+    #[test]
+    fn test_unsupported_surface_usage() {
+        let surface_caps = wgpu::SurfaceCapabilities {
+            formats: vec![wgpu::TextureFormat::Bgra8UnormSrgb],
+            present_modes: vec![wgpu::PresentMode::Fifo],
+            alpha_modes: vec![wgpu::CompositeAlphaMode::Opaque],
+            usages: wgpu::TextureUsages::RENDER_ATTACHMENT
+        };
+        let options = SurfaceOptions {
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC
+        };
+
+        let config = surface_configuration(&surface_caps, wgpu::TextureFormat::Bgra8UnormSrgb, 800, 600, &options);
+        assert!(matches!(
+            config,
+            Err(RenderEngineError::UnsupportedSurfaceUsage(usage)) if usage == wgpu::TextureUsages::COPY_SRC
+        ));
+    }
+
+    #[test]
+    fn test_no_adapter() {
+        let options = AdapterOptions {
+            backends: wgpu::Backends::empty(),
+            force_fallback_adapter: true,
+            ..Default::default()
+        };
+        let instance = options.create_instance();
+
+        let adapter = pollster::block_on(options.request_adapter(&instance, None));
+        assert!(matches!(adapter, Err(RenderEngineError::NoAdapter)));
+    }
+
+    #[test]
+    fn test_surface_format() {
+        assert_eq!(
+            surface_format(&[wgpu::TextureFormat::Bgra8Unorm, wgpu::TextureFormat::Bgra8UnormSrgb]).unwrap(),
+            wgpu::TextureFormat::Bgra8UnormSrgb
+        );
+        assert_eq!(surface_format(&[wgpu::TextureFormat::Rgba16Float]).unwrap(), wgpu::TextureFormat::Rgba16Float);
+        assert!(matches!(surface_format(&[]), Err(RenderEngineError::NoSupportedFormat)));
+    }
+
+    #[test]
+    fn test_padded_bytes_per_row() {
+        assert_eq!(padded_bytes_per_row(1), 256);
+        assert_eq!(padded_bytes_per_row(64), 256);
+        assert_eq!(padded_bytes_per_row(65), 512);
+    }
+
+    #[test]
+    fn test_unpad_rows() {
+        let width = 3;
+        let height = 2;
+        let padded_row = padded_bytes_per_row(width) as usize;
+
+        let mut padded = vec![0xFF; padded_row * height as usize];
+        for row in 0..height as usize {
+            for byte in 0..(width * OFFSCREEN_BYTES_PER_PIXEL) as usize {
+                padded[row * padded_row + byte] = (row * 100 + byte) as u8;
+            }
         }
+
+        let pixels = unpad_rows(&padded, width, height);
+        assert_eq!(pixels.len(), (width * height * OFFSCREEN_BYTES_PER_PIXEL) as usize);
+        assert_eq!(pixels[0], 0);
+        assert_eq!(pixels[11], 11);
+        assert_eq!(pixels[12], 100);
+        assert!(pixels.iter().all(|byte| *byte != 0xFF));
+    }
+
+    #[test]
+    fn test_multisampled_readback_size() {
+        let size = wgpu::Extent3d {
+            width: 37,
+            height: 21,
+            depth_or_array_layers: 1
+        };
+
+        let (single_readback, single_multisampled) = offscreen_descriptors(size, 1);
+        let (msaa_readback, msaa_multisampled) = offscreen_descriptors(size, 4);
+
+        assert!(single_multisampled.is_none());
+        let msaa_multisampled = msaa_multisampled.unwrap();
+        assert_eq!(msaa_multisampled.sample_count, 4);
+        assert_eq!(msaa_multisampled.size, size);
+
+        assert_eq!(single_readback.sample_count, 1);
+        assert_eq!(msaa_readback.sample_count, 1);
+        assert_eq!(single_readback.size, msaa_readback.size);
+
+        let readback_len = |desc: &wgpu::TextureDescriptor| {
+            padded_bytes_per_row(desc.size.width) * desc.size.height
+        };
+        assert_eq!(readback_len(&single_readback), readback_len(&msaa_readback));
+    }
+
+    #[test]
+    fn test_check_sample_count() {
+        let features = wgpu::TextureFormatFeatures {
+            allowed_usages: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            flags: wgpu::TextureFormatFeatureFlags::MULTISAMPLE_X4
+        };
+
+        assert!(check_sample_count(features, 1).is_ok());
+        assert!(check_sample_count(features, 4).is_ok());
+        assert!(matches!(
+            check_sample_count(features, 8),
+            Err(RenderEngineError::UnsupportedSampleCount(8))
+        ));
     }
 }
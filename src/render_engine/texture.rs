@@ -1,61 +1,242 @@
 use crate::render_engine::DeviceState;
 use crate::resource::{ ResourceHandler, ResourceMetaData };
 use uuid::Uuid;
+use serde::{ Serialize, Deserialize };
 use std::sync::Arc;
 
 struct Surface {
     id: Uuid,
     texture: wgpu::SurfaceTexture,
-    view: wgpu::TextureView
+    view: wgpu::TextureView,
+    format: wgpu::TextureFormat
+}
+
+/// How a texture is filtered and addressed when sampled
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SamplerDesc {
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub address_mode: wgpu::AddressMode
+}
+
+impl SamplerDesc {
+    pub fn nearest() -> SamplerDesc {
+        SamplerDesc {
+            mag_filter: wgpu::FilterMode::Nearest,
+            min_filter: wgpu::FilterMode::Nearest,
+            address_mode: wgpu::AddressMode::ClampToEdge
+        }
+    }
+
+    pub fn linear() -> SamplerDesc {
+        SamplerDesc {
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            address_mode: wgpu::AddressMode::ClampToEdge
+        }
+    }
+
+    pub fn address_mode(mut self, address_mode: wgpu::AddressMode) -> SamplerDesc {
+        self.address_mode = address_mode;
+        self
+    }
+
+    pub fn descriptor(&self) -> wgpu::SamplerDescriptor<'static> {
+        wgpu::SamplerDescriptor {
+            label: Some("Texture Sampler"),
+            address_mode_u: self.address_mode,
+            address_mode_v: self.address_mode,
+            address_mode_w: self.address_mode,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for SamplerDesc {
+    fn default() -> SamplerDesc {
+        SamplerDesc::linear()
+    }
+}
+
+/// The surface's identity, which outlives the texture of any single frame
+struct SurfaceState {
+    id: Uuid,
+    format: wgpu::TextureFormat,
+    frame: Option<Arc<Surface>>
+}
+
+impl SurfaceState {
+    fn new(format: wgpu::TextureFormat) -> SurfaceState {
+        SurfaceState {
+            id: Uuid::new_v4(),
+            format,
+            frame: None
+        }
+    }
+
+    /// Replaces the current frame with the one `next_texture` returns. The identity is
+    /// kept on error so the surface can be reconfigured and acquired again
+    fn acquire(
+        &mut self,
+        next_texture: impl FnOnce() -> Result<wgpu::SurfaceTexture, wgpu::SurfaceError>
+    ) -> Result<(), wgpu::SurfaceError> {
+        // Drop our hold on the old frame before asking for a new one
+        self.frame = None;
+        let texture = next_texture()?;
+        let view = texture.texture.create_view(&wgpu::TextureViewDescriptor::default());
+        self.frame = Some(Arc::new(Surface {
+            id: self.id,
+            texture,
+            view,
+            format: self.format
+        }));
+        Ok(())
+    }
 }
 
 struct Dynamic {
     id: Uuid,
     texture: wgpu::Texture,
-    view: wgpu::TextureView
+    view: wgpu::TextureView,
+    sampler_desc: SamplerDesc,
+    sampler: wgpu::Sampler
 }
 
 pub enum Texture {
     None,
     Surface(Arc<Surface>),
-    Dynamic(Dynamic)
+    Dynamic(Box<Dynamic>)
+}
+
+impl Texture {
+    pub fn view(&self) -> Option<&wgpu::TextureView> {
+        match self {
+            Texture::None => None,
+            Texture::Surface(surface) => Some(&surface.view),
+            Texture::Dynamic(dynamic) => Some(&dynamic.view)
+        }
+    }
+
+    /// Sampler for binding the texture in a bind group. The surface is never sampled
+    pub fn sampler(&self) -> Option<&wgpu::Sampler> {
+        match self {
+            Texture::Dynamic(dynamic) => Some(&dynamic.sampler),
+            _ => None
+        }
+    }
+
+    pub fn sampler_desc(&self) -> Option<SamplerDesc> {
+        match self {
+            Texture::Dynamic(dynamic) => Some(dynamic.sampler_desc),
+            _ => None
+        }
+    }
+}
+
+/// What the caller should do after failing to acquire a surface texture
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SurfaceRecovery {
+    /// The surface no longer matches the window and must be configured again
+    Reconfigure,
+    /// Acquiring took too long; skip this frame and try again next frame
+    SkipFrame,
+    /// The device cannot continue rendering
+    Fatal
+}
+
+impl SurfaceRecovery {
+    pub fn from_error(error: &wgpu::SurfaceError) -> SurfaceRecovery {
+        match error {
+            wgpu::SurfaceError::Lost | wgpu::SurfaceError::Outdated => SurfaceRecovery::Reconfigure,
+            wgpu::SurfaceError::Timeout => SurfaceRecovery::SkipFrame,
+            wgpu::SurfaceError::OutOfMemory => SurfaceRecovery::Fatal
+        }
+    }
 }
 
 pub struct TextureHandler<'manager> {
     device_state: &'manager DeviceState,
-    surface_texture: Option<Arc<Surface>>
+    surface: Option<SurfaceState>
 }
 
 impl<'manager> TextureHandler<'manager> {
     pub fn new(device_state: &'manager DeviceState) -> TextureHandler {
         TextureHandler {
             device_state,
-            surface_texture: None
+            surface: None
         }
     }
 
-    pub fn set_surface(&mut self, surface: &wgpu::Surface) -> Uuid {
-        let surface_texture = surface.get_current_texture().unwrap();
-        let surface_view = surface_texture.texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let id = Uuid::new_v4();
-        self.surface_texture = Some(Arc::new(Surface {
-            id,
-            texture: surface_texture,
-            view: surface_view
-        }));
+    pub fn set_surface(&mut self, surface: &wgpu::Surface, format: wgpu::TextureFormat) -> Uuid {
+        let mut state = SurfaceState::new(format);
+        state.acquire(|| surface.get_current_texture()).unwrap();
+        let id = state.id;
+        self.surface = Some(state);
         id
     }
+
+    /// Replaces the cached surface texture with the surface's next frame. Must be called
+    /// at the start of every frame since presenting consumes the previous texture. On
+    /// error, `SurfaceRecovery::from_error` says how the caller should recover
+    pub fn acquire_frame(&mut self, surface: &wgpu::Surface) -> Result<(), wgpu::SurfaceError> {
+        self.surface.as_mut()
+            .expect("Surface must be set before frames are acquired")
+            .acquire(|| surface.get_current_texture())
+    }
+
+    /// Releases the handler's hold on the current surface texture so the surface can be
+    /// dropped
+    pub fn release_surface(&mut self) {
+        self.surface = None;
+    }
+
+    pub fn surface_format(&self) -> Option<wgpu::TextureFormat> {
+        self.surface.as_ref().map(|surface| surface.format)
+    }
+
+    /// Creates a sampleable render target along with the sampler used to read it. Only
+    /// needs the device, so render graphs can create their own dynamic textures
+    pub fn create_dynamic(
+        device: &wgpu::Device,
+        id: Uuid,
+        size: wgpu::Extent3d,
+        format: wgpu::TextureFormat,
+        sampler_desc: SamplerDesc
+    ) -> Texture {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Dynamic Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[]
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&sampler_desc.descriptor());
+
+        Texture::Dynamic(Box::new(Dynamic {
+            id,
+            texture,
+            view,
+            sampler_desc,
+            sampler
+        }))
+    }
 }
 
 impl ResourceHandler<Texture> for TextureHandler<'_> {
     fn create(&mut self, meta_data: &ResourceMetaData) -> Texture {
-        let is_surface = if let Some(surface) = &self.surface_texture {
+        let is_surface = if let Some(surface) = &self.surface {
             meta_data.uuid == surface.id
         } else {
             false
         };
 
-        Texture::Surface(self.surface_texture.as_ref().unwrap().clone())
+        Texture::Surface(self.surface.as_ref().and_then(|surface| surface.frame.clone()).unwrap())
     }
 
     fn destroy(&mut self, texture: Texture) {
@@ -63,3 +244,89 @@ impl ResourceHandler<Texture> for TextureHandler<'_> {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_engine::AdapterOptions;
+    use crate::resource::ResourceManager;
+    use crate::test_support::headless_device;
+
+    #[test]
+    fn test_surface_recovery() {
+        assert_eq!(SurfaceRecovery::from_error(&wgpu::SurfaceError::Lost), SurfaceRecovery::Reconfigure);
+        assert_eq!(SurfaceRecovery::from_error(&wgpu::SurfaceError::Outdated), SurfaceRecovery::Reconfigure);
+        assert_eq!(SurfaceRecovery::from_error(&wgpu::SurfaceError::Timeout), SurfaceRecovery::SkipFrame);
+        assert_eq!(SurfaceRecovery::from_error(&wgpu::SurfaceError::OutOfMemory), SurfaceRecovery::Fatal);
+    }
+
+    #[test]
+    fn test_acquire_after_lost_frame() {
+        let mut state = SurfaceState::new(wgpu::TextureFormat::Bgra8UnormSrgb);
+        let id = state.id;
+
+        assert_eq!(state.acquire(|| Err(wgpu::SurfaceError::Outdated)), Err(wgpu::SurfaceError::Outdated));
+        assert!(state.frame.is_none());
+
+        // After reconfiguring, the next acquire still knows which surface it belongs to
+        assert_eq!(state.acquire(|| Err(wgpu::SurfaceError::Lost)), Err(wgpu::SurfaceError::Lost));
+        assert_eq!(state.id, id);
+        assert_eq!(state.format, wgpu::TextureFormat::Bgra8UnormSrgb);
+    }
+
+    #[test]
+    fn test_release_surface() {
+        let options = AdapterOptions::default();
+        let instance = options.create_instance();
+        let Ok(device_state) = pollster::block_on(DeviceState::new(&instance, None, &options)) else { return };
+
+        // The texture half of `RenderEngine::shutdown`, which must leave nothing holding
+        // the surface before it is dropped
+        let mut texture_handler = ResourceManager::new::<4>(TextureHandler::new(&device_state));
+        texture_handler.handler.surface = Some(SurfaceState::new(wgpu::TextureFormat::Bgra8UnormSrgb));
+        assert_eq!(texture_handler.handler.surface_format(), Some(wgpu::TextureFormat::Bgra8UnormSrgb));
+
+        texture_handler.clear();
+        texture_handler.handler.release_surface();
+        assert!(texture_handler.handler.surface.is_none());
+        assert_eq!(texture_handler.handler.surface_format(), None);
+        assert_eq!(texture_handler.iter().count(), 0);
+    }
+
+    #[test]
+    fn test_create_dynamic() {
+        let Some((device, _)) = headless_device() else { return };
+        let size = wgpu::Extent3d {
+            width: 32,
+            height: 16,
+            depth_or_array_layers: 1
+        };
+
+        let texture = TextureHandler::create_dynamic(
+            &device,
+            Uuid::new_v4(),
+            size,
+            wgpu::TextureFormat::Rgba8Unorm,
+            SamplerDesc::nearest()
+        );
+        assert!(texture.view().is_some());
+        assert!(texture.sampler().is_some());
+        assert_eq!(texture.sampler_desc(), Some(SamplerDesc::nearest()));
+        assert!(Texture::None.sampler().is_none());
+    }
+
+    #[test]
+    fn test_sampler_descriptors() {
+        let nearest = SamplerDesc::nearest().descriptor();
+        assert_eq!(nearest.mag_filter, wgpu::FilterMode::Nearest);
+        assert_eq!(nearest.min_filter, wgpu::FilterMode::Nearest);
+        assert_eq!(nearest.address_mode_u, wgpu::AddressMode::ClampToEdge);
+
+        let linear = SamplerDesc::linear()
+            .address_mode(wgpu::AddressMode::Repeat)
+            .descriptor();
+        assert_eq!(linear.mag_filter, wgpu::FilterMode::Linear);
+        assert_eq!(linear.min_filter, wgpu::FilterMode::Linear);
+        assert_eq!(linear.address_mode_u, wgpu::AddressMode::Repeat);
+        assert_eq!(linear.address_mode_v, wgpu::AddressMode::Repeat);
+    }
+}
@@ -2,6 +2,7 @@ use winit::{
     event_loop::EventLoop,
     window::{ self, WindowBuilder }
 };
+use crate::render_engine::RenderEngineError;
 
 pub struct Window {
     size: winit::dpi::PhysicalSize<u32>,
@@ -11,9 +12,9 @@ pub struct Window {
 }
 
 impl Window {
-    pub fn new(instance: &wgpu::Instance) -> Window {
+    pub fn new(instance: &wgpu::Instance) -> Result<Window, RenderEngineError> {
         let event_loop = Some(EventLoop::new());
-        let window = WindowBuilder::new().build(&event_loop.as_ref().unwrap()).unwrap();
+        let window = WindowBuilder::new().build(event_loop.as_ref().unwrap())?;
         let size = window.inner_size();
 
         /* # Safety
@@ -21,13 +22,22 @@ impl Window {
          * The surface only needs to live as long as the window, and the window lasts as 
          * long as the surface so this will remain valid
          */
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
+        let surface = unsafe { instance.create_surface(&window) }?;
 
-        Window {
+        Ok(Window {
             size,
             event_loop,
             window,
             surface
-        }
+        })
+    }
+
+    /// Drops the surface before the window it was created from. Field order alone would
+    /// drop the window first
+    pub fn close(self) {
+        let Window { surface, window, event_loop, .. } = self;
+        drop(surface);
+        drop(window);
+        drop(event_loop);
     }
 }
@@ -4,17 +4,20 @@ pub mod shader_builder;
 pub mod pass_builder;
 pub mod pipeline_builder;
 pub mod handle_map;
+pub mod description;
 
-pub use compiled_graph::CompiledGraph;
+pub use compiled_graph::{ CompiledGraph, CompileError };
 
 use uuid::Uuid;
 use petgraph::graph::{ NodeIndex, Graph };
 use thiserror::Error;
+use serde::{ Serialize, Deserialize };
+use std::any::Any;
 use std::collections::HashMap;
 
-use pass_builder::{ PassHandle, RenderPassBuilder };
+use pass_builder::{ PassHandle, PassResource, RenderPassBuilder };
 use pipeline_builder::{ PipelineHandle, PipelineLayoutBuilder };
-use resource::{ ResourceHandle, Resource };
+use resource::{ ResourceHandle, Resource, DynamicResourceDesc };
 use shader_builder::{ ShaderHandle, ShaderRepresentation };
 use handle_map::{ HandleType, HandleMap, Handle };
 
@@ -24,6 +27,12 @@ enum Vertex {
     Blue(PassHandle)
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum NodeKind {
+    Resource,
+    Pass
+}
+
 #[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
 pub struct VertexHandle {
     node_index: NodeIndex,
@@ -44,7 +53,11 @@ pub enum RenderGraphResult {
     #[error("Resource was not created as a vertex")]
     ResourceDoesNotExist,
     #[error("Pass was not created as a vertex")]
-    PassDoesNotExist
+    PassDoesNotExist,
+    #[error("Resource is not dynamic")]
+    ResourceNotDynamic,
+    #[error("Graph contains a cycle")]
+    CyclicGraph
 }
 
 struct RenderGraphMeta {
@@ -74,7 +87,14 @@ impl RenderGraphMeta {
 struct PipelineInfo<'info> {
     builder: PipelineLayoutBuilder<'info>,
     vertex_shader: ResourceHandle,
-    fragment_shader: Option<ResourceHandle>
+    fragment_shader: Option<ResourceHandle>,
+    vertex_entry: &'info str,
+    fragment_entry: &'info str
+}
+
+impl PipelineInfo<'_> {
+    const VERTEX_SHADER_ENTRY: &'static str = "vs_main";
+    const FRAGMENT_SHADER_ENTRY: &'static str = "fs_main";
 }
 
 pub struct RenderGraph<'graph> {
@@ -84,6 +104,8 @@ pub struct RenderGraph<'graph> {
     resources: HandleMap<ResourceHandle, Resource<'graph>>,
     graph: RenderGraphMeta,
     vertex_handle_map: HashMap<Handle, VertexHandle>,
+    dynamic_resource_descs: HashMap<ResourceHandle, DynamicResourceDesc>,
+    resource_userdata: HashMap<ResourceHandle, Box<dyn Any>>,
 }
 
 impl<'graph> RenderGraph<'graph> {
@@ -95,6 +117,8 @@ impl<'graph> RenderGraph<'graph> {
             resources: HandleMap::new(),
             graph: RenderGraphMeta::new(),
             vertex_handle_map: HashMap::new(),
+            dynamic_resource_descs: HashMap::new(),
+            resource_userdata: HashMap::new(),
         }
     }
 
@@ -111,27 +135,48 @@ impl<'graph> RenderGraph<'graph> {
         self.pipelines.add(PipelineInfo {
                 builder: layout,
                 vertex_shader,
-                fragment_shader
+                fragment_shader,
+                vertex_entry: PipelineInfo::VERTEX_SHADER_ENTRY,
+                fragment_entry: PipelineInfo::FRAGMENT_SHADER_ENTRY
             }, id.map(|id| id.to_string())
         )
     }
 
-    pub fn add_render_pass(&mut self, pass: RenderPassBuilder<'graph>) -> (VertexHandle, Vec<VertexHandle>) {
-        let pass_handle = self.passes.add(pass.clone(), pass.label.map(|l| l.to_string()));
+    /// Adds a pipeline whose vertex and fragment stages both come from `shader`,
+    /// using the given entry points
+    pub fn add_pipeline_single_module(&mut self,
+                                      layout: PipelineLayoutBuilder<'graph>,
+                                      shader: ShaderHandle,
+                                      vertex_entry: &'graph str,
+                                      fragment_entry: &'graph str
+    ) -> PipelineHandle {
+        self.pipelines.add(PipelineInfo {
+                builder: layout,
+                vertex_shader: shader,
+                fragment_shader: Some(shader),
+                vertex_entry,
+                fragment_entry
+            }, None
+        )
+    }
+
+    pub fn add_render_pass(&mut self, mut pass: RenderPassBuilder<'graph>) -> (VertexHandle, Vec<VertexHandle>) {
+        let pass_handle = PassHandle::new();
         let pass_node = self.graph.add_node(Vertex::Blue(pass_handle));
 
         let resource_iter = pass.colour_attachments.iter()
             .chain(pass.depth_stencil.iter())
             .chain(pass.vertex_buffer.iter())
-            .chain(pass.index_buffer.iter());
+            .chain(pass.index_buffer.iter())
+            .chain(pass.instance_buffer.iter().map(|(instance_buffer, _)| instance_buffer))
+            .chain(pass.sampled_textures.iter());
 
         // Get all output resources from this pass builder
         // First, create any new resources we need
-        let new_outputs: Vec<Resource> = resource_iter.clone()
+        let new_outputs: Vec<(Resource, Option<&str>)> = resource_iter.clone()
             .filter(|a| a.is_output())
             .filter(|a| a.is_new_resource())
-            .map(|_| Resource::Dynamic(Uuid::new_v4()))
-            .inspect(|resource| { self.resources.add(*resource, None); })
+            .map(|a| (Resource::Dynamic(Uuid::new_v4()), a.new_resource_name()))
             .collect();
 
         // Get existing nodes from these resources
@@ -146,11 +191,10 @@ impl<'graph> RenderGraph<'graph> {
         let mut outputs: Vec<VertexHandle> = existing_outputs.iter()
             .map(|resource| self.add_resource(*resource))
             .collect();
-        outputs.append(
-            &mut new_outputs.iter()
-                .map(|resource| self.add_resource(*resource))
-                .collect()
-        );
+        let new_output_vertices: Vec<VertexHandle> = new_outputs.iter()
+            .map(|(resource, name)| self.add_named_resource(*resource, *name))
+            .collect();
+        outputs.extend(new_output_vertices.iter().copied());
 
         for vertex_handle in outputs.iter() {
             self.graph.add_edge(pass_node, vertex_handle.node_index);
@@ -163,20 +207,51 @@ impl<'graph> RenderGraph<'graph> {
             .for_each(|vertex_handle| { self.graph.add_edge(vertex_handle.node_index, pass_node); });
 
         new_outputs.iter()
-            .map(|resource_handle| self.add_resource(resource_handle.into_persistent()))
+            .map(|(resource_handle, _)| self.add_resource(resource_handle.into_persistent()))
             .collect::<Vec<VertexHandle>>()
             .iter()
             .for_each(|vertex_handle| { self.graph.add_edge(vertex_handle.node_index, pass_node); });
 
+        // Store the pass writing to the resources just created, so it can be compiled
+        let new_resources = pass.resources_mut().filter(|resource| resource.is_new_resource());
+        for (resource, vertex_handle) in new_resources.zip(&new_output_vertices) {
+            *resource = PassResource::OnlyOutput(Some(vertex_handle.handle), resource.new_resource_name());
+        }
+        let label = pass.label.map(|l| l.to_string());
+        self.passes.insert(pass_handle, pass, label);
+
         let pass_vertex_handle = VertexHandle::new_from_node(pass_node, pass_handle);
         self.vertex_handle_map.insert(pass_handle, pass_vertex_handle);
         (pass_vertex_handle, outputs)
     }
 
+    /// Adds a pass which clears `target` to `colour` without binding a pipeline or drawing
+    pub fn add_clear_pass(&mut self, target: PassResource<'graph>, colour: wgpu::Color) -> (VertexHandle, Vec<VertexHandle>) {
+        self.add_render_pass(RenderPassBuilder::clear_pass(target, colour))
+    }
+
+    /// Adds a pipeline using `shader` for both stages and a pass drawing a single
+    /// fullscreen triangle with it into `target`, as for post-processing or blitting
+    pub fn fullscreen_pass(&mut self, shader: ShaderHandle, target: VertexHandle) -> VertexHandle {
+        let pipeline = self.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+        let (pass, _) = self.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .label("Fullscreen Pass")
+                .add_colour_attachment(PassResource::OnlyOutput(Some(target.handle), None))
+        );
+        pass
+    }
+
     pub fn add_resource(&mut self, resource: Resource<'graph>) -> VertexHandle {
+        self.add_named_resource(resource, None)
+    }
+
+    /// Adds a resource. Dynamic resources are given `name` in the string map, persistent
+    /// resources always use their own name
+    fn add_named_resource(&mut self, resource: Resource<'graph>, name: Option<&str>) -> VertexHandle {
         let resource_handle = match resource {
             Resource::Persistent(id) => self.resources.add(resource, id.string_id.map(|s| s.to_string())),
-            Resource::Dynamic(_) => self.resources.add(resource, None)
+            Resource::Dynamic(_) => self.resources.add(resource, name.map(|s| s.to_string()))
         };
 
         let resource_node = self.graph.add_node(Vertex::Red(resource_handle));
@@ -185,6 +260,131 @@ impl<'graph> RenderGraph<'graph> {
         resource_vertex_handle
     }
 
+    pub fn shader_count(&self) -> usize {
+        self.shaders.len()
+    }
+
+    pub fn pipeline_count(&self) -> usize {
+        self.pipelines.len()
+    }
+
+    pub fn pass_count(&self) -> usize {
+        self.passes.len()
+    }
+
+    pub fn resource_count(&self) -> usize {
+        self.resources.len()
+    }
+
+    pub fn vertex_for(&self, handle: Handle) -> Option<VertexHandle> {
+        self.vertex_handle_map.get(&handle).copied()
+    }
+
+    pub fn node_kind(&self, vertex: VertexHandle) -> Option<NodeKind> {
+        self.graph.forward_graph.node_weight(vertex.node_index).map(|v| match v {
+            Vertex::Red(_) => NodeKind::Resource,
+            Vertex::Blue(_) => NodeKind::Pass
+        })
+    }
+
+    pub fn set_dynamic_resource_desc(&mut self, handle: ResourceHandle, desc: DynamicResourceDesc) -> Result<(), RenderGraphResult> {
+        match self.resources.get_from_handle(&handle) {
+            Some(Resource::Dynamic(_)) => {
+                self.dynamic_resource_descs.insert(handle, desc);
+                Ok(())
+            },
+            Some(Resource::Persistent(_)) => Err(RenderGraphResult::ResourceNotDynamic),
+            None => Err(RenderGraphResult::ResourceDoesNotExist)
+        }
+    }
+
+    pub fn dynamic_resource_desc(&self, handle: ResourceHandle) -> Option<&DynamicResourceDesc> {
+        self.dynamic_resource_descs.get(&handle)
+    }
+
+    /// Attach tooling metadata to a resource, replacing anything attached before. The
+    /// graph never reads it
+    pub fn set_resource_userdata(&mut self, handle: ResourceHandle, data: Box<dyn Any>) -> Result<(), RenderGraphResult> {
+        if self.resources.get_from_handle(&handle).is_none() {
+            return Err(RenderGraphResult::ResourceDoesNotExist)
+        }
+        self.resource_userdata.insert(handle, data);
+        Ok(())
+    }
+
+    /// Metadata attached to a resource, if there is some and it is a `T`
+    pub fn get_resource_userdata<T: Any>(&self, handle: ResourceHandle) -> Option<&T> {
+        self.resource_userdata.get(&handle).and_then(|data| data.downcast_ref())
+    }
+
+    /// Pass nodes in the order they will be executed
+    fn ordered_pass_nodes(&self) -> Result<Vec<NodeIndex>, RenderGraphResult> {
+        let execution_order = petgraph::algo::toposort(&self.graph.forward_graph, None)
+            .map_err(|_| RenderGraphResult::CyclicGraph)?;
+
+        Ok(execution_order.into_iter()
+            .filter(|node| matches!(self.graph.forward_graph[*node], Vertex::Blue(_)))
+            .collect())
+    }
+
+    /// Passes in the order they will be executed
+    pub fn execution_order(&self) -> Result<Vec<PassHandle>, RenderGraphResult> {
+        Ok(self.ordered_pass_nodes()?.into_iter()
+            .filter_map(|node| match self.graph.forward_graph[node] {
+                Vertex::Blue(pass) => Some(pass),
+                Vertex::Red(_) => None
+            })
+            .collect())
+    }
+
+    /// For every dynamic resource, the index of the first and last pass (in execution
+    /// order) that reads or writes it. Resources whose spans do not overlap can alias
+    /// the same allocation
+    pub fn resource_lifetimes(&self) -> Result<HashMap<ResourceHandle, (usize, usize)>, RenderGraphResult> {
+        let pass_indices: HashMap<NodeIndex, usize> = self.ordered_pass_nodes()?.into_iter()
+            .enumerate()
+            .map(|(index, node)| (node, index))
+            .collect();
+
+        let mut lifetimes = HashMap::new();
+        for node in self.graph.forward_graph.node_indices() {
+            let Vertex::Red(resource_handle) = self.graph.forward_graph[node] else {
+                continue
+            };
+            let Some(Resource::Dynamic(_)) = self.resources.get_from_handle(&resource_handle) else {
+                continue
+            };
+
+            let touching_passes = self.graph.forward_graph.neighbors_undirected(node)
+                .filter_map(|neighbour| pass_indices.get(&neighbour))
+                .copied();
+
+            let span = touching_passes.fold(None, |span: Option<(usize, usize)>, index| {
+                Some(span.map_or((index, index), |(first, last)| (first.min(index), last.max(index))))
+            });
+
+            if let Some(span) = span {
+                lifetimes.insert(resource_handle, span);
+            }
+        }
+
+        Ok(lifetimes)
+    }
+
+    /// Resources that no pass reads or writes, in the order they were added
+    pub fn orphan_resources(&self) -> Vec<VertexHandle> {
+        let is_unconnected = |graph: &Graph<Vertex, ()>, node| graph.neighbors_undirected(node).next().is_none();
+
+        self.graph.forward_graph.node_indices()
+            .filter_map(|node| match self.graph.forward_graph[node] {
+                Vertex::Red(resource_handle) => Some(VertexHandle::new_from_node(node, resource_handle)),
+                Vertex::Blue(_) => None
+            })
+            .filter(|vertex| is_unconnected(&self.graph.forward_graph, vertex.node_index))
+            .filter(|vertex| is_unconnected(&self.graph.reverse_graph, vertex.node_index))
+            .collect()
+    }
+
     pub fn string_graph(&self) -> Graph<String, String> {
         let get_resource_display = |handle| {
             let resource = self.resources.get_from_handle(handle).unwrap();
@@ -212,3 +412,257 @@ impl<'graph> RenderGraph<'graph> {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resource_lifetimes() {
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let pipeline = graph.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+
+        let (_, first_outputs) = graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .add_colour_attachment(PassResource::new_output())
+        );
+        let first_temp = first_outputs[0].handle;
+
+        let (_, second_outputs) = graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .add_colour_attachment(PassResource::OnlyInput(first_temp))
+                .add_colour_attachment(PassResource::new_output())
+        );
+        let second_temp = second_outputs[0].handle;
+
+        graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .add_colour_attachment(PassResource::OnlyInput(second_temp))
+        );
+
+        let lifetimes = graph.resource_lifetimes().unwrap();
+        assert_eq!(lifetimes.get(&first_temp), Some(&(0, 1)));
+        assert_eq!(lifetimes.get(&second_temp), Some(&(1, 2)));
+    }
+
+    #[test]
+    fn test_vertex_lookup() {
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let pipeline = graph.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+        let surface = graph.add_resource(Resource::persistent_with_name("Surface"));
+
+        let (pass, _) = graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .add_colour_attachment(PassResource::OnlyInput(surface.handle))
+        );
+
+        assert_eq!(graph.vertex_for(pass.handle), Some(pass));
+        assert_eq!(graph.vertex_for(surface.handle), Some(surface));
+        assert_eq!(graph.vertex_for(pipeline), None);
+
+        assert_eq!(graph.node_kind(pass), Some(NodeKind::Pass));
+        assert_eq!(graph.node_kind(surface), Some(NodeKind::Resource));
+    }
+
+    #[test]
+    fn test_dynamic_resource_desc() {
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let pipeline = graph.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+        let persistent = graph.add_resource(Resource::persistent_with_name("Surface"));
+
+        let (_, outputs) = graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .add_colour_attachment(PassResource::new_output())
+        );
+
+        let desc = DynamicResourceDesc {
+            width: 640,
+            height: 480,
+            format: wgpu::TextureFormat::Rgba8Unorm
+        };
+        graph.set_dynamic_resource_desc(outputs[0].handle, desc).unwrap();
+        assert_eq!(graph.dynamic_resource_desc(outputs[0].handle), Some(&desc));
+
+        assert!(matches!(
+            graph.set_dynamic_resource_desc(persistent.handle, desc),
+            Err(RenderGraphResult::ResourceNotDynamic)
+        ));
+    }
+
+    #[test]
+    fn test_resource_userdata() {
+        #[derive(Debug, PartialEq)]
+        struct DebugInfo {
+            category: &'static str,
+            expected_size: u64
+        }
+
+        let mut graph = RenderGraph::new();
+        let surface = graph.add_resource(Resource::persistent_with_name("Surface"));
+        let info = DebugInfo { category: "Swapchain", expected_size: 640 * 480 * 4 };
+
+        assert_eq!(graph.get_resource_userdata::<DebugInfo>(surface.handle), None);
+        graph.set_resource_userdata(surface.handle, Box::new(info)).unwrap();
+        assert_eq!(
+            graph.get_resource_userdata::<DebugInfo>(surface.handle),
+            Some(&DebugInfo { category: "Swapchain", expected_size: 640 * 480 * 4 })
+        );
+        assert_eq!(graph.get_resource_userdata::<u32>(surface.handle), None);
+
+        assert!(matches!(
+            graph.set_resource_userdata(Handle::new(), Box::new(0u32)),
+            Err(RenderGraphResult::ResourceDoesNotExist)
+        ));
+    }
+
+    #[test]
+    fn test_named_dynamic_resource() {
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let pipeline = graph.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+
+        let (_, outputs) = graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .add_colour_attachment(PassResource::named_output("BlurTemp"))
+                .add_colour_attachment(PassResource::new_output())
+        );
+
+        assert_eq!(graph.resources.get_string_from_handle(&outputs[0].handle), Some("BlurTemp".to_string()));
+        assert_eq!(graph.resources.get_string_from_handle(&outputs[1].handle), None);
+
+        let string_graph = graph.string_graph();
+        assert_eq!(string_graph.node_weights().filter(|name| *name == "BlurTemp").count(), 1);
+    }
+
+    #[test]
+    fn test_clear_pass() {
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let pipeline = graph.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+
+        let (clear, outputs) = graph.add_clear_pass(PassResource::new_output(), wgpu::Color::BLACK);
+        let (draw, _) = graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .add_colour_attachment(PassResource::InputAndOutput(outputs[0].handle))
+        );
+
+        let clear_pass = graph.passes.get_from_handle(&clear.handle).unwrap();
+        assert!(clear_pass.is_clear_pass());
+        assert_eq!(clear_pass.pipeline, None);
+        assert_eq!(
+            clear_pass.colour_operations(outputs[0].handle, wgpu::Operations { load: wgpu::LoadOp::Load, store: true }).load,
+            wgpu::LoadOp::Clear(wgpu::Color::BLACK)
+        );
+
+        assert_eq!(graph.execution_order().unwrap(), vec![clear.handle, draw.handle]);
+    }
+
+    #[test]
+    fn test_counts() {
+        let mut graph = RenderGraph::new();
+        assert_eq!(
+            (graph.shader_count(), graph.pipeline_count(), graph.pass_count(), graph.resource_count()),
+            (0, 0, 0, 0)
+        );
+
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let pipeline = graph.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+        let surface = graph.add_resource(Resource::persistent_with_name("Surface"));
+
+        let (_, outputs) = graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .add_colour_attachment(PassResource::new_output())
+        );
+        graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .add_colour_attachment(PassResource::OnlyInput(outputs[0].handle))
+                .add_colour_attachment(PassResource::InputAndOutput(surface.handle))
+        );
+
+        assert_eq!(graph.shader_count(), 1);
+        assert_eq!(graph.pipeline_count(), 1);
+        assert_eq!(graph.pass_count(), 2);
+        // Every output of a pass is given its own resource vertex
+        assert_eq!(graph.resource_count(), 4);
+    }
+
+    #[test]
+    fn test_fullscreen_pass() {
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let surface = graph.add_resource(Resource::persistent_with_name("Surface"));
+
+        let pass = graph.fullscreen_pass(shader, surface);
+
+        assert_eq!(graph.pass_count(), 1);
+        assert_eq!(graph.pipeline_count(), 1);
+        assert_eq!(graph.node_kind(pass), Some(NodeKind::Pass));
+        assert!(graph.graph.forward_graph.contains_edge(surface.node_index, pass.node_index));
+
+        let builder = graph.passes.get_from_handle(&pass.handle).unwrap();
+        assert_eq!(builder.colour_attachments, vec![PassResource::OnlyOutput(Some(surface.handle), None)]);
+        assert_eq!(builder.draw_ranges(), (0..3, 0..1));
+    }
+
+    #[test]
+    fn test_pipeline_single_module() {
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), Some("sky"));
+        let single = graph.add_pipeline_single_module(PipelineLayoutBuilder::layout(), shader, "sky_vs", "sky_fs");
+        let default = graph.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+
+        let single = graph.pipelines.get_from_handle(&single).unwrap();
+        assert_eq!((single.vertex_shader, single.fragment_shader), (shader, Some(shader)));
+        assert_eq!((single.vertex_entry, single.fragment_entry), ("sky_vs", "sky_fs"));
+
+        let default = graph.pipelines.get_from_handle(&default).unwrap();
+        assert_eq!((default.vertex_entry, default.fragment_entry), ("vs_main", "fs_main"));
+        assert_eq!(graph.shader_count(), 1);
+    }
+
+    #[test]
+    fn test_orphan_resources() {
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let pipeline = graph.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+        let surface = graph.add_resource(Resource::persistent_with_name("Surface"));
+        let unused = graph.add_resource(Resource::persistent_with_name("Unused"));
+        assert_eq!(graph.orphan_resources(), vec![surface, unused]);
+
+        graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .add_colour_attachment(PassResource::InputAndOutput(surface.handle))
+        );
+
+        assert_eq!(graph.orphan_resources(), vec![unused]);
+    }
+
+    #[test]
+    fn test_with_pipeline() {
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let pipeline = graph.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+        let debug_pipeline = graph.add_pipeline_single_module(PipelineLayoutBuilder::layout(), shader, "vs_debug", "fs_debug");
+        let surface = graph.add_resource(Resource::persistent_with_name("Surface"));
+
+        let pass = RenderPassBuilder::render_pass(pipeline)
+            .label("Scene Pass")
+            .add_colour_attachment(PassResource::InputAndOutput(surface.handle))
+            .scissor(0, 0, 64, 64)
+            .instances(4);
+        let debug_pass = pass.clone().with_pipeline(debug_pipeline);
+
+        assert_eq!(pass.pipeline, Some(pipeline));
+        assert_eq!(debug_pass.pipeline, Some(debug_pipeline));
+        assert_eq!(debug_pass.label, pass.label);
+        assert_eq!(debug_pass.colour_attachments, pass.colour_attachments);
+        assert_eq!(debug_pass.scissor, pass.scissor);
+        assert_eq!(debug_pass.draw_ranges(), pass.draw_ranges());
+
+        let clear = RenderPassBuilder::clear_pass(PassResource::new_output(), wgpu::Color::BLACK);
+        assert!(!clear.with_pipeline(pipeline).is_clear_pass());
+    }
+}
@@ -1,21 +1,47 @@
-use std::collections::HashMap;
+use std::collections::{ HashMap, HashSet };
+use std::time::Duration;
 use wgpu::{
     PipelineLayout,
     RenderPass,
     RenderPipeline,
-    ShaderModule,
     CommandEncoder,
     CommandBuffer
 };
 use uuid::Uuid;
+use thiserror::Error;
 use crate::render_graph::{
-    shader_builder::{ ShaderBuilder, ShaderSource, ShaderHandle },
-    pass_builder::RenderPassBuilder,
-    resource::ResourceHandle,
+    shader_builder::{ ShaderBuilder, ShaderCache, ShaderSource, ShaderHandle },
+    pass_builder::{ RenderPassBuilder, PassHandle, ScissorRect },
+    pipeline_builder::{ BindGroupLayoutBuilder, VisibilityBuilder },
+    resource::{ ResourceHandle, DynamicResourceDesc },
     handle_map::HandleType,
-    Vertex, PipelineInfo
+    Vertex, PipelineInfo, RenderGraph
 };
 use crate::render;
+use crate::render_engine::texture::{ SamplerDesc, Texture, TextureHandler };
+
+#[derive(Debug, Error, PartialEq)]
+pub enum CompileError {
+    #[error("Pass has {attachments} colour attachments but its pipeline has {targets} colour targets")]
+    ColourTargetMismatch {
+        attachments: usize,
+        targets: usize
+    },
+    #[error("No colour attachment was supplied for a pass resource")]
+    MissingColourAttachment(ResourceHandle),
+    #[error("No vertex buffer was supplied for a pass instance buffer")]
+    MissingInstanceBuffer(ResourceHandle),
+    #[error("Sampled texture is not a dynamic resource with a description")]
+    MissingSampledTexture(ResourceHandle),
+    #[error("Timing was requested but the device lacks Features::TIMESTAMP_QUERY")]
+    TimestampQueryUnsupported,
+    #[error("Graph contains a cycle, so its passes have no execution order")]
+    CyclicGraph,
+    #[error("Pass reads a resource it is also drawing to, or draws to it twice")]
+    ReadWriteHazard(ResourceHandle),
+    #[error("Passes sampling the same texture ask for different samplers")]
+    SamplerMismatch(ResourceHandle)
+}
 
 pub struct ResourcePair<T> {
     id: Uuid,
@@ -30,22 +56,160 @@ impl<T> ResourcePair<T> {
     }
 }
 
+/// Identifies a compiled pipeline. Passes that share a pipeline handle but override
+/// its fixed-function state need their own wgpu pipeline
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct PipelineKey {
+    pipeline: Uuid,
+    cull_mode: Option<wgpu::Face>,
+    /// The graph adds a bind group to the layout when the pass samples textures
+    sampled_textures: usize
+}
+
+impl PipelineKey {
+    pub fn from_pass(pass: &RenderPassBuilder) -> PipelineKey {
+        PipelineKey {
+            pipeline: pass.pipeline.expect("Clear passes have no pipeline").uuid(),
+            cull_mode: pass.cull_mode.map_or(
+                CompiledGraph::PRIMITIVE_STATE.cull_mode,
+                |cull_mode| cull_mode.face()
+            ),
+            sampled_textures: pass.sampled_textures.len()
+        }
+    }
+}
+
 pub struct ShaderData<'shader, I, S: Clone + std::fmt::Debug + ShaderSource<'shader>> {
     pub module_builder: ResourcePair<ShaderBuilder<'shader, S>>,
     pub inputs: &'shader [I]
 }
 
+/// Pipeline and shader cache activity accumulated across every `render` of a
+/// `CompiledGraph`. Pipelines being created every frame means the graph is thrashing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompileStats {
+    pub pipelines_created: usize,
+    pub pipelines_reused: usize,
+    pub shaders_compiled: usize,
+    pub shaders_reused: usize
+}
+
+/// Texture the graph owns for a dynamic resource, recreated when its description changes
+struct DynamicTexture {
+    desc: DynamicResourceDesc,
+    texture: Texture
+}
+
+/// Sampler for the texture backing `handle`, taken from the passes which sample it. A
+/// texture only has one sampler, so those passes must agree
+fn sampler_for(graph: &RenderGraph, handle: ResourceHandle) -> Result<SamplerDesc, CompileError> {
+    let mut samplers = graph.passes.iter()
+        .map(|(_, pass)| pass)
+        .filter(|pass| pass.sampled_textures.iter().any(|texture| texture.resource_handle() == Some(handle)))
+        .map(|pass| pass.sampler);
+
+    let sampler = samplers.next().unwrap_or_default();
+    if samplers.any(|other| other != sampler) {
+        return Err(CompileError::SamplerMismatch(handle))
+    }
+    Ok(sampler)
+}
+
+/// Layout of the bind group holding the `count` textures a pass samples
+fn sampled_texture_layout(count: usize) -> render::BindingGroupLayout<'static> {
+    (0..count).fold(
+        BindGroupLayoutBuilder::binding().label("Sampled Graph Textures"),
+        |builder, _| builder.add_sampled_texture(VisibilityBuilder::visibility().fragment())
+    ).build()
+}
+
+/// Texture and sampler bindings of the `index`th sampled texture of a pass
+fn sampled_texture_bindings(index: usize) -> (u32, u32) {
+    let texture_binding = 2 * index as u32;
+    (texture_binding, texture_binding + 1)
+}
+
+/// Rejects passes which would read a texture while drawing to it. An `InputAndOutput`
+/// colour attachment is only read through its load op, which is safe, but the pass
+/// must not also sample it or attach it more than once. Nothing is copied to break the
+/// hazard; the pass should sample a copy written by an earlier pass instead
+fn check_read_write_hazards(pass: &RenderPassBuilder) -> Result<(), CompileError> {
+    let mut written = HashSet::new();
+    for attachment in pass.colour_attachments.iter().chain(pass.depth_stencil.iter()) {
+        if let Some(resource_handle) = attachment.resource_handle() {
+            if !written.insert(resource_handle) {
+                return Err(CompileError::ReadWriteHazard(resource_handle))
+            }
+        }
+    }
+
+    match pass.sampled_textures.iter().find_map(|texture| texture.resource_handle().filter(|handle| written.contains(handle))) {
+        Some(resource_handle) => Err(CompileError::ReadWriteHazard(resource_handle)),
+        None => Ok(())
+    }
+}
+
+/// Calls `set_scissor_rect` with the pass's scissor, if it has one
+fn apply_scissor(scissor: Option<ScissorRect>, set_scissor_rect: impl FnOnce(u32, u32, u32, u32)) {
+    if let Some(ScissorRect { x, y, width, height }) = scissor {
+        set_scissor_rect(x, y, width, height);
+    }
+}
+
+/// Timestamp queries written before and after every pass of a frame
+struct PassTiming {
+    query_set: wgpu::QuerySet,
+    resolve_buffer: wgpu::Buffer,
+    readback_buffer: wgpu::Buffer,
+    passes: Vec<PassHandle>
+}
+
+impl PassTiming {
+    /// `None` for a graph without passes, since wgpu rejects empty query sets
+    fn new(device: &wgpu::Device, pass_count: usize) -> Option<PassTiming> {
+        if pass_count == 0 {
+            return None
+        }
+        let query_count = CompiledGraph::timestamp_query_count(pass_count);
+        let size = (query_count * wgpu::QUERY_SIZE) as wgpu::BufferAddress;
+
+        Some(PassTiming {
+            query_set: device.create_query_set(&wgpu::QuerySetDescriptor {
+                label: Some("Pass Timestamp Queries"),
+                ty: wgpu::QueryType::Timestamp,
+                count: query_count
+            }),
+            resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Pass Timestamp Resolve Buffer"),
+                size,
+                usage: wgpu::BufferUsages::QUERY_RESOLVE | wgpu::BufferUsages::COPY_SRC,
+                mapped_at_creation: false
+            }),
+            readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some("Pass Timestamp Readback Buffer"),
+                size,
+                usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+                mapped_at_creation: false
+            }),
+            passes: Vec::with_capacity(pass_count)
+        })
+    }
+}
+
 pub struct CompiledGraph<'graph> {
-    shaders: HashMap<Uuid, ShaderModule>,
-    pipeline_layouts: HashMap<Uuid, PipelineLayout>,
-    render_pipelines: HashMap<Uuid, RenderPipeline>,
+    shaders: ShaderCache,
+    pipeline_layouts: HashMap<(Uuid, usize), PipelineLayout>,
+    render_pipelines: HashMap<PipelineKey, RenderPipeline>,
     render_passes: HashMap<Uuid, RenderPass<'graph>>,
     render_queues: Vec<&'graph wgpu::Queue>,
+    encoder_label: &'graph str,
+    timing_enabled: bool,
+    timing: Option<PassTiming>,
+    stats: CompileStats,
+    dynamic_textures: HashMap<ResourceHandle, DynamicTexture>
 }
 
 impl<'graph> CompiledGraph<'graph> {
-    const VERTEX_SHADER_ENTRY: &'static str = "vs_main";
-    const FRAGMENT_SHADER_ENTRY: &'static str = "fs_main";
     const DEFAULT_CLEAR_COLOUR: wgpu::Color = wgpu::Color {
         r: 1.0,
         g: 0.0,
@@ -62,6 +226,47 @@ impl<'graph> CompiledGraph<'graph> {
         conservative: false
     };
 
+    pub fn new(queues: &[&'graph render::Queue]) -> CompiledGraph<'graph> {
+        CompiledGraph {
+            shaders: ShaderCache::new(),
+            pipeline_layouts: HashMap::new(),
+            render_pipelines: HashMap::new(),
+            render_passes: HashMap::new(),
+            render_queues: queues.iter()
+                .filter(|queue| queue.supports_render())
+                .map(|queue| queue.inner())
+            .collect(),
+            encoder_label: "Compiled Graph Encoder",
+            timing_enabled: false,
+            timing: None,
+            stats: CompileStats::default(),
+            dynamic_textures: HashMap::new()
+        }
+    }
+
+    /// Records a timestamp before and after every pass so their GPU durations can be read
+    /// with `pass_durations`. The device must have `Features::TIMESTAMP_QUERY`
+    pub fn with_timing(mut self, enabled: bool) -> Self {
+        self.timing_enabled = enabled;
+        self
+    }
+
+    /// Label for the frame's command encoder, e.g. to tell frames apart in a capture
+    pub fn with_encoder_label(mut self, label: &'graph str) -> Self {
+        self.encoder_label = label;
+        self
+    }
+
+    pub fn stats(&self) -> CompileStats {
+        self.stats
+    }
+
+    /// Number of timestamp queries needed to time `pass_count` passes
+    pub fn timestamp_query_count(pass_count: usize) -> u32 {
+        2 * pass_count as u32
+    }
+
+    #[allow(clippy::too_many_arguments)]
     pub fn render_from_graph<S>(
         graph: &'graph super::RenderGraph,
         device: &wgpu::Device,
@@ -71,96 +276,336 @@ impl<'graph> CompiledGraph<'graph> {
         colour_target_state: &'graph [Option<wgpu::ColorTargetState>],
         vertex_buffer_attachments: &HashMap<ResourceHandle, wgpu::BufferSlice>,
         colour_attachments: &HashMap<ResourceHandle, wgpu::RenderPassColorAttachment>
-    ) where
+    ) -> Result<(), CompileError> where
+        S: Clone + std::fmt::Debug + ShaderSource<'graph> {
+        CompiledGraph::new(queues).render(
+            graph,
+            device,
+            shaders,
+            vertex_buffer_layout,
+            colour_target_state,
+            vertex_buffer_attachments,
+            colour_attachments
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn render<S>(
+        &mut self,
+        graph: &'graph super::RenderGraph,
+        device: &wgpu::Device,
+        shaders: &HashMap<ShaderHandle, &ShaderBuilder<'graph, S>>,
+        vertex_buffer_layout: &'graph [wgpu::VertexBufferLayout],
+        colour_target_state: &'graph [Option<wgpu::ColorTargetState>],
+        vertex_buffer_attachments: &HashMap<ResourceHandle, wgpu::BufferSlice>,
+        colour_attachments: &HashMap<ResourceHandle, wgpu::RenderPassColorAttachment>
+    ) -> Result<(), CompileError> where
         S: Clone + std::fmt::Debug + ShaderSource<'graph> {
         /* Algorithm:
-         * 1. Reverse directions and perform topological sort on graph
+         * 1. Topologically sort the forward graph, so passes are recorded in execution
+         *  order and later passes see what earlier ones wrote
          * 2. From topological sort, if the resource is not an external dependency, create
          *  when needed. If the resource cannot be created (Input and a vertex buffer, for
-         *  example), then panic
+         *  example), then return an error
          */
-        let mut compiled_graph = CompiledGraph {
-            shaders: HashMap::new(),
-            pipeline_layouts: HashMap::new(),
-            render_pipelines: HashMap::new(),
-            render_passes: HashMap::new(),
-            render_queues: queues.iter().filter_map(
-                |queue| {
-                    if let render::Queue::Render(wgpu_queue) = queue {
-                        return Some(wgpu_queue)
-                    }
-                    None
-                }
-            ).collect(),
-        };
+        let nodes_to_visit = petgraph::algo::toposort(&graph.graph.forward_graph, None)
+            .map_err(|_| CompileError::CyclicGraph)?;
+
+        self.timing = None;
+        if self.timing_enabled {
+            if !device.features().contains(wgpu::Features::TIMESTAMP_QUERY) {
+                return Err(CompileError::TimestampQueryUnsupported)
+            }
+            self.timing = PassTiming::new(device, graph.pass_count());
+        }
 
         let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
-            label: Some("Compiled Graph Encoder")
+            label: Some(self.encoder_label)
         });
-        let nodes_to_visit = petgraph::algo::toposort(&graph.graph.reverse_graph, None).unwrap();
 
         let mut pipeline_layouts = HashMap::new();
 
         for node_index in nodes_to_visit {
             let v = graph.graph.forward_graph.node_weight(node_index).unwrap();
             match v {
-                Vertex::Red(resource_handle) => {
-                    todo!();
+                Vertex::Red(_) => {
+                    // Dynamic textures are created by the first pass which touches them
                 },
                 Vertex::Blue(pass_handle) => {
                     let pass = graph.passes.get_from_handle(pass_handle).unwrap();
-                    let pipeline_info = graph.pipelines.get_from_handle(&pass.pipeline).unwrap();
-                    if !pipeline_layouts.contains_key(&pass.pipeline) {
-                        pipeline_layouts.insert(pass.pipeline, pipeline_info.builder.clone().build());
+                    check_read_write_hazards(pass)?;
+                    self.create_dynamic_textures(graph, device, pass)?;
+                    // Sampled textures go in the first bind group the layout leaves free
+                    let mut sampled_texture_group = 0;
+                    if let Some(pipeline) = pass.pipeline {
+                        let pipeline_info = graph.pipelines.get_from_handle(&pipeline).unwrap();
+                        sampled_texture_group = pipeline_info.builder.bind_group_count() as u32;
+                        let pipeline_layout = pipeline_layouts.entry(pipeline)
+                            .or_insert_with(|| pipeline_info.builder.clone().build());
+                        // Create wgpu pipeline if it doesnt exist already
+                        self.create_pipeline(
+                            pass,
+                            pipeline_info,
+                            pipeline_layout,
+                            device,
+                            shaders,
+                            vertex_buffer_layout,
+                            colour_target_state
+                        );
+                    }
+
+                    if let Some(timing) = &mut self.timing {
+                        let query_index = CompiledGraph::timestamp_query_count(timing.passes.len());
+                        encoder.write_timestamp(&timing.query_set, query_index);
+                        timing.passes.push(*pass_handle);
                     }
-                    let pipeline_layout = pipeline_layouts.get_mut(&pass.pipeline).unwrap();
-                    // Create wgpu pipeline if it doesnt exist already
-                    compiled_graph.create_pipeline(
-                        pass,
-                        pipeline_info,
-                        pipeline_layout,
-                        device,
-                        &shaders,
-                        vertex_buffer_layout,
-                        colour_target_state
-                    );
 
                     // Create render pass from pipeline
-                    compiled_graph.create_render_pass(
+                    let pass_colour_attachments = self.colour_attachments_with_dynamic(colour_attachments);
+                    self.create_render_pass(
                         device,
                         &mut encoder,
                         pass,
+                        sampled_texture_group,
+                        colour_target_state,
                         vertex_buffer_attachments,
-                        colour_attachments
-                    );
+                        &pass_colour_attachments
+                    )?;
+
+                    if let Some(timing) = &self.timing {
+                        let query_index = CompiledGraph::timestamp_query_count(timing.passes.len()) - 1;
+                        encoder.write_timestamp(&timing.query_set, query_index);
+                    }
                 },
             }
         }
 
-        compiled_graph.render_queues[0].submit(std::iter::once(encoder.finish()));
+        if let Some(timing) = &self.timing {
+            let query_count = CompiledGraph::timestamp_query_count(timing.passes.len());
+            encoder.resolve_query_set(&timing.query_set, 0..query_count, &timing.resolve_buffer, 0);
+            encoder.copy_buffer_to_buffer(
+                &timing.resolve_buffer, 0,
+                &timing.readback_buffer, 0,
+                (query_count * wgpu::QUERY_SIZE) as wgpu::BufferAddress
+            );
+        }
+
+        self.render_queues[0].submit(std::iter::once(encoder.finish()));
+        Ok(())
+    }
+
+    /// GPU time spent in each pass of the last `render`, in the order the passes ran.
+    /// Blocks until the frame has finished. `None` if timing was not enabled or the graph
+    /// had no passes
+    pub fn pass_durations(&self, device: &wgpu::Device) -> Option<Vec<(PassHandle, Duration)>> {
+        let timing = self.timing.as_ref()?;
+        let readback_slice = timing.readback_buffer.slice(..);
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        readback_slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).unwrap();
+        });
+        device.poll(wgpu::Maintain::Wait);
+        receiver.recv().unwrap().expect("Failed to map timestamp readback buffer");
+
+        let timestamps: Vec<u64> = readback_slice.get_mapped_range()
+            .chunks_exact(wgpu::QUERY_SIZE as usize)
+            .map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        timing.readback_buffer.unmap();
+
+        let durations = durations_from_timestamps(&timestamps, self.render_queues[0].get_timestamp_period());
+        Some(timing.passes.iter().copied().zip(durations).collect())
+    }
+
+    /// Orders the attachments for a pass so that its `n`th colour attachment writes to
+    /// the `n`th colour target of the pipeline, failing if the counts differ. Clear passes
+    /// have no pipeline, so every attachment is bound
+    fn map_colour_attachments<A: Clone>(
+        render_pass: &RenderPassBuilder,
+        colour_target_state: &[Option<wgpu::ColorTargetState>],
+        colour_attachments: &HashMap<ResourceHandle, A>
+    ) -> Result<Vec<Option<A>>, CompileError> {
+        let bound_slots: Vec<bool> = if render_pass.is_clear_pass() {
+            vec![true; render_pass.colour_attachments.len()]
+        } else {
+            if render_pass.colour_attachments.len() != colour_target_state.len() {
+                return Err(CompileError::ColourTargetMismatch {
+                    attachments: render_pass.colour_attachments.len(),
+                    targets: colour_target_state.len()
+                })
+            }
+            colour_target_state.iter().map(Option::is_some).collect()
+        };
+
+        render_pass.colour_attachments.iter()
+            .zip(bound_slots)
+            .map(|(attachment, bound)| {
+                // A `None` target leaves the slot unbound in the pipeline
+                if !bound {
+                    return Ok(None)
+                }
+
+                let resource_handle = attachment.resource_handle()
+                    .expect("New dynamic resources must be created before their pass is compiled");
+                colour_attachments.get(&resource_handle)
+                    .cloned()
+                    .map(Some)
+                    .ok_or(CompileError::MissingColourAttachment(resource_handle))
+            })
+        .collect()
+    }
+
+    /// Dynamic resources with a description which `pass` draws to or samples
+    fn dynamic_resources_for(graph: &RenderGraph, pass: &RenderPassBuilder) -> Vec<(ResourceHandle, DynamicResourceDesc)> {
+        pass.colour_attachments.iter()
+            .chain(pass.sampled_textures.iter())
+            .filter_map(|resource| resource.resource_handle())
+            .filter_map(|handle| graph.dynamic_resource_desc(handle).map(|desc| (handle, *desc)))
+            .collect()
+    }
+
+    /// Creates the textures backing the dynamic resources `pass` uses, replacing any whose
+    /// description has changed since they were created
+    fn create_dynamic_textures(
+        &mut self,
+        graph: &RenderGraph,
+        device: &wgpu::Device,
+        pass: &RenderPassBuilder
+    ) -> Result<(), CompileError> {
+        for (handle, desc) in CompiledGraph::dynamic_resources_for(graph, pass) {
+            if self.dynamic_textures.get(&handle).is_some_and(|texture| texture.desc == desc) {
+                continue;
+            }
+
+            let size = wgpu::Extent3d {
+                width: desc.width,
+                height: desc.height,
+                depth_or_array_layers: 1
+            };
+            let texture = TextureHandler::create_dynamic(device, handle.uuid(), size, desc.format, sampler_for(graph, handle)?);
+            self.dynamic_textures.insert(handle, DynamicTexture { desc, texture });
+        }
+        Ok(())
+    }
+
+    /// The supplied colour attachments along with one for every dynamic texture. Dynamic
+    /// textures start cleared, unless a pass overrides the load op
+    fn colour_attachments_with_dynamic<'a>(
+        &'a self,
+        colour_attachments: &HashMap<ResourceHandle, wgpu::RenderPassColorAttachment<'a>>
+    ) -> HashMap<ResourceHandle, wgpu::RenderPassColorAttachment<'a>> {
+        let mut attachments: HashMap<ResourceHandle, wgpu::RenderPassColorAttachment> = self.dynamic_textures.iter()
+            .filter_map(|(handle, texture)| texture.texture.view().map(|view| (*handle, view)))
+            .map(|(handle, view)| (handle, wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(Self::DEFAULT_CLEAR_COLOUR),
+                    store: true
+                }
+            }))
+            .collect();
+        attachments.extend(colour_attachments.iter().map(|(handle, attachment)| (*handle, attachment.clone())));
+        attachments
+    }
+
+    /// Binds every texture `pass` samples, each with its own sampler, as bind group
+    /// `group` of `pipeline`
+    fn sampled_texture_bind_group(
+        &self,
+        device: &wgpu::Device,
+        pipeline: &RenderPipeline,
+        pass: &RenderPassBuilder,
+        group: u32
+    ) -> Result<wgpu::BindGroup, CompileError> {
+        let textures = pass.sampled_textures.iter()
+            .map(|texture| {
+                let handle = texture.resource_handle()
+                    .expect("New dynamic resources must be created before their pass is compiled");
+                self.dynamic_textures.get(&handle)
+                    .and_then(|texture| texture.texture.view().zip(texture.texture.sampler()))
+                    .ok_or(CompileError::MissingSampledTexture(handle))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let entries: Vec<wgpu::BindGroupEntry> = textures.into_iter()
+            .enumerate()
+            .flat_map(|(index, (view, sampler))| {
+                let (texture_binding, sampler_binding) = sampled_texture_bindings(index);
+                [
+                    wgpu::BindGroupEntry {
+                        binding: texture_binding,
+                        resource: wgpu::BindingResource::TextureView(view)
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: sampler_binding,
+                        resource: wgpu::BindingResource::Sampler(sampler)
+                    }
+                ]
+            })
+            .collect();
+
+        Ok(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Sampled Graph Textures"),
+            layout: &pipeline.get_bind_group_layout(group),
+            entries: &entries
+        }))
     }
 
     fn create_render_pass<'render_pass>(
-        &'render_pass mut self,
+        &'render_pass self,
         device: &wgpu::Device,
         encoder: &mut CommandEncoder,
         render_pass: &RenderPassBuilder,
+        sampled_texture_group: u32,
+        colour_target_state: &[Option<wgpu::ColorTargetState>],
         vertex_buffer_attachments: &HashMap<ResourceHandle, wgpu::BufferSlice>,
         colour_attachments: &HashMap<ResourceHandle, wgpu::RenderPassColorAttachment>
-    ) {
-        let pipeline = self.render_pipelines.get(&render_pass.pipeline.uuid()).unwrap();
-        let attachments: Vec<Option<wgpu::RenderPassColorAttachment>> = render_pass.colour_attachments.iter()
-            .map(|h| Some(colour_attachments.get(&h.resource_handle().unwrap()).unwrap().clone()))
-        .collect();
+    ) -> Result<(), CompileError> {
+        let mut attachments = Self::map_colour_attachments(render_pass, colour_target_state, colour_attachments)?;
+        for (attachment, resource) in attachments.iter_mut().zip(&render_pass.colour_attachments) {
+            if let (Some(attachment), Some(resource_handle)) = (attachment, resource.resource_handle()) {
+                attachment.ops = render_pass.colour_operations(resource_handle, attachment.ops);
+            }
+        }
 
+        let pipeline = (!render_pass.is_clear_pass())
+            .then(|| self.render_pipelines.get(&PipelineKey::from_pass(render_pass)).unwrap());
+        let sampled_textures = match pipeline {
+            Some(pipeline) if !render_pass.sampled_textures.is_empty() =>
+                Some(self.sampled_texture_bind_group(device, pipeline, render_pass, sampled_texture_group)?),
+            _ => None
+        };
+        let instance_buffer = render_pass.instance_buffer;
+        let scissor = render_pass.scissor;
+        let (vertices, instances) = render_pass.draw_ranges();
         let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render pass"),
             color_attachments: &attachments,
             depth_stencil_attachment: None
         });
 
-        render_pass.set_pipeline(&pipeline);
-        render_pass.draw(0..3, 0..1);
+        // Clear passes are done once their attachments have been loaded
+        let Some(pipeline) = pipeline else {
+            return Ok(())
+        };
+        render_pass.set_pipeline(pipeline);
+        apply_scissor(scissor, |x, y, width, height| render_pass.set_scissor_rect(x, y, width, height));
+        if let Some(sampled_textures) = &sampled_textures {
+            render_pass.set_bind_group(sampled_texture_group, sampled_textures, &[]);
+        }
+
+        if let Some((instance_buffer, slot)) = instance_buffer {
+            let resource_handle = instance_buffer.resource_handle()
+                .expect("New dynamic resources must be created before their pass is compiled");
+            let buffer = vertex_buffer_attachments.get(&resource_handle)
+                .ok_or(CompileError::MissingInstanceBuffer(resource_handle))?;
+            render_pass.set_vertex_buffer(slot, *buffer);
+        }
+
+        render_pass.draw(vertices, instances);
+        Ok(())
     }
 
     fn create_pipeline<S>(
@@ -175,7 +620,9 @@ impl<'graph> CompiledGraph<'graph> {
     ) where
         S: Clone + std::fmt::Debug + ShaderSource<'graph>,
     {
-        if !self.render_pipelines.contains_key(&pass_builder.pipeline.uuid()) {
+        let pipeline_key = PipelineKey::from_pass(pass_builder);
+        if self.render_pipelines.contains_key(&pipeline_key) {
+            self.stats.pipelines_reused += 1;
             return
         }
 
@@ -199,63 +646,480 @@ impl<'graph> CompiledGraph<'graph> {
             }
         );
 
-        if !self.shaders.contains_key(&vertex_shader.module_builder.id) {
-            self.shaders.insert(
-                vertex_shader.module_builder.id,
-                device.create_shader_module(vertex_shader.module_builder.resource.build())
-            );
-        }
-
-        if let Some(fs) = &fragment_shader {
-            if !self.shaders.contains_key(&fs.module_builder.id) {
-                self.shaders.insert(
-                    fs.module_builder.id,
-                    device.create_shader_module(fs.module_builder.resource.build())
-                );
+        let shader_builders = std::iter::once(&vertex_shader.module_builder.resource)
+            .chain(fragment_shader.as_ref().map(|fs| &fs.module_builder.resource));
+        for shader_builder in shader_builders {
+            if self.shaders.contains(shader_builder.content_hash()) {
+                self.stats.shaders_reused += 1;
+            } else {
+                self.stats.shaders_compiled += 1;
             }
+            self.shaders.get(device, shader_builder);
         }
 
-        if !self.pipeline_layouts.contains_key(&pass_builder.pipeline.uuid()) {
-            self.pipeline_layouts.insert(
-                pass_builder.pipeline.uuid(),
-                pipeline_layout.create(&device)
-            );
-        }
+        let layout_key = (pipeline_key.pipeline, pipeline_key.sampled_textures);
+        self.pipeline_layouts.entry(layout_key)
+            .or_insert_with(|| {
+                let sampled_textures = (pipeline_key.sampled_textures > 0)
+                    .then(|| sampled_texture_layout(pipeline_key.sampled_textures));
+                pipeline_layout.create_with(device, sampled_textures.as_ref())
+            });
 
-        let vertex_shader_module = self.shaders.get(&vertex_shader.module_builder.id).unwrap();
+        let vertex_shader_module = self.shaders.module(vertex_shader.module_builder.resource.content_hash()).unwrap();
         let fragment_shader_module = fragment_shader.as_ref().map(
-            |b| self.shaders.get(&b.module_builder.id).unwrap()
+            |b| self.shaders.module(b.module_builder.resource.content_hash()).unwrap()
         );
-        let pipeline_layout = self.pipeline_layouts.get(&pass_builder.pipeline.uuid()).unwrap();
+        let pipeline_layout = self.pipeline_layouts.get(&layout_key).unwrap();
 
         let render_pipeline_descriptor = wgpu::RenderPipelineDescriptor {
             label: None,
             layout: Some(pipeline_layout),
             vertex: wgpu::VertexState {
                 module: &vertex_shader_module,
-                entry_point: Self::VERTEX_SHADER_ENTRY,
+                entry_point: pipeline_info.vertex_entry,
                 buffers: vertex_shader.inputs
             },
             fragment: fragment_shader_module.map(|fs|
                 wgpu::FragmentState {
                     module: &fs,
-                    entry_point: Self::FRAGMENT_SHADER_ENTRY,
+                    entry_point: pipeline_info.fragment_entry,
                     targets: fragment_shader.unwrap().inputs,
                 },
             ),
-            primitive: Self::PRIMITIVE_STATE,
-            depth_stencil: None,
-            multisample: wgpu::MultisampleState {
-                count: 1,
-                mask: !0,
-                alpha_to_coverage_enabled: false
+            primitive: wgpu::PrimitiveState {
+                cull_mode: pipeline_key.cull_mode,
+                ..Self::PRIMITIVE_STATE
             },
+            depth_stencil: pipeline_info.builder.depth_stencil_state(),
+            multisample: pipeline_info.builder.multisample_state(),
             multiview: None
         };
 
         self.render_pipelines.insert(
-            pass_builder.pipeline.uuid(),
+            pipeline_key,
             device.create_render_pipeline(&render_pipeline_descriptor)
         );
+        self.stats.pipelines_created += 1;
+    }
+}
+
+/// Converts `[begin, end]` timestamp pairs to durations. `period` is the nanoseconds per
+/// timestamp tick
+fn durations_from_timestamps(timestamps: &[u64], period: f32) -> Vec<Duration> {
+    timestamps.chunks_exact(2)
+        .map(|pair| {
+            let ticks = pair[1].saturating_sub(pair[0]);
+            Duration::from_nanos((ticks as f64 * period as f64) as u64)
+        })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_graph::handle_map::Handle;
+    use crate::render_graph::pass_builder::{ CullMode, PassResource, LoadOp };
+    use crate::render_graph::{ RenderGraph, resource::Resource, shader_builder::ShaderRepresentation };
+    use crate::render_graph::pipeline_builder::{ PipelineLayoutBuilder, BindGroupLayoutBuilder, VisibilityBuilder };
+    use crate::render_graph::shader_builder::WgslBuilder;
+    use crate::test_support::{ headless_device, render_target };
+
+    #[test]
+    fn test_cull_mode_pipeline_key() {
+        let pipeline = Handle::new();
+        let default_pass = RenderPassBuilder::render_pass(pipeline);
+        let back_pass = RenderPassBuilder::render_pass(pipeline).cull_mode(CullMode::Back);
+        let front_pass = RenderPassBuilder::render_pass(pipeline).cull_mode(CullMode::Front);
+        let no_cull_pass = RenderPassBuilder::render_pass(pipeline).cull_mode(CullMode::None);
+
+        assert_eq!(PipelineKey::from_pass(&default_pass), PipelineKey::from_pass(&back_pass));
+        assert_ne!(PipelineKey::from_pass(&back_pass), PipelineKey::from_pass(&front_pass));
+        assert_ne!(PipelineKey::from_pass(&back_pass), PipelineKey::from_pass(&no_cull_pass));
+        assert_ne!(PipelineKey::from_pass(&front_pass), PipelineKey::from_pass(&no_cull_pass));
+    }
+
+    #[test]
+    fn test_pipeline_created_once() {
+        let Some((device, queue)) = headless_device() else { return };
+        let queue = render::Queue::Universal(queue);
+
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let pipeline = graph.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+        let target = graph.add_resource(Resource::persistent_with_name("Target"));
+        for cull_mode in [CullMode::Back, CullMode::Back, CullMode::None] {
+            graph.add_render_pass(
+                RenderPassBuilder::render_pass(pipeline)
+                    .add_colour_attachment(PassResource::InputAndOutput(target.handle))
+                    .cull_mode(cull_mode)
+            );
+        }
+
+        let triangle = triangle_shader();
+        let shaders = HashMap::from([(shader, &triangle)]);
+        let view = render_target(&device, TARGET_FORMAT, 4, 4).create_view(&Default::default());
+        let colour_attachments = HashMap::from([(target.handle, cleared(&view))]);
+
+        // Only the pass overriding the cull mode needs a pipeline of its own
+        let mut compiled = CompiledGraph::new(&[&queue]);
+        compiled.render(&graph, &device, &shaders, &[], &TARGETS, &HashMap::new(), &colour_attachments).unwrap();
+        assert_eq!(compiled.render_pipelines.len(), 2);
+        assert_eq!((compiled.stats().pipelines_created, compiled.stats().pipelines_reused), (2, 1));
+    }
+
+    #[test]
+    fn test_cyclic_graph() {
+        let Some((device, queue)) = headless_device() else { return };
+        let queue = render::Queue::Universal(queue);
+
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let pipeline = graph.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+        let target = graph.add_resource(Resource::persistent_with_name("Target"));
+        graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .add_colour_attachment(PassResource::InputAndOutput(target.handle))
+        );
+
+        // Point every edge back the other way too, as a bad description could
+        let edges: Vec<_> = graph.graph.forward_graph.edge_indices()
+            .filter_map(|edge| graph.graph.forward_graph.edge_endpoints(edge))
+            .collect();
+        assert!(!edges.is_empty());
+        for (from, to) in edges {
+            graph.graph.add_edge(to, from);
+        }
+
+        let triangle = triangle_shader();
+        let shaders = HashMap::from([(shader, &triangle)]);
+        let view = render_target(&device, TARGET_FORMAT, 4, 4).create_view(&Default::default());
+        let colour_attachments = HashMap::from([(target.handle, cleared(&view))]);
+
+        let result = CompiledGraph::new(&[&queue])
+            .render(&graph, &device, &shaders, &[], &TARGETS, &HashMap::new(), &colour_attachments);
+        assert!(matches!(result, Err(CompileError::CyclicGraph)));
+    }
+
+    #[test]
+    fn test_multiple_colour_targets() {
+        let albedo = Handle::new();
+        let normal = Handle::new();
+        let gbuffer_pass = RenderPassBuilder::render_pass(Handle::new())
+            .add_colour_attachment(PassResource::OnlyOutput(Some(albedo), None))
+            .add_colour_attachment(PassResource::OnlyOutput(Some(normal), None));
+
+        let target = |format| Some(wgpu::ColorTargetState {
+            format,
+            blend: None,
+            write_mask: wgpu::ColorWrites::ALL
+        });
+        let targets = [
+            target(wgpu::TextureFormat::Rgba8Unorm),
+            target(wgpu::TextureFormat::Rgba16Float)
+        ];
+        let attachments = HashMap::from([(normal, "normal"), (albedo, "albedo")]);
+
+        let mapped = CompiledGraph::map_colour_attachments(&gbuffer_pass, &targets, &attachments).unwrap();
+        assert_eq!(mapped, vec![Some("albedo"), Some("normal")]);
+
+        assert_eq!(
+            CompiledGraph::map_colour_attachments(&gbuffer_pass, &targets[..1], &attachments),
+            Err(CompileError::ColourTargetMismatch { attachments: 2, targets: 1 })
+        );
+
+        let missing = HashMap::from([(albedo, "albedo")]);
+        assert_eq!(
+            CompiledGraph::map_colour_attachments(&gbuffer_pass, &targets, &missing),
+            Err(CompileError::MissingColourAttachment(normal))
+        );
+    }
+
+    #[test]
+    fn test_instanced_draw() {
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let pipeline = graph.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+        let instances = graph.add_resource(Resource::persistent_with_name("VoxelInstances"));
+
+        let (pass, _) = graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .add_colour_attachment(PassResource::new_output())
+                .set_instance_buffer(PassResource::OnlyInput(instances.handle), 1)
+                .instances(64)
+        );
+
+        assert!(graph.graph.forward_graph.contains_edge(instances.node_index, pass.node_index));
+
+        let pass = graph.passes.get_from_handle(&pass.handle).unwrap();
+        assert_eq!(pass.instance_buffer, Some((PassResource::OnlyInput(instances.handle), 1)));
+        assert_eq!(pass.draw_ranges(), (0..3, 0..64));
+        assert_eq!(RenderPassBuilder::render_pass(pipeline).draw_ranges(), (0..3, 0..1));
+    }
+
+    const TARGET_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+    const TARGETS: [Option<wgpu::ColorTargetState>; 1] = [Some(wgpu::ColorTargetState {
+        format: TARGET_FORMAT,
+        blend: None,
+        write_mask: wgpu::ColorWrites::ALL
+    })];
+
+    fn triangle_shader() -> ShaderBuilder<'static, WgslBuilder<'static>> {
+        ShaderBuilder::shader(WgslBuilder::from_buffer(include_str!("../triangle.wgsl")))
+    }
+
+    fn cleared(view: &wgpu::TextureView) -> wgpu::RenderPassColorAttachment<'_> {
+        wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                store: true
+            }
+        }
+    }
+
+    #[test]
+    fn test_compile_stats() {
+        let Some((device, queue)) = headless_device() else { return };
+        let queue = render::Queue::Universal(queue);
+
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let pipeline = graph.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+        let target = graph.add_resource(Resource::persistent_with_name("Target"));
+        graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .add_colour_attachment(PassResource::OnlyOutput(Some(target.handle), None))
+        );
+
+        let triangle = triangle_shader();
+        let shaders = HashMap::from([(shader, &triangle)]);
+        let view = render_target(&device, TARGET_FORMAT, 4, 4).create_view(&Default::default());
+        let colour_attachments = HashMap::from([(target.handle, cleared(&view))]);
+
+        let mut compiled = CompiledGraph::new(&[&queue]);
+        let mut compile = || compiled.render(&graph, &device, &shaders, &[], &TARGETS, &HashMap::new(), &colour_attachments);
+
+        // The vertex and fragment stages share a module, so the fragment stage is a reuse
+        compile().unwrap();
+        compile().unwrap();
+        assert_eq!(compiled.stats(), CompileStats {
+            pipelines_created: 1,
+            pipelines_reused: 1,
+            shaders_compiled: 1,
+            shaders_reused: 1
+        });
+    }
+
+    const BLUR_SHADER: &str = "
+        @group(0) @binding(0) var source: texture_2d<f32>;
+        @group(0) @binding(1) var source_sampler: sampler;
+
+        @vertex
+        fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+            let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+            return vec4<f32>(uv * 2.0 - 1.0, 0.0, 1.0);
+        }
+
+        @fragment
+        fn fs_main(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+            return textureSample(source, source_sampler, position.xy / 4.0);
+        }
+    ";
+
+    #[test]
+    fn test_sampled_pass_output() {
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let blur_shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let scene_pipeline = graph.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+        let blur_pipeline = graph.add_pipeline(PipelineLayoutBuilder::layout(), blur_shader, Some(blur_shader), None);
+        let surface = graph.add_resource(Resource::persistent_with_name("Surface"));
+
+        let (horizontal, outputs) = graph.add_render_pass(
+            RenderPassBuilder::render_pass(scene_pipeline)
+                .add_colour_attachment(PassResource::named_output("BlurTemp"))
+        );
+        let blur_temp = outputs[0].handle;
+        let desc = DynamicResourceDesc {
+            width: 4,
+            height: 4,
+            format: TARGET_FORMAT
+        };
+        graph.set_dynamic_resource_desc(blur_temp, desc).unwrap();
+
+        let (vertical, _) = graph.add_render_pass(
+            RenderPassBuilder::render_pass(blur_pipeline)
+                .sample_texture(blur_temp)
+                .sampler(SamplerDesc::nearest())
+                .add_colour_attachment(PassResource::OnlyOutput(Some(surface.handle), None))
+        );
+
+        assert_eq!(graph.execution_order().unwrap(), vec![horizontal.handle, vertical.handle]);
+
+        // Both passes resolve the temporary to the same texture
+        let horizontal = graph.passes.get_from_handle(&horizontal.handle).unwrap();
+        let vertical = graph.passes.get_from_handle(&vertical.handle).unwrap();
+        assert_eq!(CompiledGraph::dynamic_resources_for(&graph, horizontal), vec![(blur_temp, desc)]);
+        assert_eq!(CompiledGraph::dynamic_resources_for(&graph, vertical), vec![(blur_temp, desc)]);
+
+        // The texture takes its sampler from the pass that samples it
+        assert_eq!(sampler_for(&graph, blur_temp), Ok(SamplerDesc::nearest()));
+        assert_eq!(sampler_for(&graph, surface.handle), Ok(SamplerDesc::default()));
+
+        let layout = sampled_texture_layout(2);
+        let (texture_binding, sampler_binding) = sampled_texture_bindings(1);
+        assert!(matches!(layout.entries[texture_binding as usize].ty, wgpu::BindingType::Texture { .. }));
+        assert!(matches!(layout.entries[sampler_binding as usize].ty, wgpu::BindingType::Sampler(_)));
+
+        let Some((device, queue)) = headless_device() else { return };
+        let queue = render::Queue::Universal(queue);
+        let scene = triangle_shader();
+        let blur = ShaderBuilder::shader(WgslBuilder::from_buffer(BLUR_SHADER));
+        let shaders = HashMap::from([(shader, &scene), (blur_shader, &blur)]);
+        let view = render_target(&device, TARGET_FORMAT, 4, 4).create_view(&Default::default());
+        let colour_attachments = HashMap::from([(surface.handle, cleared(&view))]);
+
+        let mut compiled = CompiledGraph::new(&[&queue]);
+        compiled.render(&graph, &device, &shaders, &[], &TARGETS, &HashMap::new(), &colour_attachments).unwrap();
+        assert_eq!(compiled.dynamic_textures[&blur_temp].texture.sampler_desc(), Some(SamplerDesc::nearest()));
+    }
+
+    #[test]
+    fn test_sampled_texture_group() {
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let pipeline = graph.add_pipeline(
+            PipelineLayoutBuilder::layout()
+                .bind_group(BindGroupLayoutBuilder::binding().add_uniform_buffer::<[f32; 4]>(VisibilityBuilder::visibility().fragment())),
+            shader,
+            Some(shader),
+            None
+        );
+        let (_, outputs) = graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .add_colour_attachment(PassResource::new_output())
+        );
+        let texture = outputs[0].handle;
+
+        // The layout's own bind group keeps group 0, so sampled textures move to group 1
+        let pipeline_info = graph.pipelines.get_from_handle(&pipeline).unwrap();
+        assert_eq!(pipeline_info.builder.bind_group_count(), 1);
+
+        let sampling_pass = |sampler| RenderPassBuilder::render_pass(pipeline)
+            .sample_texture(texture)
+            .sampler(sampler)
+            .add_colour_attachment(PassResource::new_output());
+        graph.add_render_pass(sampling_pass(SamplerDesc::nearest()));
+        assert_eq!(sampler_for(&graph, texture), Ok(SamplerDesc::nearest()));
+
+        graph.add_render_pass(sampling_pass(SamplerDesc::linear()));
+        assert_eq!(sampler_for(&graph, texture), Err(CompileError::SamplerMismatch(texture)));
+    }
+
+    #[test]
+    fn test_scissor() {
+        let pass = RenderPassBuilder::render_pass(Handle::new()).scissor(16, 32, 320, 240);
+        assert_eq!(pass.scissor, Some(ScissorRect { x: 16, y: 32, width: 320, height: 240 }));
+
+        let mut applied = Vec::new();
+        apply_scissor(pass.scissor, |x, y, width, height| applied.push((x, y, width, height)));
+        assert_eq!(applied, vec![(16, 32, 320, 240)]);
+
+        let unscissored = RenderPassBuilder::render_pass(Handle::new());
+        apply_scissor(unscissored.scissor, |_, _, _, _| panic!("Passes without a scissor must not set one"));
+    }
+
+    #[test]
+    fn test_read_write_hazard() {
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let pipeline = graph.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+        let surface = graph.add_resource(Resource::persistent_with_name("Surface"));
+
+        let (accumulate, _) = graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .add_colour_attachment(PassResource::InputAndOutput(surface.handle))
+        );
+        let (feedback, _) = graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .add_colour_attachment(PassResource::InputAndOutput(surface.handle))
+                .sample_texture(surface.handle)
+        );
+        let (doubled, _) = graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .add_colour_attachment(PassResource::InputAndOutput(surface.handle))
+                .add_colour_attachment(PassResource::OnlyOutput(Some(surface.handle), None))
+        );
+
+        let accumulate = graph.passes.get_from_handle(&accumulate.handle).unwrap();
+        let feedback = graph.passes.get_from_handle(&feedback.handle).unwrap();
+        let doubled = graph.passes.get_from_handle(&doubled.handle).unwrap();
+
+        // Loading an attachment and drawing over it is fine on its own
+        assert_eq!(check_read_write_hazards(accumulate), Ok(()));
+        assert_eq!(check_read_write_hazards(feedback), Err(CompileError::ReadWriteHazard(surface.handle)));
+        assert_eq!(check_read_write_hazards(doubled), Err(CompileError::ReadWriteHazard(surface.handle)));
+    }
+
+    #[test]
+    fn test_surface_load_op() {
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let pipeline = graph.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+        let surface = graph.add_resource(Resource::persistent_with_name("Surface"));
+        let other = Handle::new();
+
+        let background = wgpu::Color { r: 0.1, g: 0.2, b: 0.3, a: 1.0 };
+        let pass = RenderPassBuilder::render_pass(pipeline)
+            .add_colour_attachment(PassResource::InputAndOutput(surface.handle))
+            .add_colour_attachment(PassResource::InputAndOutput(other))
+            .colour_load_op(surface.handle, LoadOp::Clear(background));
+
+        let load = wgpu::Operations {
+            load: wgpu::LoadOp::Load,
+            store: true
+        };
+        let surface_ops = pass.colour_operations(surface.handle, load);
+        assert_eq!(surface_ops.load, wgpu::LoadOp::Clear(background));
+        assert!(surface_ops.store);
+        assert_eq!(pass.colour_operations(other, load), load);
+
+        let reloaded = pass.colour_load_op(surface.handle, LoadOp::Load);
+        assert_eq!(reloaded.load_ops.len(), 1);
+        assert_eq!(reloaded.colour_operations(surface.handle, load).load, wgpu::LoadOp::Load);
+    }
+
+    #[test]
+    fn test_timestamp_query_count() {
+        let mut graph = RenderGraph::new();
+        let shader = graph.add_shader(ShaderRepresentation::shader(), None);
+        let pipeline = graph.add_pipeline(PipelineLayoutBuilder::layout(), shader, Some(shader), None);
+        for _ in 0..3 {
+            graph.add_render_pass(
+                RenderPassBuilder::render_pass(pipeline)
+                    .add_colour_attachment(PassResource::new_output())
+            );
+        }
+
+        assert_eq!(CompiledGraph::timestamp_query_count(graph.pass_count()), 6);
+        assert_eq!(CompiledGraph::timestamp_query_count(0), 0);
+    }
+
+    #[test]
+    fn test_durations_from_timestamps() {
+        let durations = durations_from_timestamps(&[100, 300, 300, 1300], 2.0);
+        assert_eq!(durations, vec![Duration::from_nanos(400), Duration::from_nanos(2000)]);
+
+        // A fractional tick period, a timestamp which went backwards and an unpaired end
+        let durations = durations_from_timestamps(&[1_000, 13_000, 500, 400, 7], 83.25);
+        assert_eq!(durations, vec![Duration::from_nanos(999_000), Duration::ZERO]);
+        assert!(durations_from_timestamps(&[], 1.0).is_empty());
+    }
+
+    #[test]
+    fn test_no_timing_without_passes() {
+        let Some((device, _)) = headless_device() else { return };
+        assert!(PassTiming::new(&device, 0).is_none());
     }
 }
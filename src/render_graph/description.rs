@@ -0,0 +1,351 @@
+use uuid::Uuid;
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use serde::{ Serialize, Deserialize };
+
+use crate::render_graph::{
+    RenderGraph, Vertex, VertexHandle, NodeKind, PipelineInfo,
+    handle_map::{ Handle, HandleType },
+    pass_builder::{ PassHandle, PassResource, CullMode, LoadOp, RenderPassBuilder, ScissorRect },
+    pipeline_builder::{ PipelineHandle, PipelineLayoutDescription },
+    resource::{ ResourceHandle, Resource, Id, DynamicResourceDesc },
+    shader_builder::{ ShaderHandle, ShaderStage, ShaderRepresentation }
+};
+use crate::render_engine::texture::SamplerDesc;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ShaderDescription {
+    pub handle: ShaderHandle,
+    pub id: Option<String>,
+    pub stages: Vec<(ShaderStage, Vec<ResourceHandle>)>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PipelineDescription {
+    pub handle: PipelineHandle,
+    pub id: Option<String>,
+    pub layout: PipelineLayoutDescription,
+    pub vertex_shader: ShaderHandle,
+    pub fragment_shader: Option<ShaderHandle>,
+    pub vertex_entry: String,
+    pub fragment_entry: String
+}
+
+/// Owned form of a `PassResource`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PassResourceDescription {
+    OnlyInput(ResourceHandle),
+    OnlyOutput(Option<ResourceHandle>, Option<String>),
+    InputAndOutput(ResourceHandle)
+}
+
+impl From<&PassResource<'_>> for PassResourceDescription {
+    fn from(resource: &PassResource) -> PassResourceDescription {
+        match *resource {
+            PassResource::OnlyInput(handle) => PassResourceDescription::OnlyInput(handle),
+            PassResource::OnlyOutput(handle, name) =>
+                PassResourceDescription::OnlyOutput(handle, name.map(|name| name.to_string())),
+            PassResource::InputAndOutput(handle) => PassResourceDescription::InputAndOutput(handle)
+        }
+    }
+}
+
+impl PassResourceDescription {
+    pub fn pass_resource(&self) -> PassResource<'_> {
+        match self {
+            PassResourceDescription::OnlyInput(handle) => PassResource::OnlyInput(*handle),
+            PassResourceDescription::OnlyOutput(handle, name) => PassResource::OnlyOutput(*handle, name.as_deref()),
+            PassResourceDescription::InputAndOutput(handle) => PassResource::InputAndOutput(*handle)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PassDescription {
+    pub handle: PassHandle,
+    pub label: Option<String>,
+    pub colour_attachments: Vec<PassResourceDescription>,
+    pub depth_stencil: Option<PassResourceDescription>,
+    pub vertex_buffer: Option<PassResourceDescription>,
+    pub index_buffer: Option<PassResourceDescription>,
+    pub instance_buffer: Option<(PassResourceDescription, u32)>,
+    pub instance_count: u32,
+    pub sampled_textures: Vec<PassResourceDescription>,
+    pub sampler: SamplerDesc,
+    pub cull_mode: Option<CullMode>,
+    pub load_ops: Vec<(ResourceHandle, LoadOp)>,
+    pub clear_colour: Option<wgpu::Color>,
+    pub scissor: Option<ScissorRect>,
+    pub pipeline: Option<PipelineHandle>
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ResourceKind {
+    Persistent {
+        global_id: Uuid,
+        name: Option<String>
+    },
+    Dynamic {
+        id: Uuid,
+        desc: Option<DynamicResourceDesc>
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceDescription {
+    pub handle: ResourceHandle,
+    pub id: Option<String>,
+    pub kind: ResourceKind
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeDescription {
+    pub kind: NodeKind,
+    pub handle: Handle
+}
+
+/// A render graph with every borrowed string and GPU-adjacent builder replaced by an
+/// owned equivalent, so it can be saved and loaded independently of any device
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GraphDescription {
+    pub shaders: Vec<ShaderDescription>,
+    pub pipelines: Vec<PipelineDescription>,
+    pub passes: Vec<PassDescription>,
+    pub resources: Vec<ResourceDescription>,
+    /// Graph vertices in insertion order
+    pub nodes: Vec<NodeDescription>,
+    /// Forward edges as indices into `nodes`
+    pub edges: Vec<(usize, usize)>
+}
+
+impl<'graph> RenderGraph<'graph> {
+    pub fn to_description(&self) -> GraphDescription {
+        let mut shaders: Vec<ShaderDescription> = self.shaders.iter()
+            .map(|(handle, shader)| {
+                let mut stages: Vec<(ShaderStage, Vec<ResourceHandle>)> = shader.stages().iter()
+                    .map(|(stage, inputs)| (*stage, inputs.clone()))
+                    .collect();
+                stages.sort_by_key(|(stage, _)| *stage);
+
+                ShaderDescription {
+                    handle: *handle,
+                    id: self.shaders.get_string_from_handle(handle),
+                    stages
+                }
+            })
+            .collect();
+        shaders.sort_by_key(|shader| shader.handle.uuid());
+
+        let mut pipelines: Vec<PipelineDescription> = self.pipelines.iter()
+            .map(|(handle, pipeline)| PipelineDescription {
+                handle: *handle,
+                id: self.pipelines.get_string_from_handle(handle),
+                layout: pipeline.builder.describe(),
+                vertex_shader: pipeline.vertex_shader,
+                fragment_shader: pipeline.fragment_shader,
+                vertex_entry: pipeline.vertex_entry.to_string(),
+                fragment_entry: pipeline.fragment_entry.to_string()
+            })
+            .collect();
+        pipelines.sort_by_key(|pipeline| pipeline.handle.uuid());
+
+        let mut passes: Vec<PassDescription> = self.passes.iter()
+            .map(|(handle, pass)| PassDescription {
+                handle: *handle,
+                label: pass.label.map(|label| label.to_string()),
+                colour_attachments: pass.colour_attachments.iter().map(PassResourceDescription::from).collect(),
+                depth_stencil: pass.depth_stencil.as_ref().map(PassResourceDescription::from),
+                vertex_buffer: pass.vertex_buffer.as_ref().map(PassResourceDescription::from),
+                index_buffer: pass.index_buffer.as_ref().map(PassResourceDescription::from),
+                instance_buffer: pass.instance_buffer.as_ref()
+                    .map(|(resource, slot)| (PassResourceDescription::from(resource), *slot)),
+                instance_count: pass.instance_count,
+                sampled_textures: pass.sampled_textures.iter().map(PassResourceDescription::from).collect(),
+                sampler: pass.sampler,
+                cull_mode: pass.cull_mode,
+                load_ops: pass.load_ops.clone(),
+                clear_colour: pass.clear_colour,
+                scissor: pass.scissor,
+                pipeline: pass.pipeline
+            })
+            .collect();
+        passes.sort_by_key(|pass| pass.handle.uuid());
+
+        let mut resources: Vec<ResourceDescription> = self.resources.iter()
+            .map(|(handle, resource)| ResourceDescription {
+                handle: *handle,
+                id: self.resources.get_string_from_handle(handle),
+                kind: match resource {
+                    Resource::Persistent(id) => ResourceKind::Persistent {
+                        global_id: id.global_id,
+                        name: id.string_id.map(|name| name.to_string())
+                    },
+                    Resource::Dynamic(id) => ResourceKind::Dynamic {
+                        id: *id,
+                        desc: self.dynamic_resource_descs.get(handle).copied()
+                    }
+                }
+            })
+            .collect();
+        resources.sort_by_key(|resource| resource.handle.uuid());
+
+        let nodes = self.graph.forward_graph.node_weights()
+            .map(|vertex| match vertex {
+                Vertex::Red(handle) => NodeDescription { kind: NodeKind::Resource, handle: *handle },
+                Vertex::Blue(handle) => NodeDescription { kind: NodeKind::Pass, handle: *handle }
+            })
+            .collect();
+
+        let edges = self.graph.forward_graph.edge_references()
+            .map(|edge| (edge.source().index(), edge.target().index()))
+            .collect();
+
+        GraphDescription {
+            shaders,
+            pipelines,
+            passes,
+            resources,
+            nodes,
+            edges
+        }
+    }
+
+    /// Rebuilds a graph from its description. Labels and names are borrowed from the
+    /// description, so it must outlive the graph
+    pub fn from_description(description: &'graph GraphDescription) -> RenderGraph<'graph> {
+        let mut graph = RenderGraph::new();
+
+        for shader in &description.shaders {
+            let representation = shader.stages.iter().fold(
+                ShaderRepresentation::shader(),
+                |representation, (stage, inputs)| {
+                    inputs.iter()
+                        .fold(representation.add_stage(*stage), |stage, input| stage.add_input(*input))
+                    .finish()
+                }
+            );
+            graph.shaders.insert(shader.handle, representation, shader.id.clone());
+        }
+
+        for pipeline in &description.pipelines {
+            graph.pipelines.insert(pipeline.handle, PipelineInfo {
+                builder: pipeline.layout.builder(),
+                vertex_shader: pipeline.vertex_shader,
+                fragment_shader: pipeline.fragment_shader,
+                vertex_entry: &pipeline.vertex_entry,
+                fragment_entry: &pipeline.fragment_entry
+            }, pipeline.id.clone());
+        }
+
+        for pass in &description.passes {
+            graph.passes.insert(pass.handle, RenderPassBuilder {
+                label: pass.label.as_deref(),
+                colour_attachments: pass.colour_attachments.iter().map(|resource| resource.pass_resource()).collect(),
+                depth_stencil: pass.depth_stencil.as_ref().map(|resource| resource.pass_resource()),
+                vertex_buffer: pass.vertex_buffer.as_ref().map(|resource| resource.pass_resource()),
+                index_buffer: pass.index_buffer.as_ref().map(|resource| resource.pass_resource()),
+                instance_buffer: pass.instance_buffer.as_ref()
+                    .map(|(resource, slot)| (resource.pass_resource(), *slot)),
+                instance_count: pass.instance_count,
+                sampled_textures: pass.sampled_textures.iter().map(|resource| resource.pass_resource()).collect(),
+                sampler: pass.sampler,
+                cull_mode: pass.cull_mode,
+                load_ops: pass.load_ops.clone(),
+                clear_colour: pass.clear_colour,
+                scissor: pass.scissor,
+                pipeline: pass.pipeline
+            }, pass.label.clone());
+        }
+
+        for resource in &description.resources {
+            let live_resource = match &resource.kind {
+                ResourceKind::Persistent { global_id, name } => Resource::Persistent(Id {
+                    global_id: *global_id,
+                    string_id: name.as_deref()
+                }),
+                ResourceKind::Dynamic { id, desc } => {
+                    if let Some(desc) = desc {
+                        graph.dynamic_resource_descs.insert(resource.handle, *desc);
+                    }
+                    Resource::Dynamic(*id)
+                }
+            };
+            graph.resources.insert(resource.handle, live_resource, resource.id.clone());
+        }
+
+        for node in &description.nodes {
+            let vertex = match node.kind {
+                NodeKind::Resource => Vertex::Red(node.handle),
+                NodeKind::Pass => Vertex::Blue(node.handle)
+            };
+            let node_index = graph.graph.add_node(vertex);
+            graph.vertex_handle_map.insert(node.handle, VertexHandle::new_from_node(node_index, node.handle));
+        }
+
+        for (from, to) in &description.edges {
+            graph.graph.add_edge(NodeIndex::new(*from), NodeIndex::new(*to));
+        }
+
+        graph
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_graph::pipeline_builder::{ PipelineLayoutBuilder, DepthStencilBuilder };
+
+    #[test]
+    fn test_description_round_trip() {
+        let mut graph = RenderGraph::new();
+        let surface = graph.add_resource(Resource::persistent_with_name("Surface"));
+        let shader = graph.add_shader(
+            ShaderRepresentation::shader()
+                .add_stage(ShaderStage::Vertex).finish()
+                .add_stage(ShaderStage::Fragment)
+                    .add_input(surface.handle)
+                .finish(),
+            Some("default_shader")
+        );
+        let pipeline = graph.add_pipeline(
+            PipelineLayoutBuilder::layout()
+                .label("Render Pipeline Layout")
+                .depth_stencil(DepthStencilBuilder::depth_stencil(wgpu::TextureFormat::Depth32Float)),
+            shader, Some(shader),
+            Some("render_pipeline")
+        );
+
+        let (_, outputs) = graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .label("Scene Pass")
+                .add_colour_attachment(PassResource::named_output("SceneColour"))
+        );
+        graph.set_dynamic_resource_desc(outputs[0].handle, DynamicResourceDesc {
+            width: 320,
+            height: 240,
+            format: wgpu::TextureFormat::Rgba8Unorm
+        }).unwrap();
+
+        graph.add_render_pass(
+            RenderPassBuilder::render_pass(pipeline)
+                .label("Composite Pass")
+                .cull_mode(CullMode::None)
+                .scissor(0, 0, 160, 120)
+                .sampler(SamplerDesc::nearest())
+                .add_colour_attachment(PassResource::OnlyInput(outputs[0].handle))
+                .add_colour_attachment(PassResource::InputAndOutput(surface.handle))
+                .colour_load_op(surface.handle, LoadOp::Clear(wgpu::Color::BLACK))
+        );
+
+        let description = graph.to_description();
+        assert_eq!(description.passes.len(), 2);
+
+        let rebuilt = RenderGraph::from_description(&description);
+        assert_eq!(rebuilt.to_description(), description);
+        assert_eq!(
+            rebuilt.string_graph().node_weights().collect::<Vec<_>>(),
+            graph.string_graph().node_weights().collect::<Vec<_>>()
+        );
+        assert_eq!(rebuilt.resource_lifetimes().unwrap(), graph.resource_lifetimes().unwrap());
+    }
+}
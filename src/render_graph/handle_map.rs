@@ -1,7 +1,8 @@
 use uuid::Uuid;
+use serde::{ Serialize, Deserialize };
 use std::collections::HashMap;
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Handle(Uuid);
 
 pub trait HandleType {
@@ -38,12 +39,29 @@ impl<T, HandleT> HandleMap<HandleT, T> where
 
     pub fn add(&mut self, object: T, string_id: Option<String>) -> HandleT {
         let handle = HandleT::new();
+        self.insert(handle, object, string_id);
+        handle
+    }
+
+    /// Adds an object under a handle that was created elsewhere
+    pub fn insert(&mut self, handle: HandleT, object: T, string_id: Option<String>) {
         self.handle_map.insert(handle, object);
         if let Some(id) = string_id {
             self.string_map.insert(id.clone(), handle);
             self.handle_to_string_map.insert(handle, id);
         }
-        handle
+    }
+
+    pub fn len(&self) -> usize {
+        self.handle_map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handle_map.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&HandleT, &T)> {
+        self.handle_map.iter()
     }
 
     pub fn get_from_string(&self, string_id: &String) -> Option<&T> {
@@ -1,18 +1,33 @@
+use std::ops::Range;
+use serde::{ Serialize, Deserialize };
 use crate::render_graph::resource::ResourceHandle;
 use crate::render_graph::pipeline_builder::PipelineHandle;
+use crate::render_engine::texture::SamplerDesc;
 pub use crate::render_graph::handle_map::Handle as PassHandle;
 
-#[derive(Debug, Clone, Copy)]
-pub enum PassResource {
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PassResource<'pass> {
     OnlyInput(ResourceHandle),
-    OnlyOutput(Option<ResourceHandle>),
+    /// Writes to `handle`, or to a new dynamic resource when `None`. The name is given to
+    /// the new resource so it can be found by string in the graph
+    OnlyOutput(Option<ResourceHandle>, Option<&'pass str>),
     InputAndOutput(ResourceHandle)
 }
 
-impl PassResource {
+impl<'pass> PassResource<'pass> {
+    /// Output to a new, unnamed dynamic resource
+    pub fn new_output() -> Self {
+        PassResource::OnlyOutput(None, None)
+    }
+
+    /// Output to a new dynamic resource identified by `name`
+    pub fn named_output(name: &'pass str) -> Self {
+        PassResource::OnlyOutput(None, Some(name))
+    }
+
     pub fn is_output(&self) -> bool {
         match self {
-            PassResource::OnlyOutput(_) => true,
+            PassResource::OnlyOutput(..) => true,
             PassResource::InputAndOutput(_) => true,
             PassResource::OnlyInput(_) => false
         }
@@ -20,14 +35,14 @@ impl PassResource {
 
     pub fn is_input(&self) -> bool {
         match self {
-            PassResource::OnlyOutput(_) => false,
+            PassResource::OnlyOutput(..) => false,
             PassResource::InputAndOutput(_) => true,
             PassResource::OnlyInput(_) => true
         }
     }
 
     pub fn is_new_resource(&self) -> bool {
-        if let PassResource::OnlyOutput(resource) = *self {
+        if let PassResource::OnlyOutput(resource, _) = *self {
             resource.is_none()
         } else {
             false
@@ -36,21 +51,90 @@ impl PassResource {
 
     pub fn resource_handle(&self) -> Option<ResourceHandle> {
         match *self {
-            PassResource::OnlyOutput(resource) => resource,
+            PassResource::OnlyOutput(resource, _) => resource,
             PassResource::OnlyInput(resource) => Some(resource),
             PassResource::InputAndOutput(resource) => Some(resource)
         }
     }
+
+    /// Name to give the resource this creates, if any
+    pub fn new_resource_name(&self) -> Option<&'pass str> {
+        match *self {
+            PassResource::OnlyOutput(None, name) => name,
+            _ => None
+        }
+    }
+}
+
+/// Face culling override for a single pass, replacing the pipeline default
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CullMode {
+    None,
+    Front,
+    Back
+}
+
+impl CullMode {
+    pub fn face(&self) -> Option<wgpu::Face> {
+        match self {
+            CullMode::None => None,
+            CullMode::Front => Some(wgpu::Face::Front),
+            CullMode::Back => Some(wgpu::Face::Back)
+        }
+    }
+}
+
+/// What a colour attachment holds at the start of a pass
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum LoadOp {
+    /// Keep whatever was last written
+    Load,
+    /// Clear to the colour, e.g. a background colour for the surface
+    Clear(wgpu::Color)
+}
+
+impl LoadOp {
+    pub fn wgpu(&self) -> wgpu::LoadOp<wgpu::Color> {
+        match *self {
+            LoadOp::Load => wgpu::LoadOp::Load,
+            LoadOp::Clear(colour) => wgpu::LoadOp::Clear(colour)
+        }
+    }
+}
+
+/// Region of the attachments a pass may draw to, in pixels from the top left
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScissorRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32
 }
 
 #[derive(Clone)]
 pub struct RenderPassBuilder<'pass> {
     pub label: Option<&'pass str>,
-    pub colour_attachments: Vec<PassResource>,
-    pub depth_stencil: Option<PassResource>,
-    pub vertex_buffer: Option<PassResource>,
-    pub index_buffer: Option<PassResource>,
-    pub pipeline: PipelineHandle,
+    pub colour_attachments: Vec<PassResource<'pass>>,
+    pub depth_stencil: Option<PassResource<'pass>>,
+    pub vertex_buffer: Option<PassResource<'pass>>,
+    pub index_buffer: Option<PassResource<'pass>>,
+    /// Per-instance vertex buffer and the slot it is bound to
+    pub instance_buffer: Option<(PassResource<'pass>, u32)>,
+    pub instance_count: u32,
+    /// Textures written by earlier passes which this pass samples. They fill the bind
+    /// group after the pipeline layout's own in order, each taking a texture binding
+    /// followed by a sampler binding
+    pub sampled_textures: Vec<PassResource<'pass>>,
+    /// How `sampled_textures` are filtered and addressed
+    pub sampler: SamplerDesc,
+    pub cull_mode: Option<CullMode>,
+    pub load_ops: Vec<(ResourceHandle, LoadOp)>,
+    /// Colour every attachment is cleared to when no load op overrides it
+    pub clear_colour: Option<wgpu::Color>,
+    /// Drawing is clipped to this region when set. It must lie within the attachments
+    pub scissor: Option<ScissorRect>,
+    /// `None` for passes which only clear their attachments and never draw
+    pub pipeline: Option<PipelineHandle>,
 }
 
 impl<'pass> RenderPassBuilder<'pass> {
@@ -61,32 +145,148 @@ impl<'pass> RenderPassBuilder<'pass> {
             depth_stencil: None,
             vertex_buffer: None,
             index_buffer: None,
-            pipeline
+            instance_buffer: None,
+            instance_count: 1,
+            sampled_textures: Vec::new(),
+            sampler: SamplerDesc::default(),
+            cull_mode: None,
+            load_ops: Vec::new(),
+            clear_colour: None,
+            scissor: None,
+            pipeline: Some(pipeline)
+        }
+    }
+
+    /// A pass with no pipeline which clears `target` to `colour` without drawing
+    pub fn clear_pass(target: PassResource<'pass>, colour: wgpu::Color) -> Self {
+        RenderPassBuilder {
+            label: None,
+            colour_attachments: vec![target],
+            depth_stencil: None,
+            vertex_buffer: None,
+            index_buffer: None,
+            instance_buffer: None,
+            instance_count: 1,
+            sampled_textures: Vec::new(),
+            sampler: SamplerDesc::default(),
+            cull_mode: None,
+            load_ops: Vec::new(),
+            clear_colour: Some(colour),
+            scissor: None,
+            pipeline: None
         }
     }
 
+    pub fn is_clear_pass(&self) -> bool {
+        self.pipeline.is_none()
+    }
+
+    /// The same pass drawn with `pipeline` instead, keeping every attachment and setting
+    pub fn with_pipeline(mut self, pipeline: PipelineHandle) -> Self {
+        self.pipeline = Some(pipeline);
+        self
+    }
+
     pub fn label(mut self, label: &'pass str) -> Self {
         self.label = Some(label);
         self
     }
 
-    pub fn add_colour_attachment(mut self, attachment: PassResource) -> Self {
+    pub fn add_colour_attachment(mut self, attachment: PassResource<'pass>) -> Self {
         self.colour_attachments.push(attachment);
         self
     }
 
-    pub fn set_depth_stencil_attachment(mut self, depth_stencil: PassResource) -> Self {
+    pub fn set_depth_stencil_attachment(mut self, depth_stencil: PassResource<'pass>) -> Self {
         self.depth_stencil = Some(depth_stencil);
         self
     }
 
-    pub fn set_vertex_buffer(mut self, vertex_buffer: PassResource) -> Self {
+    pub fn set_vertex_buffer(mut self, vertex_buffer: PassResource<'pass>) -> Self {
         self.vertex_buffer = Some(vertex_buffer);
         self
     }
 
-    pub fn set_index_buffer(mut self, index_buffer: PassResource) -> Self {
+    pub fn set_index_buffer(mut self, index_buffer: PassResource<'pass>) -> Self {
         self.index_buffer = Some(index_buffer);
         self
     }
+
+    /// Binds `instance_buffer` as a per-instance vertex buffer at `slot`
+    pub fn set_instance_buffer(mut self, instance_buffer: PassResource<'pass>, slot: u32) -> Self {
+        self.instance_buffer = Some((instance_buffer, slot));
+        self
+    }
+
+    /// Samples `texture`, usually the output of an earlier pass, in this pass's shaders
+    pub fn sample_texture(mut self, texture: ResourceHandle) -> Self {
+        self.sampled_textures.push(PassResource::OnlyInput(texture));
+        self
+    }
+
+    /// Filtering and addressing for every texture this pass samples
+    pub fn sampler(mut self, sampler: SamplerDesc) -> Self {
+        self.sampler = sampler;
+        self
+    }
+
+    pub fn instances(mut self, count: u32) -> Self {
+        self.instance_count = count;
+        self
+    }
+
+    /// Every resource the pass reads or writes, in the order the graph visits them
+    pub fn resources_mut(&mut self) -> impl Iterator<Item = &mut PassResource<'pass>> {
+        self.colour_attachments.iter_mut()
+            .chain(self.depth_stencil.iter_mut())
+            .chain(self.vertex_buffer.iter_mut())
+            .chain(self.index_buffer.iter_mut())
+            .chain(self.instance_buffer.iter_mut().map(|(instance_buffer, _)| instance_buffer))
+            .chain(self.sampled_textures.iter_mut())
+    }
+
+    /// Vertex and instance ranges drawn by this pass
+    pub fn draw_ranges(&self) -> (Range<u32>, Range<u32>) {
+        (0..3, 0..self.instance_count)
+    }
+
+    /// Clips drawing to the `width` by `height` pixel region at `(x, y)`
+    pub fn scissor(mut self, x: u32, y: u32, width: u32, height: u32) -> Self {
+        self.scissor = Some(ScissorRect { x, y, width, height });
+        self
+    }
+
+    pub fn cull_mode(mut self, cull_mode: CullMode) -> Self {
+        self.cull_mode = Some(cull_mode);
+        self
+    }
+
+    /// Overrides the load op of the colour attachment writing to `resource`, leaving
+    /// every other attachment with its own
+    pub fn colour_load_op(mut self, resource: ResourceHandle, load_op: LoadOp) -> Self {
+        self.load_ops.retain(|(handle, _)| *handle != resource);
+        self.load_ops.push((resource, load_op));
+        self
+    }
+
+    /// Operations for the colour attachment writing to `resource`, replacing the load op
+    /// of `operations` if this pass overrides it or clears all of its attachments
+    pub fn colour_operations(
+        &self,
+        resource: ResourceHandle,
+        operations: wgpu::Operations<wgpu::Color>
+    ) -> wgpu::Operations<wgpu::Color> {
+        let load_op = self.load_ops.iter()
+            .find(|(handle, _)| *handle == resource)
+            .map(|(_, load_op)| *load_op)
+            .or(self.clear_colour.map(LoadOp::Clear));
+
+        match load_op {
+            Some(load_op) => wgpu::Operations {
+                load: load_op.wgpu(),
+                ..operations
+            },
+            None => operations
+        }
+    }
 }
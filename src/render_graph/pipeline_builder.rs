@@ -1,13 +1,27 @@
+use std::num::NonZeroU32;
 use crate::render;
+use serde::{ Serialize, Deserialize };
+use thiserror::Error;
 pub use crate::render_graph::handle_map::Handle as PipelineHandle;
 
-#[derive(Debug, Copy, Clone)]
-struct BindGroupData {
+#[derive(Debug, Error, PartialEq)]
+pub enum LayoutError {
+    #[error("Shader expects {expected} bind groups but the layout configures {configured}")]
+    BindGroupCountMismatch {
+        expected: usize,
+        configured: usize
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BindGroupData {
     visibility: VisibilityBuilder,
-    binding: wgpu::BindingType
+    binding: wgpu::BindingType,
+    /// Number of elements when the binding is an array
+    count: Option<NonZeroU32>
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
 pub struct VisibilityBuilder {
     visibility_bits: u32
 }
@@ -56,11 +70,45 @@ impl<'binding> BindGroupLayoutBuilder<'binding> {
     pub fn add_binding(mut self, visibility: VisibilityBuilder, binding: wgpu::BindingType) -> Self {
         self.bindings.push(BindGroupData {
             visibility,
-            binding
+            binding,
+            count: None
+        });
+        self
+    }
+
+    /// Adds an array of `count` bindings of the same type, such as the textures of an
+    /// atlas. Binding arrays need the adapter to support the matching feature
+    pub fn add_binding_array(mut self, visibility: VisibilityBuilder, binding: wgpu::BindingType, count: NonZeroU32) -> Self {
+        self.bindings.push(BindGroupData {
+            visibility,
+            binding,
+            count: Some(count)
         });
         self
     }
 
+    /// Adds a uniform buffer binding sized for a `render::UniformBuffer<T>`
+    pub fn add_uniform_buffer<T: bytemuck::Pod>(self, visibility: VisibilityBuilder) -> Self {
+        self.add_binding(visibility, render::UniformBuffer::<T>::binding_type())
+    }
+
+    /// Adds a uniform buffer binding for a `render::UniformBuffer<T>` which is selected
+    /// with a dynamic offset when the bind group is set
+    pub fn add_dynamic_uniform_buffer<T: bytemuck::Pod>(self, visibility: VisibilityBuilder) -> Self {
+        self.add_binding(visibility, render::UniformBuffer::<T>::dynamic_binding_type())
+    }
+
+    /// Adds a filterable 2D texture binding followed by the sampler used to read it, as
+    /// bound for `RenderPassBuilder::sample_texture`
+    pub fn add_sampled_texture(self, visibility: VisibilityBuilder) -> Self {
+        self.add_binding(visibility, wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false
+            })
+            .add_binding(visibility, wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering))
+    }
+
     pub fn label(mut self, label: &'binding str) -> Self {
         self.label = Some(label);
         self
@@ -73,7 +121,7 @@ impl<'binding> BindGroupLayoutBuilder<'binding> {
                 binding: index as u32,
                 visibility: binding.visibility.build(),
                 ty: binding.binding,
-                count: None,
+                count: binding.count,
             })
         .collect();
 
@@ -84,17 +132,196 @@ impl<'binding> BindGroupLayoutBuilder<'binding> {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DepthStencilBuilder {
+    format: wgpu::TextureFormat,
+    depth_compare: wgpu::CompareFunction,
+    depth_write_enabled: bool
+}
+
+impl DepthStencilBuilder {
+    pub fn depth_stencil(format: wgpu::TextureFormat) -> Self {
+        DepthStencilBuilder {
+            format,
+            depth_compare: wgpu::CompareFunction::Less,
+            depth_write_enabled: true
+        }
+    }
+
+    pub fn depth_compare(mut self, depth_compare: wgpu::CompareFunction) -> Self {
+        self.depth_compare = depth_compare;
+        self
+    }
+
+    pub fn depth_write_enabled(mut self, depth_write_enabled: bool) -> Self {
+        self.depth_write_enabled = depth_write_enabled;
+        self
+    }
+
+    pub fn build(self) -> wgpu::DepthStencilState {
+        wgpu::DepthStencilState {
+            format: self.format,
+            depth_write_enabled: self.depth_write_enabled,
+            depth_compare: self.depth_compare,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default()
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MultisampleBuilder {
+    count: u32,
+    mask: u64,
+    alpha_to_coverage_enabled: bool
+}
+
+impl MultisampleBuilder {
+    /// Multisampling with `count` samples per pixel, all of them enabled
+    pub fn multisample(count: u32) -> Self {
+        MultisampleBuilder {
+            count,
+            mask: !0,
+            alpha_to_coverage_enabled: false
+        }
+    }
+
+    /// Bitmask of which samples are written
+    pub fn mask(mut self, mask: u64) -> Self {
+        self.mask = mask;
+        self
+    }
+
+    /// Derive sample coverage from the fragment's alpha, for alpha-tested geometry
+    pub fn alpha_to_coverage_enabled(mut self, alpha_to_coverage_enabled: bool) -> Self {
+        self.alpha_to_coverage_enabled = alpha_to_coverage_enabled;
+        self
+    }
+
+    pub fn build(self) -> wgpu::MultisampleState {
+        wgpu::MultisampleState {
+            count: self.count,
+            mask: self.mask,
+            alpha_to_coverage_enabled: self.alpha_to_coverage_enabled
+        }
+    }
+}
+
+/// Vertex buffer layout whose attributes are packed in the order they are added
+#[derive(Debug, Clone, PartialEq)]
+pub struct VertexLayoutBuilder {
+    step_mode: wgpu::VertexStepMode,
+    attributes: Vec<wgpu::VertexAttribute>,
+    stride: wgpu::BufferAddress
+}
+
+impl VertexLayoutBuilder {
+    pub fn vertex() -> Self {
+        VertexLayoutBuilder {
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: Vec::new(),
+            stride: 0
+        }
+    }
+
+    /// Layout which advances once per instance rather than once per vertex
+    pub fn instance() -> Self {
+        VertexLayoutBuilder {
+            step_mode: wgpu::VertexStepMode::Instance,
+            ..VertexLayoutBuilder::vertex()
+        }
+    }
+
+    /// Append an attribute directly after the previous one
+    pub fn attribute(mut self, format: wgpu::VertexFormat, shader_location: wgpu::ShaderLocation) -> Self {
+        self.attributes.push(wgpu::VertexAttribute {
+            format,
+            offset: self.stride,
+            shader_location
+        });
+        self.stride += format.size();
+        self
+    }
+
+    /// Leave `bytes` unused after the previous attribute, for padding in the vertex struct
+    pub fn skip(mut self, bytes: wgpu::BufferAddress) -> Self {
+        self.stride += bytes;
+        self
+    }
+
+    pub fn stride(&self) -> wgpu::BufferAddress {
+        self.stride
+    }
+
+    pub fn build(&self) -> wgpu::VertexBufferLayout<'_> {
+        wgpu::VertexBufferLayout {
+            array_stride: self.stride,
+            step_mode: self.step_mode,
+            attributes: &self.attributes
+        }
+    }
+}
+
+/// Owned form of a `BindGroupLayoutBuilder`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BindGroupLayoutDescription {
+    pub label: Option<String>,
+    pub bindings: Vec<BindGroupData>
+}
+
+impl BindGroupLayoutBuilder<'_> {
+    pub fn describe(&self) -> BindGroupLayoutDescription {
+        BindGroupLayoutDescription {
+            label: self.label.map(|label| label.to_string()),
+            bindings: self.bindings.clone()
+        }
+    }
+}
+
+impl BindGroupLayoutDescription {
+    pub fn builder(&self) -> BindGroupLayoutBuilder<'_> {
+        BindGroupLayoutBuilder {
+            label: self.label.as_deref(),
+            bindings: self.bindings.clone()
+        }
+    }
+}
+
+/// Owned form of a `PipelineLayoutBuilder`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PipelineLayoutDescription {
+    pub label: Option<String>,
+    pub bind_group: Option<BindGroupLayoutDescription>,
+    pub depth_stencil: Option<DepthStencilBuilder>,
+    pub multisample: Option<MultisampleBuilder>
+}
+
+impl PipelineLayoutDescription {
+    pub fn builder(&self) -> PipelineLayoutBuilder<'_> {
+        PipelineLayoutBuilder {
+            label: self.label.as_deref(),
+            bind_group: self.bind_group.as_ref().map(|bind_group| bind_group.builder()),
+            depth_stencil: self.depth_stencil,
+            multisample: self.multisample
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PipelineLayoutBuilder<'layout> {
     label: Option<&'layout str>,
-    bind_group: Option<BindGroupLayoutBuilder<'layout>>
+    bind_group: Option<BindGroupLayoutBuilder<'layout>>,
+    depth_stencil: Option<DepthStencilBuilder>,
+    multisample: Option<MultisampleBuilder>
 }
 
 impl<'layout> PipelineLayoutBuilder<'layout> {
     pub fn layout() -> Self {
         PipelineLayoutBuilder {
             label: None,
-            bind_group: None
+            bind_group: None,
+            depth_stencil: None,
+            multisample: None
         }
     }
 
@@ -108,6 +335,52 @@ impl<'layout> PipelineLayoutBuilder<'layout> {
         self
     }
 
+    pub fn depth_stencil(mut self, depth_stencil: DepthStencilBuilder) -> Self {
+        self.depth_stencil = Some(depth_stencil);
+        self
+    }
+
+    pub fn multisample(mut self, multisample: MultisampleBuilder) -> Self {
+        self.multisample = Some(multisample);
+        self
+    }
+
+    pub fn describe(&self) -> PipelineLayoutDescription {
+        PipelineLayoutDescription {
+            label: self.label.map(|label| label.to_string()),
+            bind_group: self.bind_group.as_ref().map(|bind_group| bind_group.describe()),
+            depth_stencil: self.depth_stencil,
+            multisample: self.multisample
+        }
+    }
+
+    pub fn depth_stencil_state(&self) -> Option<wgpu::DepthStencilState> {
+        self.depth_stencil.map(|depth_stencil| depth_stencil.build())
+    }
+
+    /// Multisample state for the pipeline, which is a single sample if none was set
+    pub fn multisample_state(&self) -> wgpu::MultisampleState {
+        self.multisample.unwrap_or(MultisampleBuilder::multisample(1)).build()
+    }
+
+    /// Number of bind groups the built layout will contain
+    pub fn bind_group_count(&self) -> usize {
+        usize::from(self.bind_group.is_some())
+    }
+
+    /// Builds the layout, failing if it does not have exactly the `expected_groups` bind
+    /// groups the shader declares
+    pub fn build_checked(self, expected_groups: usize) -> Result<render::PipelineLayout<'layout>, LayoutError> {
+        let configured = self.bind_group_count();
+        if configured != expected_groups {
+            return Err(LayoutError::BindGroupCountMismatch {
+                expected: expected_groups,
+                configured
+            })
+        }
+        Ok(self.build())
+    }
+
     pub fn build(self) -> render::PipelineLayout<'layout> {
         render::PipelineLayout {
             label: self.label,
@@ -117,3 +390,134 @@ impl<'layout> PipelineLayoutBuilder<'layout> {
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_depth_stencil_defaults() {
+        let state = DepthStencilBuilder::depth_stencil(wgpu::TextureFormat::Depth32Float).build();
+        assert_eq!(state.depth_compare, wgpu::CompareFunction::Less);
+        assert!(state.depth_write_enabled);
+        assert!(PipelineLayoutBuilder::layout().depth_stencil_state().is_none());
+    }
+
+    #[test]
+    fn test_read_only_depth_pipeline() {
+        let layout = PipelineLayoutBuilder::layout()
+            .depth_stencil(
+                DepthStencilBuilder::depth_stencil(wgpu::TextureFormat::Depth32Float)
+                    .depth_compare(wgpu::CompareFunction::LessEqual)
+                    .depth_write_enabled(false)
+            );
+
+        let state = layout.depth_stencil_state().unwrap();
+        assert_eq!(state.format, wgpu::TextureFormat::Depth32Float);
+        assert_eq!(state.depth_compare, wgpu::CompareFunction::LessEqual);
+        assert!(!state.depth_write_enabled);
+    }
+
+    #[test]
+    fn test_alpha_to_coverage_pipeline() {
+        assert_eq!(PipelineLayoutBuilder::layout().multisample_state(), wgpu::MultisampleState {
+            count: 1,
+            mask: !0,
+            alpha_to_coverage_enabled: false
+        });
+
+        let layout = PipelineLayoutBuilder::layout()
+            .multisample(
+                MultisampleBuilder::multisample(4)
+                    .mask(0b1011)
+                    .alpha_to_coverage_enabled(true)
+            );
+
+        let state = layout.multisample_state();
+        assert_eq!(state.count, 4);
+        assert_eq!(state.mask, 0b1011);
+        assert!(state.alpha_to_coverage_enabled);
+        assert_eq!(layout.describe().builder().multisample_state(), state);
+    }
+
+    #[test]
+    fn test_vertex_layout() {
+        let builder = VertexLayoutBuilder::vertex()
+            .attribute(wgpu::VertexFormat::Float32x2, 0)
+            .attribute(wgpu::VertexFormat::Float32x4, 1);
+        let layout = builder.build();
+
+        let offsets: Vec<_> = layout.attributes.iter().map(|attribute| attribute.offset).collect();
+        assert_eq!(offsets, vec![0, 8]);
+        assert_eq!(layout.array_stride, 24);
+        assert_eq!(layout, render::QuadVertex::layout());
+
+        let padded = VertexLayoutBuilder::instance()
+            .attribute(wgpu::VertexFormat::Float32x3, 2)
+            .skip(4)
+            .attribute(wgpu::VertexFormat::Uint32, 3);
+        assert_eq!(padded.build().attributes[1].offset, 16);
+        assert_eq!(padded.stride(), 20);
+        assert_eq!(padded.build().step_mode, wgpu::VertexStepMode::Instance);
+    }
+
+    #[test]
+    fn test_add_uniform_buffer() {
+        let layout = BindGroupLayoutBuilder::binding()
+            .add_uniform_buffer::<[f32; 16]>(VisibilityBuilder::visibility().vertex())
+            .build();
+
+        assert_eq!(layout.entries.len(), 1);
+        assert_eq!(layout.entries[0].visibility, wgpu::ShaderStages::VERTEX);
+        assert!(matches!(
+            layout.entries[0].ty,
+            wgpu::BindingType::Buffer { ty: wgpu::BufferBindingType::Uniform, .. }
+        ));
+    }
+
+    #[test]
+    fn test_binding_array() {
+        let texture = wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false
+        };
+        let builder = BindGroupLayoutBuilder::binding()
+            .add_binding_array(VisibilityBuilder::visibility().fragment(), texture, NonZeroU32::new(16).unwrap())
+            .add_binding(VisibilityBuilder::visibility().fragment(), wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering))
+            .add_dynamic_uniform_buffer::<[f32; 4]>(VisibilityBuilder::visibility().vertex());
+        assert_eq!(builder.describe().builder().describe(), builder.describe());
+
+        let layout = builder.build();
+        assert_eq!(layout.entries[0].count, NonZeroU32::new(16));
+        assert_eq!(layout.entries[0].ty, texture);
+        assert_eq!(layout.entries[1].count, None);
+        assert!(matches!(
+            layout.entries[2].ty,
+            wgpu::BindingType::Buffer { has_dynamic_offset: true, .. }
+        ));
+    }
+
+    #[test]
+    fn test_build_checked() {
+        let missing = PipelineLayoutBuilder::layout().build_checked(1);
+        assert_eq!(missing.err(), Some(LayoutError::BindGroupCountMismatch {
+            expected: 1,
+            configured: 0
+        }));
+
+        let layout = PipelineLayoutBuilder::layout()
+            .bind_group(
+                BindGroupLayoutBuilder::binding()
+                    .add_binding(
+                        VisibilityBuilder::visibility().fragment(),
+                        wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering)
+                    )
+            )
+            .build_checked(1)
+            .unwrap();
+        assert_eq!(layout.binding_group.unwrap().entries.len(), 1);
+
+        assert!(PipelineLayoutBuilder::layout().build_checked(0).is_ok());
+    }
+}
@@ -1,4 +1,5 @@
 use uuid::Uuid;
+use serde::{ Serialize, Deserialize };
 pub use crate::render_graph::handle_map::Handle as ResourceHandle;
 
 #[derive(Debug, Copy, Clone)]
@@ -23,6 +24,13 @@ impl Id<'_> {
     }
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DynamicResourceDesc {
+    pub width: u32,
+    pub height: u32,
+    pub format: wgpu::TextureFormat
+}
+
 #[derive(Debug, Copy, Clone)]
 pub enum Resource<'resource> {
     Persistent(Id<'resource>),
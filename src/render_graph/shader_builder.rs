@@ -1,9 +1,14 @@
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{ HashMap, HashSet };
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{ Hash, Hasher };
+use std::path::{ Path, PathBuf };
+use serde::{ Serialize, Deserialize };
+use thiserror::Error;
 use crate::render_graph::resource::ResourceHandle;
 pub use crate::render_graph::handle_map::Handle as ShaderHandle;
 
-#[derive(Eq, PartialEq, Hash)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
 pub enum ShaderStage {
     Vertex,
     Fragment,
@@ -23,7 +28,7 @@ impl ShaderStageInputs {
     }
 
     pub fn finish(mut self) -> ShaderRepresentation {
-        self.representation.stages.insert(self.stage, self.inputs).unwrap();
+        self.representation.stages.insert(self.stage, self.inputs);
         self.representation
     }
 }
@@ -39,6 +44,10 @@ impl ShaderRepresentation {
         }
     }
 
+    pub fn stages(&self) -> &HashMap<ShaderStage, Vec<ResourceHandle>> {
+        &self.stages
+    }
+
     pub fn add_stage(self, stage: ShaderStage) -> ShaderStageInputs {
         ShaderStageInputs {
             stage,
@@ -79,6 +88,69 @@ impl<'shader, S> ShaderBuilder<'shader, S> where
             source: self.shader.build()
         }
     }
+
+    /// Hash of the built source and the label, equal for builders which would compile to
+    /// the same module. Sources other than WGSL are hashed through their `Debug` form
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.label.hash(&mut hasher);
+        match self.shader.build() {
+            wgpu::ShaderSource::Wgsl(source) => source.hash(&mut hasher),
+            _ => format!("{:?}", self.shader).hash(&mut hasher)
+        }
+        hasher.finish()
+    }
+}
+
+/// Owns compiled shader modules, keyed by `ShaderBuilder::content_hash`, so each shader
+/// is only compiled once
+pub struct ShaderCache<M = wgpu::ShaderModule> {
+    modules: HashMap<u64, M>
+}
+
+impl<M> ShaderCache<M> {
+    pub fn new() -> ShaderCache<M> {
+        ShaderCache {
+            modules: HashMap::new()
+        }
+    }
+
+    pub fn get_or_compile(&mut self, key: u64, compile: impl FnOnce() -> M) -> &M {
+        self.modules.entry(key).or_insert_with(compile)
+    }
+
+    /// A module compiled earlier, without compiling it if it is missing
+    pub fn module(&self, key: u64) -> Option<&M> {
+        self.modules.get(&key)
+    }
+
+    pub fn contains(&self, key: u64) -> bool {
+        self.modules.contains_key(&key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.modules.len()
+    }
+}
+
+impl ShaderCache<wgpu::ShaderModule> {
+    pub fn get<'shader, S>(&mut self, device: &wgpu::Device, shader: &ShaderBuilder<'shader, S>) -> &wgpu::ShaderModule where
+        S: ShaderSource<'shader> + std::fmt::Debug + Clone {
+        self.get_or_compile(shader.content_hash(), || device.create_shader_module(shader.build()))
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum IncludeError {
+    #[error("Could not read {}: {source}", path.display())]
+    Io {
+        path: PathBuf,
+        source: std::io::Error
+    },
+    #[error("Include \"{0}\" was not found in any include directory")]
+    NotFound(String),
+    #[error("Include cycle: {}", .0.iter().map(|path| path.display().to_string()).collect::<Vec<_>>().join(" -> "))]
+    Cycle(Vec<PathBuf>)
 }
 
 #[derive(Debug, Clone)]
@@ -93,6 +165,8 @@ impl<'shader> ShaderSource<'shader> for WgslBuilder<'shader> {
 }
 
 impl WgslBuilder<'_> {
+    const INCLUDE_DIRECTIVE: &'static str = "// #include";
+
     pub fn from_file<'s, P: AsRef<std::path::Path>>(source: P) -> WgslBuilder<'s> {
         let file_as_string = std::fs::read_to_string(source).unwrap();
         WgslBuilder {
@@ -105,4 +179,189 @@ impl WgslBuilder<'_> {
             source: Cow::from(source)
         }
     }
+
+    /// Parses the source before accepting it, so syntax errors are reported here rather
+    /// than when the pipeline is created
+    pub fn from_buffer_validated<'s>(source: &'s str) -> Result<WgslBuilder<'s>, String> {
+        naga::front::wgsl::parse_str(source)
+            .map_err(|error| error.emit_to_string(source))?;
+        Ok(WgslBuilder::from_buffer(source))
+    }
+
+    /// Loads the file, replacing each `// #include "name.wgsl"` line with the contents
+    /// of that file. Includes are looked up next to the including file, then in each of
+    /// `include_dirs` in order. A file included more than once is only pasted the first
+    /// time
+    pub fn from_file_with_includes<'s, P: AsRef<Path>>(source: P, include_dirs: &[PathBuf]) -> Result<WgslBuilder<'s>, IncludeError> {
+        let mut included = HashSet::new();
+        let mut include_stack = Vec::new();
+        let source = Self::resolve_includes(source.as_ref(), include_dirs, &mut included, &mut include_stack)?;
+        Ok(WgslBuilder {
+            source: Cow::Owned(source)
+        })
+    }
+
+    fn resolve_includes(
+        path: &Path,
+        include_dirs: &[PathBuf],
+        included: &mut HashSet<PathBuf>,
+        include_stack: &mut Vec<PathBuf>
+    ) -> Result<String, IncludeError> {
+        let read_error = |source| IncludeError::Io { path: path.to_path_buf(), source };
+        let path = path.canonicalize().map_err(read_error)?;
+
+        if include_stack.contains(&path) {
+            include_stack.push(path);
+            return Err(IncludeError::Cycle(include_stack.clone()))
+        }
+        if !included.insert(path.clone()) {
+            return Ok(String::new())
+        }
+
+        let source = std::fs::read_to_string(&path).map_err(read_error)?;
+        include_stack.push(path.clone());
+
+        let mut output = String::with_capacity(source.len());
+        for line in source.lines() {
+            match Self::include_target(line) {
+                Some(name) => {
+                    let include = Self::find_include(name, path.parent(), include_dirs)?;
+                    output.push_str(&Self::resolve_includes(&include, include_dirs, included, include_stack)?);
+                },
+                None => {
+                    output.push_str(line);
+                    output.push('\n');
+                }
+            }
+        }
+
+        include_stack.pop();
+        Ok(output)
+    }
+
+    /// The file named by an include directive, or `None` if the line is not one
+    fn include_target(line: &str) -> Option<&str> {
+        line.trim()
+            .strip_prefix(Self::INCLUDE_DIRECTIVE)?
+            .trim()
+            .strip_prefix('"')?
+            .strip_suffix('"')
+    }
+
+    fn find_include(name: &str, including_dir: Option<&Path>, include_dirs: &[PathBuf]) -> Result<PathBuf, IncludeError> {
+        including_dir.into_iter()
+            .chain(include_dirs.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(name))
+            .find(|candidate| candidate.is_file())
+            .ok_or_else(|| IncludeError::NotFound(name.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render_graph::handle_map::HandleType;
+
+    #[test]
+    fn test_shader_representation_stages() {
+        let input = ResourceHandle::new();
+        let shader = ShaderRepresentation::shader()
+            .add_stage(ShaderStage::Vertex).finish()
+            .add_stage(ShaderStage::Fragment)
+                .add_input(input)
+            .finish();
+
+        assert_eq!(shader.stages().len(), 2);
+        assert_eq!(shader.stages()[&ShaderStage::Vertex], vec![]);
+        assert_eq!(shader.stages()[&ShaderStage::Fragment], vec![input]);
+    }
+
+    #[test]
+    fn test_shader_cache_compiles_once() {
+        let shader = ShaderBuilder::shader(WgslBuilder::from_buffer("@vertex fn vs_main() {}"))
+            .label("cached");
+        let mut cache = ShaderCache::new();
+        let mut compile_count = 0;
+
+        for _ in 0..2 {
+            cache.get_or_compile(shader.content_hash(), || {
+                compile_count += 1;
+                compile_count
+            });
+        }
+
+        assert_eq!(compile_count, 1);
+        assert_eq!(cache.len(), 1);
+        assert!(cache.contains(shader.content_hash()));
+        assert_eq!(cache.module(shader.content_hash()), Some(&1));
+    }
+
+    #[test]
+    fn test_validated_wgsl() {
+        assert!(WgslBuilder::from_buffer_validated(include_str!("../triangle.wgsl")).is_ok());
+
+        let error = WgslBuilder::from_buffer_validated("@vertex fn vs_main( -> {").unwrap_err();
+        assert!(!error.is_empty());
+    }
+
+    #[test]
+    fn test_content_hash() {
+        let source = "@vertex fn vs_main() {}";
+        let shader = ShaderBuilder::shader(WgslBuilder::from_buffer(source)).label("triangle");
+        let same = ShaderBuilder::shader(WgslBuilder::from_buffer(source)).label("triangle");
+        assert_eq!(shader.content_hash(), same.content_hash());
+
+        let changed = ShaderBuilder::shader(WgslBuilder::from_buffer("@fragment fn fs_main() {}")).label("triangle");
+        assert_ne!(shader.content_hash(), changed.content_hash());
+
+        let relabelled = ShaderBuilder::shader(WgslBuilder::from_buffer(source)).label("quad");
+        assert_ne!(shader.content_hash(), relabelled.content_hash());
+    }
+
+    /// Writes `files` into a fresh directory under the system temp directory
+    fn write_shader_files(files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wgsl_includes_{}", uuid::Uuid::new_v4()));
+        for (name, contents) in files {
+            let path = dir.join(name);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_wgsl_includes() {
+        let dir = write_shader_files(&[
+            ("shaders/main.wgsl", "// #include \"lighting.wgsl\"\n// #include \"common.wgsl\"\n@fragment fn fs_main() -> @location(0) vec4<f32> { return shade(half()); }\n"),
+            ("shaders/lighting.wgsl", "// #include \"common.wgsl\"\nfn shade(x: f32) -> vec4<f32> { return vec4<f32>(x); }\n"),
+            ("shared/common.wgsl", "fn half() -> f32 { return 0.5; }\n")
+        ]);
+
+        let builder = WgslBuilder::from_file_with_includes(dir.join("shaders/main.wgsl"), &[dir.join("shared")]).unwrap();
+        assert_eq!(builder.source.matches("fn half()").count(), 1);
+        assert!(builder.source.find("fn half()") < builder.source.find("fn shade("));
+        assert!(!builder.source.contains("#include"));
+        assert!(WgslBuilder::from_buffer_validated(&builder.source).is_ok());
+
+        let missing = WgslBuilder::from_file_with_includes(dir.join("shaders/main.wgsl"), &[]);
+        assert!(matches!(missing, Err(IncludeError::NotFound(name)) if name == "common.wgsl"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_wgsl_include_cycle() {
+        let dir = write_shader_files(&[
+            ("a.wgsl", "// #include \"b.wgsl\"\n"),
+            ("b.wgsl", "// #include \"a.wgsl\"\n")
+        ]);
+
+        let Err(IncludeError::Cycle(cycle)) = WgslBuilder::from_file_with_includes(dir.join("a.wgsl"), &[]) else {
+            panic!("An include cycle must be an error");
+        };
+        let names: Vec<_> = cycle.iter().map(|path| path.file_name().unwrap().to_str().unwrap()).collect();
+        assert_eq!(names, vec!["a.wgsl", "b.wgsl", "a.wgsl"]);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -5,27 +5,41 @@ pub mod api {
 
 use crate::sparse_set::{ SparseSet, ElementHandle };
 use std::collections::{ BinaryHeap, HashMap, HashSet };
+use std::cmp::Reverse;
 use std::time::{ Instant, Duration };
 use std::sync::{ Arc, RwLock };
 use uuid::Uuid;
 use std::borrow::Cow;
 use std::path::{ Path, PathBuf };
+use serde::{ Serialize, Deserialize };
 
 pub struct ResourceHandle<R> {
     resource_handle: ElementHandle,
+    uuid: Uuid,
     manager: Arc<RwLock<ResourceReferenceManager>>,
     _resource_phantom: std::marker::PhantomData<R>
 }
 
 impl<R> ResourceHandle<R> {
-    fn new(resource_handle: ElementHandle, manager: Arc<RwLock<ResourceReferenceManager>>) -> ResourceHandle<R> {
+    fn new(
+        resource_handle: ElementHandle,
+        uuid: Uuid,
+        manager: Arc<RwLock<ResourceReferenceManager>>
+    ) -> ResourceHandle<R> {
         manager.write().unwrap().activate(resource_handle);
         ResourceHandle {
             resource_handle,
+            uuid,
             manager,
             _resource_phantom: std::marker::PhantomData
         }
     }
+
+    /// Stable identity of the resource. Unlike `==`, which only holds within one
+    /// manager, this can be used to key caches across managers
+    pub fn uuid(&self) -> Uuid {
+        self.uuid
+    }
 }
 
 impl<R> PartialEq for ResourceHandle<R> {
@@ -40,6 +54,7 @@ impl<R> Clone for ResourceHandle<R> {
         self.manager.write().unwrap().activate(self.resource_handle);
         ResourceHandle {
             resource_handle: self.resource_handle,
+            uuid: self.uuid,
             manager: self.manager.clone(),
             _resource_phantom: std::marker::PhantomData
         }
@@ -79,6 +94,44 @@ impl<'s> ResourceMetaData<'s> {
     }
 }
 
+/// Everything needed to recreate a resource through its handler, without its data
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceManifestEntry {
+    pub uuid: Uuid,
+    pub name: Option<String>,
+    pub path: Option<PathBuf>,
+    pub lifetime: ResourceLifetime
+}
+
+/// Time source for resource lifetimes and upkeep intervals. A manual clock only moves
+/// when advanced, so expiry can be stepped through without waiting
+#[derive(Debug, Clone)]
+pub enum Clock {
+    System,
+    Manual(Arc<RwLock<Instant>>)
+}
+
+impl Clock {
+    /// A manual clock starting at the current time
+    pub fn manual() -> Clock {
+        Clock::Manual(Arc::new(RwLock::new(Instant::now())))
+    }
+
+    pub fn now(&self) -> Instant {
+        match self {
+            Clock::System => Instant::now(),
+            Clock::Manual(now) => *now.read().unwrap()
+        }
+    }
+
+    /// Moves a manual clock forward. Does nothing to the system clock
+    pub fn advance(&self, duration: Duration) {
+        if let Clock::Manual(now) = self {
+            *now.write().unwrap() += duration;
+        }
+    }
+}
+
 pub trait ResourceHandler<R> {
     fn create(&mut self, meta_data: &ResourceMetaData) -> R;
     fn destroy(&mut self, resource: R);
@@ -88,11 +141,14 @@ pub struct ResourceManager<R, H> where
     H: ResourceHandler<R> + Sized {
     last_resource_id: usize,
     resource_id_map: HashMap<Uuid, ElementHandle>,
+    uuid_map: HashMap<ElementHandle, Uuid>,
     name_id_map: HashMap<String, Uuid>,
     path_id_map: HashMap<PathBuf, Uuid>,
     resources: SparseSet<R>,
     resources_being_destroyed: Vec<R>,
     reference_manager: Arc<RwLock<ResourceReferenceManager>>,
+    destroy_budget: usize,
+    last_upkeep: Option<Instant>,
     pub handler: H
 }
 
@@ -108,47 +164,115 @@ impl<R, H> std::ops::Drop for ResourceManager<R, H> where
 
 impl<R, H> ResourceManager<R, H> where
     H: ResourceHandler<R> + Sized {
-    const RESOURCES_TO_DESTROY_PER_UPKEEP: usize = 10;
+    const DEFAULT_RESOURCES_TO_DESTROY_PER_UPKEEP: usize = 10;
     pub fn new<const MAX_RESOURCES: usize>(
         handler: H
+    ) -> ResourceManager<R, H> {
+        Self::new_with_destroy_budget::<MAX_RESOURCES>(handler, Self::DEFAULT_RESOURCES_TO_DESTROY_PER_UPKEEP)
+    }
+
+    /// Creates a manager which destroys at most `destroy_budget` expired resources
+    /// each upkeep
+    pub fn new_with_destroy_budget<const MAX_RESOURCES: usize>(
+        handler: H,
+        destroy_budget: usize
     ) -> ResourceManager<R, H> {
         let mut resources_being_destroyed = Vec::new();
         resources_being_destroyed.reserve_exact(MAX_RESOURCES);
         ResourceManager {
             last_resource_id: 0,
             resource_id_map: HashMap::new(),
+            uuid_map: HashMap::new(),
             name_id_map: HashMap::new(),
             path_id_map: HashMap::new(),
             resources: SparseSet::new(MAX_RESOURCES),
             resources_being_destroyed,
-            reference_manager: Arc::new(RwLock::new(ResourceReferenceManager::new())),
+            reference_manager: Arc::new(RwLock::new(ResourceReferenceManager::new(Clock::System))),
+            destroy_budget,
+            last_upkeep: None,
             handler,
         }
     }
 
+    /// Measures lifetimes and upkeep intervals with `clock` instead of the system clock
+    pub fn with_clock(self, clock: Clock) -> ResourceManager<R, H> {
+        self.reference_manager.write().unwrap().clock = clock;
+        self
+    }
+
     fn create_resource_handle(&self, element: ElementHandle) -> api::Resource<R> {
-        api::Resource::new(element, self.reference_manager.clone())
+        api::Resource::new(element, self.uuid_map[&element], self.reference_manager.clone())
     }
 
     pub fn upkeep(&mut self) {
+        self.last_upkeep = Some(self.reference_manager.read().unwrap().clock.now());
         for resource in self.reference_manager.write().unwrap().upkeep() {
             let (_, resource_dropped) = self.resources.remove(resource);
+            self.uuid_map.remove(&resource);
             // The buffer can be overflowed with mass creation and deletion of objects
             // To avoid moves, we will ensure that we can never overrun the buffer by
             // deleting when the buffer is filled
-            if self.resources_being_destroyed.len() == Self::RESOURCES_TO_DESTROY_PER_UPKEEP {
+            if self.resources_being_destroyed.len() == self.resources_being_destroyed.capacity() {
                 self.handler.destroy(resource_dropped.unwrap());
             } else {
                 self.resources_being_destroyed.push(resource_dropped.unwrap());
             }
         }
 
-        for _ in 0..Self::RESOURCES_TO_DESTROY_PER_UPKEEP.min(self.resources_being_destroyed.len()) {
+        for _ in 0..self.destroy_budget.min(self.resources_being_destroyed.len()) {
             let resource = self.resources_being_destroyed.pop().unwrap();
             self.handler.destroy(resource);
         }
     }
 
+    /// Runs `upkeep` only if at least `interval` has passed since the last upkeep.
+    /// Returns whether it ran
+    pub fn upkeep_if_elapsed(&mut self, interval: Duration) -> bool {
+        let now = self.reference_manager.read().unwrap().clock.now();
+        let elapsed = self.last_upkeep.is_none_or(|last| now.duration_since(last) >= interval);
+        if elapsed {
+            self.upkeep();
+        }
+        elapsed
+    }
+
+    /// Immediately destroys every resource, leaving the manager as if it were freshly
+    /// created. Handles obtained before the clear are left dangling; ids keep increasing
+    /// so they never resolve to a resource created afterwards
+    pub fn clear(&mut self) {
+        while let Some((_, resource)) = self.resources.remove_at_dense(0) {
+            self.handler.destroy(resource);
+        }
+
+        for resource in self.resources_being_destroyed.drain(..) {
+            self.handler.destroy(resource);
+        }
+
+        self.resource_id_map.clear();
+        self.uuid_map.clear();
+        self.name_id_map.clear();
+        self.path_id_map.clear();
+        self.resources.clear();
+        // Outstanding handles keep the old reference manager alive so dropping them
+        // cannot corrupt the reference counts of new resources
+        let clock = self.reference_manager.read().unwrap().clock.clone();
+        self.reference_manager = Arc::new(RwLock::new(ResourceReferenceManager::new(clock)));
+    }
+
+    /// Prevents the resource from being evicted, even when nothing references it
+    pub fn pin(&mut self, uuid: &Uuid) {
+        let resource_id = *self.resource_id_map.get(uuid).unwrap();
+        self.reference_manager.write().unwrap().pin(resource_id);
+    }
+
+    /// Returns the resource to normal lifetime behaviour. An unreferenced resource will
+    /// be evicted once its lifetime has elapsed from the moment it was unpinned, even if
+    /// it was dropped long before
+    pub fn unpin(&mut self, uuid: &Uuid) {
+        let resource_id = *self.resource_id_map.get(uuid).unwrap();
+        self.reference_manager.write().unwrap().unpin(resource_id);
+    }
+
     pub fn get_from_path<P: AsRef<Path>>(&self, path: P) -> api::Resource<R> {
         let path_buf = path.as_ref().to_path_buf();
         self.get_from_uuid(self.path_id_map.get(&path_buf).unwrap())
@@ -172,6 +296,7 @@ impl<R, H> ResourceManager<R, H> where
         self.last_resource_id += 1;
         let resource_id = ElementHandle(self.last_resource_id);
         self.resource_id_map.insert(meta_resource.uuid, resource_id);
+        self.uuid_map.insert(resource_id, meta_resource.uuid);
         self.resources.push(resource_id, self.handler.create(meta_resource));
 
         if let Some(name) = &meta_resource.name {
@@ -186,12 +311,73 @@ impl<R, H> ResourceManager<R, H> where
         self.create_resource_handle(resource_id)
     }
 
+    /// UUIDs of every tracked resource created with `lifetime`, including ones waiting to
+    /// expire
+    pub fn handles_with_lifetime(&self, lifetime: ResourceLifetime) -> Vec<Uuid> {
+        self.reference_manager.read().unwrap()
+            .resources_with_lifetime(lifetime)
+            .filter_map(|resource| self.uuid_map.get(&resource).copied())
+            .collect()
+    }
+
+    /// Every live resource with its UUID. This does not create handles, so reference
+    /// counts are unaffected
+    pub fn iter(&self) -> impl Iterator<Item = (Uuid, &R)> {
+        self.resources.iter()
+            .map(|(resource_id, resource)| (self.uuid_map[&resource_id], resource))
+    }
+
+    /// Describes every live resource so the set can be recreated with `import_manifest`
+    pub fn export_manifest(&self) -> Vec<ResourceManifestEntry> {
+        let names: HashMap<&Uuid, &String> = self.name_id_map.iter().map(|(name, uuid)| (uuid, name)).collect();
+        let paths: HashMap<&Uuid, &PathBuf> = self.path_id_map.iter().map(|(path, uuid)| (uuid, path)).collect();
+        let reference_manager = self.reference_manager.read().unwrap();
+
+        self.resources.iter()
+            .map(|(resource_id, _)| {
+                let uuid = self.uuid_map[&resource_id];
+                ResourceManifestEntry {
+                    uuid,
+                    name: names.get(&uuid).map(|name| name.to_string()),
+                    path: paths.get(&uuid).map(|path| path.to_path_buf()),
+                    lifetime: reference_manager.all_resources[&resource_id].lifetime
+                }
+            })
+            .collect()
+    }
+
+    /// Recreates each resource in the manifest through the handler, keeping its UUID.
+    /// Resources which already exist are not created again. Returns a handle to every
+    /// entry, in order
+    pub fn import_manifest(&mut self, entries: &[ResourceManifestEntry]) -> Vec<api::Resource<R>> {
+        entries.iter()
+            .map(|entry| {
+                if self.resource_id_map.contains_key(&entry.uuid) {
+                    return self.get_from_uuid(&entry.uuid)
+                }
+
+                self.create(&ResourceMetaData {
+                    uuid: entry.uuid,
+                    lifetime: entry.lifetime,
+                    name: entry.name.as_deref().map(|name| Cow::Owned(name.to_string())),
+                    path: entry.path.clone()
+                })
+            })
+            .collect()
+    }
+
     pub fn resource(&self, handle: api::Resource<R>) -> &R {
         self.resources.get(handle.resource_handle).unwrap()
     }
+
+    /// The resource behind `handle`, or `None` if it was destroyed while the handle was
+    /// still held, such as by `clear`
+    pub fn try_resource(&self, handle: &api::Resource<R>) -> Option<&R> {
+        self.resources.get(handle.resource_handle)
+    }
 }
 
-#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 /// How long the resource lasts after all references run out
 pub enum ResourceLifetime {
     /// Destroyed immediately
@@ -203,13 +389,19 @@ pub enum ResourceLifetime {
     /// We expect the resource to be loaded again
     Long,
     /// Never destroyed until the main resource manager is dropped
-    Forever
+    Forever,
+    /// Destroyed once the given duration has passed
+    Custom(Duration)
 }
 
 struct ResourceReferenceManager {
     all_resources: HashMap<ElementHandle, ResourceReference>,
     active_resources: HashSet<ResourceReference>,
-    inactive_resources: BinaryHeap<ResourceReference>
+    // Reversed so the soonest deletion is at the top of the heap
+    inactive_resources: BinaryHeap<Reverse<ResourceReference>>,
+    pinned_resources: HashSet<ElementHandle>,
+    lifetimes: [(ResourceLifetime, Duration); 5],
+    clock: Clock
 }
 
 impl ResourceReferenceManager {
@@ -221,11 +413,14 @@ impl ResourceReferenceManager {
         (ResourceLifetime::Forever, Duration::MAX)
     ];
 
-    fn new() -> ResourceReferenceManager {
+    fn new(clock: Clock) -> ResourceReferenceManager {
         ResourceReferenceManager {
             all_resources: HashMap::new(),
             active_resources: HashSet::new(),
-            inactive_resources: BinaryHeap::new()
+            inactive_resources: BinaryHeap::new(),
+            pinned_resources: HashSet::new(),
+            lifetimes: Self::LIFETIMES,
+            clock
         }
     }
 
@@ -238,7 +433,6 @@ impl ResourceReferenceManager {
                 deletion_time: None
             });
         }
-        self.activate(resource);
     }
 
     fn activate(&mut self, resource: ElementHandle) {
@@ -256,29 +450,67 @@ impl ResourceReferenceManager {
 
         if self.all_resources.get(&resource).unwrap().reference_count == 0 {
             self.active_resources.remove(&self.all_resources.get(&resource).unwrap());
-            let resource_prototype = self.all_resources.get(&resource).unwrap();
-            self.inactive_resources.push(ResourceReference {
-                reference_count: resource_prototype.reference_count,
-                resource: resource_prototype.resource,
-                lifetime: resource_prototype.lifetime,
-                deletion_time: Instant::now().checked_add(
-                    *Self::LIFETIMES.iter()
-                        .find(|(lifetime, _)| *lifetime == resource_prototype.lifetime)
-                        .map(|(_, d)| d)
-                    .expect("Lifetime not defined")
-                )
-            });
+            self.schedule_deletion(resource);
+        }
+    }
+
+    /// Queues the resource for deletion once its lifetime has elapsed from now. Only the
+    /// most recent deletion time recorded for a resource is honoured
+    fn schedule_deletion(&mut self, resource: ElementHandle) {
+        let lifetime = self.all_resources.get(&resource).unwrap().lifetime;
+        let deletion_time = self.clock.now().checked_add(self.lifetime_duration(lifetime));
+
+        let resource_prototype = self.all_resources.get_mut(&resource).unwrap();
+        resource_prototype.deletion_time = deletion_time;
+        self.inactive_resources.push(Reverse(*resource_prototype));
+    }
+
+    fn lifetime_duration(&self, lifetime: ResourceLifetime) -> Duration {
+        if let ResourceLifetime::Custom(duration) = lifetime {
+            return duration
+        }
+
+        *self.lifetimes.iter()
+            .find(|(table_lifetime, _)| *table_lifetime == lifetime)
+            .map(|(_, d)| d)
+        .expect("Lifetime not defined")
+    }
+
+    fn resources_with_lifetime(&self, lifetime: ResourceLifetime) -> impl Iterator<Item = ElementHandle> + '_ {
+        self.all_resources.values()
+            .filter(move |reference| reference.lifetime == lifetime)
+            .map(|reference| reference.resource)
+    }
+
+    fn pin(&mut self, resource: ElementHandle) {
+        self.pinned_resources.insert(resource);
+    }
+
+    fn unpin(&mut self, resource: ElementHandle) {
+        if !self.pinned_resources.remove(&resource) {
+            return
+        }
+
+        // The resource may have expired while pinned, so it needs a fresh deletion time
+        if self.all_resources.get(&resource).is_some_and(|r| r.reference_count == 0) {
+            self.schedule_deletion(resource);
         }
     }
 
     fn upkeep(&mut self) -> Vec<ElementHandle> {
         let mut resources_to_delete = Vec::new();
-        let now = Instant::now();
+        let now = self.clock.now();
         while self.inactive_resources.peek().is_some_and(
-            |resource| resource.deletion_time.unwrap() <= now
+            |Reverse(resource)| resource.deletion_time.is_some_and(|deletion_time| deletion_time <= now)
         ) {
-            let resource = self.inactive_resources.peek().unwrap();
-            if !self.active_resources.contains(&resource) {
+            let Reverse(resource) = self.inactive_resources.peek().unwrap();
+            // A resource is queued again each time it is dropped or unpinned, so an entry
+            // whose deletion time has since been replaced is stale
+            let is_latest = self.all_resources.get(&resource.resource)
+                .is_some_and(|latest| latest.deletion_time == resource.deletion_time);
+            if is_latest &&
+                !self.active_resources.contains(resource) &&
+                !self.pinned_resources.contains(&resource.resource) {
                 self.all_resources.remove(&resource.resource);
                 resources_to_delete.push(resource.resource);
             }
@@ -319,13 +551,352 @@ impl Ord for ResourceReference {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
         // If a reference doesnt have any deletion time, then it should always be said to
         // be deleted after one with a valid deletion time
-        if let None = self.deletion_time {
-            std::cmp::Ordering::Greater
-        } else if let None = other.deletion_time {
-            std::cmp::Ordering::Less
-        } else {
-            self.deletion_time.cmp(&other.deletion_time)
+        match (self.deletion_time, other.deletion_time) {
+            (None, None) => std::cmp::Ordering::Equal,
+            (None, Some(_)) => std::cmp::Ordering::Greater,
+            (Some(_), None) => std::cmp::Ordering::Less,
+            (Some(a), Some(b)) => a.cmp(&b)
         }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct CountingHandler {
+        created: usize,
+        destroyed: usize
+    }
+
+    impl ResourceHandler<usize> for CountingHandler {
+        fn create(&mut self, _meta_data: &ResourceMetaData) -> usize {
+            self.created += 1;
+            self.created
+        }
+
+        fn destroy(&mut self, _resource: usize) {
+            self.destroyed += 1;
+        }
+    }
+
+    fn counting_manager() -> ResourceManager<usize, CountingHandler> {
+        ResourceManager::new::<16>(CountingHandler {
+            created: 0,
+            destroyed: 0
+        })
+    }
+
+    #[test]
+    fn test_clear() {
+        let mut manager = counting_manager();
+        let first = ResourceMetaData::new_with_name("first", ResourceLifetime::Forever);
+        let second = ResourceMetaData::new(ResourceLifetime::Short);
+        let first_handle = manager.create(&first);
+        manager.create(&second);
+
+        manager.clear();
+        assert_eq!(manager.handler.destroyed, 2);
+        assert_eq!(manager.resources.len(), 0);
+        assert!(manager.name_id_map.is_empty());
+        assert!(manager.resource_id_map.is_empty());
+        drop(first_handle);
+
+        let third = ResourceMetaData::new_with_name("first", ResourceLifetime::Forever);
+        let third_handle = manager.create(&third);
+        assert_eq!(*manager.resource(third_handle), 3);
+        assert_eq!(*manager.resource(manager.get_from_name("first")), 3);
+    }
+
+    #[test]
+    fn test_clear_does_not_reuse_handles() {
+        let mut manager = counting_manager();
+        let old_handle = manager.create(&ResourceMetaData::new(ResourceLifetime::Forever));
+        manager.clear();
+
+        let new_handle = manager.create(&ResourceMetaData::new(ResourceLifetime::Forever));
+        assert!(old_handle != new_handle);
+        assert_eq!(manager.try_resource(&old_handle), None);
+        assert_eq!(manager.try_resource(&new_handle), Some(&2));
+    }
+
+    #[test]
+    fn test_try_resource() {
+        let mut manager = counting_manager();
+        let handle = manager.create(&ResourceMetaData::new(ResourceLifetime::Forever));
+        assert_eq!(manager.try_resource(&handle), Some(&1));
+
+        manager.clear();
+        assert_eq!(manager.try_resource(&handle), None);
+
+        // Still destroyed once a new resource takes up the freed space
+        manager.create(&ResourceMetaData::new(ResourceLifetime::Forever));
+        assert_eq!(manager.try_resource(&handle), None);
+    }
+
+    #[test]
+    fn test_handle_uuid() {
+        let mut manager = counting_manager();
+        let meta_data = ResourceMetaData::new_with_name("shared", ResourceLifetime::Forever);
+        let created = manager.create(&meta_data);
+        let fetched = manager.get_from_name("shared");
+        let cloned = fetched.clone();
+
+        assert_eq!(created.uuid(), meta_data.uuid);
+        assert_eq!(fetched.uuid(), created.uuid());
+        assert_eq!(cloned.uuid(), created.uuid());
+
+        let other = manager.create(&ResourceMetaData::new(ResourceLifetime::Forever));
+        assert_ne!(other.uuid(), created.uuid());
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut manager = counting_manager();
+        let meta_data: Vec<ResourceMetaData> = (0..3)
+            .map(|_| ResourceMetaData::new(ResourceLifetime::Forever))
+            .collect();
+        let handles: Vec<_> = meta_data.iter().map(|m| manager.create(m)).collect();
+
+        let mut uuids: Vec<Uuid> = manager.iter().map(|(uuid, _)| uuid).collect();
+        let mut expected: Vec<Uuid> = meta_data.iter().map(|m| m.uuid).collect();
+        uuids.sort();
+        expected.sort();
+        assert_eq!(uuids, expected);
+
+        let reference_counts: Vec<u64> = {
+            let reference_manager = manager.reference_manager.read().unwrap();
+            handles.iter()
+                .map(|h| reference_manager.all_resources[&h.resource_handle].reference_count)
+                .collect()
+        };
+        assert_eq!(reference_counts, vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn test_created_handle_is_only_reference() {
+        let mut manager = counting_manager();
+        let handle = manager.create(&ResourceMetaData::new(ResourceLifetime::None));
+
+        manager.upkeep();
+        assert_eq!(manager.handler.destroyed, 0);
+
+        drop(handle);
+        manager.upkeep();
+        assert_eq!(manager.handler.destroyed, 1);
+    }
+
+    #[test]
+    fn test_destroy_budget() {
+        let mut manager = ResourceManager::new_with_destroy_budget::<16>(CountingHandler {
+            created: 0,
+            destroyed: 0
+        }, 1);
+
+        for _ in 0..3 {
+            manager.create(&ResourceMetaData::new(ResourceLifetime::None));
+        }
+
+        for expected_destroyed in 1..=3 {
+            manager.upkeep();
+            assert_eq!(manager.handler.destroyed, expected_destroyed);
+        }
+
+        manager.upkeep();
+        assert_eq!(manager.handler.destroyed, 3);
+    }
+
+    #[test]
+    fn test_destroy_budget_defers_past_default() {
+        // More expire at once than the default budget, but they still fit in the buffer
+        let mut manager = ResourceManager::new_with_destroy_budget::<16>(CountingHandler {
+            created: 0,
+            destroyed: 0
+        }, 1);
+
+        for _ in 0..12 {
+            manager.create(&ResourceMetaData::new(ResourceLifetime::None));
+        }
+
+        manager.upkeep();
+        assert_eq!(manager.handler.destroyed, 1);
+        assert_eq!(manager.resources_being_destroyed.len(), 11);
+    }
+
+    #[test]
+    fn test_pin() {
+        let clock = Clock::manual();
+        let mut manager = counting_manager().with_clock(clock.clone());
+
+        let meta_data = ResourceMetaData::new(ResourceLifetime::Short);
+        let handle = manager.create(&meta_data);
+        manager.pin(&meta_data.uuid);
+        drop(handle);
+
+        clock.advance(Duration::from_secs(4));
+        manager.upkeep();
+        assert_eq!(manager.handler.destroyed, 0);
+        assert_eq!(*manager.resource(manager.get(&meta_data)), 1);
+
+        manager.unpin(&meta_data.uuid);
+        clock.advance(Duration::from_secs(4));
+        manager.upkeep();
+        assert_eq!(manager.handler.destroyed, 1);
+    }
+
+    #[test]
+    fn test_unpin_restarts_lifetime() {
+        let clock = Clock::manual();
+        let mut manager = counting_manager().with_clock(clock.clone());
+
+        // Dropped at 0s and due at 3s, but pinned until 2s, so it is due at 5s instead
+        let meta_data = ResourceMetaData::new(ResourceLifetime::Short);
+        let handle = manager.create(&meta_data);
+        manager.pin(&meta_data.uuid);
+        drop(handle);
+        clock.advance(Duration::from_secs(2));
+        manager.unpin(&meta_data.uuid);
+
+        clock.advance(Duration::from_secs(2));
+        manager.upkeep();
+        assert_eq!(manager.handler.destroyed, 0);
+
+        clock.advance(Duration::from_secs(1));
+        manager.upkeep();
+        assert_eq!(manager.handler.destroyed, 1);
+    }
+
+    #[test]
+    fn test_upkeep_if_elapsed() {
+        let clock = Clock::manual();
+        let mut manager = counting_manager().with_clock(clock.clone());
+        let interval = Duration::from_millis(20);
+
+        assert!(manager.upkeep_if_elapsed(interval));
+        assert!(!manager.upkeep_if_elapsed(interval));
+
+        clock.advance(interval);
+        assert!(manager.upkeep_if_elapsed(interval));
+
+        manager.upkeep();
+        assert!(!manager.upkeep_if_elapsed(interval));
+    }
+
+    #[test]
+    fn test_custom_lifetime() {
+        let clock = Clock::manual();
+        let mut manager = counting_manager().with_clock(clock.clone());
+        let forever = manager.create(&ResourceMetaData::new(ResourceLifetime::Forever));
+        let long = manager.create(&ResourceMetaData::new(ResourceLifetime::Long));
+        let custom = manager.create(&ResourceMetaData::new(
+            ResourceLifetime::Custom(Duration::from_millis(50))
+        ));
+        drop(forever);
+        drop(long);
+        drop(custom);
+
+        manager.upkeep();
+        assert_eq!(manager.handler.destroyed, 0);
+
+        clock.advance(Duration::from_millis(60));
+        manager.upkeep();
+        assert_eq!(manager.handler.destroyed, 1);
+        assert_eq!(manager.resources.len(), 2);
+    }
+
+    #[test]
+    fn test_forever_never_expires() {
+        let clock = Clock::manual();
+        let mut manager = counting_manager().with_clock(clock.clone());
+        drop(manager.create(&ResourceMetaData::new(ResourceLifetime::Forever)));
+
+        clock.advance(Duration::from_secs(365 * 24 * 60 * 60));
+        manager.upkeep();
+        assert_eq!(manager.handler.destroyed, 0);
+    }
+
+    #[test]
+    fn test_soonest_expiry_first() {
+        let clock = Clock::manual();
+        let mut manager = counting_manager().with_clock(clock.clone());
+
+        // The later deletion is queued first and must not hold back the sooner one
+        let long = ResourceMetaData::new(ResourceLifetime::Long);
+        drop(manager.create(&long));
+        drop(manager.create(&ResourceMetaData::new(ResourceLifetime::Short)));
+
+        clock.advance(Duration::from_secs(4));
+        manager.upkeep();
+        assert_eq!(manager.handler.destroyed, 1);
+        assert_eq!(manager.iter().map(|(uuid, _)| uuid).collect::<Vec<_>>(), vec![long.uuid]);
+
+        clock.advance(Duration::from_secs(5 * 60));
+        manager.upkeep();
+        assert_eq!(manager.handler.destroyed, 2);
+    }
+
+    #[test]
+    fn test_handles_with_lifetime() {
+        let mut manager = counting_manager();
+        let short: Vec<ResourceMetaData> = (0..2)
+            .map(|_| ResourceMetaData::new(ResourceLifetime::Short))
+            .collect();
+        let long = ResourceMetaData::new(ResourceLifetime::Long);
+        let custom = ResourceMetaData::new(ResourceLifetime::Custom(Duration::from_secs(1)));
+
+        let _handles: Vec<_> = short.iter().chain([&long, &custom]).map(|m| manager.create(m)).collect();
+
+        let mut found = manager.handles_with_lifetime(ResourceLifetime::Short);
+        let mut expected: Vec<Uuid> = short.iter().map(|m| m.uuid).collect();
+        found.sort();
+        expected.sort();
+        assert_eq!(found, expected);
+
+        assert_eq!(manager.handles_with_lifetime(ResourceLifetime::Long), vec![long.uuid]);
+        assert_eq!(
+            manager.handles_with_lifetime(ResourceLifetime::Custom(Duration::from_secs(1))),
+            vec![custom.uuid]
+        );
+        assert!(manager.handles_with_lifetime(ResourceLifetime::Forever).is_empty());
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let mut manager = counting_manager();
+        let named = ResourceMetaData::new_with_name("atlas", ResourceLifetime::Forever);
+        let mut path_backed = ResourceMetaData::new(ResourceLifetime::Long);
+        path_backed.path = Some(PathBuf::from("textures/stone.png"));
+        let anonymous = ResourceMetaData::new(ResourceLifetime::Custom(Duration::from_secs(2)));
+        let _handles: Vec<_> = [&named, &path_backed, &anonymous].map(|m| manager.create(m)).into();
+
+        let manifest = manager.export_manifest();
+        assert_eq!(manifest.len(), 3);
+        assert!(manifest.contains(&ResourceManifestEntry {
+            uuid: path_backed.uuid,
+            name: None,
+            path: Some(PathBuf::from("textures/stone.png")),
+            lifetime: ResourceLifetime::Long
+        }));
+
+        let mut restored = counting_manager();
+        let restored_handles = restored.import_manifest(&manifest);
+        assert_eq!(restored.handler.created, 3);
+        assert_eq!(
+            restored_handles.iter().map(|handle| handle.uuid()).collect::<Vec<_>>(),
+            manifest.iter().map(|entry| entry.uuid).collect::<Vec<_>>()
+        );
+        assert_eq!(restored.get_from_name("atlas").uuid(), named.uuid);
+        assert_eq!(restored.get_from_path("textures/stone.png").uuid(), path_backed.uuid);
+
+        let sorted = |mut entries: Vec<ResourceManifestEntry>| {
+            entries.sort_by_key(|entry| entry.uuid);
+            entries
+        };
+        assert_eq!(sorted(restored.export_manifest()), sorted(manifest.clone()));
+
+        // Importing again finds the existing resources rather than recreating them
+        restored.import_manifest(&manifest);
+        assert_eq!(restored.handler.created, 3);
+    }
+}
@@ -53,13 +53,21 @@ impl<T> SparseSet<T> {
         }
     }
 
+    /// Adds the element, growing the set if the handle is past its current length
     pub fn push(&mut self, element_id: ElementHandle, element: T) -> &mut T {
+        if element_id >= self.tombstone {
+            self.grow((element_id.0 + 1).max(2 * self.tombstone.0));
+        }
+
         if !self.contains(element_id.into()) {
             let pos = self.dense.len().into();
             self.dense.push(element_id);
             self.dense_objects.push(element);
             self.sparse[element_id.0] = pos;
         }
+
+        #[cfg(debug_assertions)]
+        self.assert_invariants();
         self.get_mut(element_id.into()).unwrap()
     }
 
@@ -77,7 +85,102 @@ impl<T> SparseSet<T> {
         self.sparse.swap(last.0, element_id.0);
         self.sparse[element_id.0] = self.tombstone;
 
-        (self.dense.pop().unwrap(), Some(self.dense_objects.pop().unwrap()))
+        let removed = (self.dense.pop().unwrap(), Some(self.dense_objects.pop().unwrap()));
+        #[cfg(debug_assertions)]
+        self.assert_invariants();
+        removed
+    }
+
+    /// Resizes the sparse array to hold `length` handles. The tombstone moves to
+    /// `length` so it stays strictly greater than every valid handle
+    fn grow(&mut self, length: usize) {
+        debug_assert!(length > self.tombstone.0);
+        let old_tombstone = self.tombstone;
+        self.tombstone = ElementHandle(length);
+
+        for position in self.sparse.iter_mut().filter(|position| **position == old_tombstone) {
+            *position = self.tombstone;
+        }
+        self.sparse.resize(length + 1, self.tombstone);
+
+        #[cfg(debug_assertions)]
+        self.assert_invariants();
+    }
+
+    /// The handle marking empty slots. Every valid handle is strictly less than it
+    pub fn tombstone(&self) -> ElementHandle {
+        self.tombstone
+    }
+
+    /// Swap-removes the element stored at the given dense position. The element that
+    /// was last in the dense array takes its place
+    pub fn remove_at_dense(&mut self, index: usize) -> Option<(ElementHandle, T)> {
+        if index >= self.dense.len() {
+            return None
+        }
+
+        let removed = self.dense.swap_remove(index);
+        let removed_object = self.dense_objects.swap_remove(index);
+
+        if let Some(moved) = self.dense.get(index) {
+            self.sparse[moved.0] = index.into();
+        }
+        self.sparse[removed.0] = self.tombstone;
+
+        #[cfg(debug_assertions)]
+        self.assert_invariants();
+        Some((removed, removed_object))
+    }
+
+    /// Removes the element stored at the given dense position, shifting every later
+    /// element down by one so the dense order is preserved
+    fn shift_remove_at_dense(&mut self, index: usize) -> Option<(ElementHandle, T)> {
+        if index >= self.dense.len() {
+            return None
+        }
+
+        let removed = self.dense.remove(index);
+        let removed_object = self.dense_objects.remove(index);
+
+        for (position, moved) in self.dense.iter().enumerate().skip(index) {
+            self.sparse[moved.0] = position.into();
+        }
+        self.sparse[removed.0] = self.tombstone;
+
+        #[cfg(debug_assertions)]
+        self.assert_invariants();
+        Some((removed, removed_object))
+    }
+
+    /// Panics if the sparse and dense arrays disagree about which elements are live
+    #[cfg(debug_assertions)]
+    pub fn assert_invariants(&self) {
+        assert_eq!(self.dense.len(), self.dense_objects.len(), "dense arrays differ in length");
+
+        for (index, element) in self.dense.iter().enumerate() {
+            assert!(*element < self.tombstone, "dense[{}] holds out of range element {:?}", index, element);
+            assert_eq!(
+                self.sparse[element.0].0, index,
+                "dense[{}] = {:?} does not round-trip through sparse", index, element
+            );
+        }
+
+        let mut seen = vec![false; self.dense.len()];
+        let mut live = 0;
+        for (element, position) in self.sparse.iter().enumerate() {
+            if *position == self.tombstone {
+                continue;
+            }
+            assert!(position.0 < self.dense.len(), "sparse[{}] points past the dense array", element);
+            assert!(!seen[position.0], "sparse[{}] shares dense index {}", element, position.0);
+            seen[position.0] = true;
+            live += 1;
+        }
+
+        assert_eq!(
+            live, self.dense.len(),
+            "every slot that is not a tombstone must be live"
+        );
     }
 
     pub fn contains(&self, element: ElementHandle) -> bool {
@@ -89,8 +192,8 @@ impl<T> SparseSet<T> {
     pub fn clear(&mut self) {
         self.dense.clear();
         self.dense_objects.clear();
-        self.sparse = self.sparse.iter_mut()
-            .map(|_| -> ElementHandle { self.tombstone }).collect();
+        // Reset in place so a set cleared every frame keeps its allocation
+        self.sparse.fill(self.tombstone);
     }
 
     pub fn get(&self, element: ElementHandle) -> Option<&T> {
@@ -107,8 +210,24 @@ impl<T> SparseSet<T> {
         Some(&mut self.dense_objects[self.sparse[element.0].0])
     }
 
+    /// Mutable references to several elements at once. Returns `None` if any handle is
+    /// missing or if two handles refer to the same element
+    pub fn get_many_mut<const N: usize>(&mut self, elements: [ElementHandle; N]) -> Option<[&mut T; N]> {
+        if !elements.iter().all(|element| self.contains(*element)) {
+            return None
+        }
+
+        let dense_indices = elements.map(|element| self.sparse[element.0].0);
+        self.dense_objects.get_disjoint_mut(dense_indices).ok()
+    }
+
+    /// Every element paired with its handle, in dense order
+    pub fn iter(&self) -> impl Iterator<Item = (ElementHandle, &T)> {
+        self.dense.iter().copied().zip(self.dense_objects.iter())
+    }
+
     pub fn get_all_elements(&self) -> Vec<ElementHandle> {
-        self.sparse.iter().filter(|s| { **s != self.tombstone }).copied().collect()
+        self.dense.clone()
     }
 
     pub fn len(&self) -> usize {
@@ -116,11 +235,114 @@ impl<T> SparseSet<T> {
     }
 }
 
+/// Elements are added as if by `push`: a handle that is already present keeps its
+/// existing value and the new one is discarded
+impl<T> Extend<(ElementHandle, T)> for SparseSet<T> {
+    fn extend<I: IntoIterator<Item = (ElementHandle, T)>>(&mut self, iter: I) {
+        for (element_id, element) in iter {
+            self.push(element_id, element);
+        }
+    }
+}
+
+/// A `SparseSet` which iterates in strict insertion order, even across removals, so
+/// iteration is deterministic for replays. Removing shifts every later element down
+/// rather than swapping in the last one, so removal is O(n) instead of O(1)
+pub struct OrderedSparseSet<T> {
+    set: SparseSet<T>
+}
+
+impl<T> OrderedSparseSet<T> {
+    pub fn new(length: usize) -> OrderedSparseSet<T> {
+        OrderedSparseSet {
+            set: SparseSet::new(length)
+        }
+    }
+
+    /// Appends the element after every other. A handle that is already present keeps
+    /// its existing value and position
+    pub fn push(&mut self, element_id: ElementHandle, element: T) -> &mut T {
+        self.set.push(element_id, element)
+    }
+
+    pub fn remove(&mut self, element_id: ElementHandle) -> Option<T> {
+        if !self.set.contains(element_id) {
+            return None
+        }
+
+        let index = self.set.sparse[element_id.0].0;
+        self.set.shift_remove_at_dense(index).map(|(_, element)| element)
+    }
+
+    pub fn contains(&self, element: ElementHandle) -> bool {
+        self.set.contains(element)
+    }
+
+    pub fn clear(&mut self) {
+        self.set.clear();
+    }
+
+    pub fn get(&self, element: ElementHandle) -> Option<&T> {
+        self.set.get(element)
+    }
+
+    pub fn get_mut(&mut self, element: ElementHandle) -> Option<&mut T> {
+        self.set.get_mut(element)
+    }
+
+    /// Every element paired with its handle, in insertion order
+    pub fn iter(&self) -> impl Iterator<Item = (ElementHandle, &T)> {
+        self.set.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.set.len()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_support::Lcg;
     const SPARSE_SET_TEST_SIZE: usize = 100;
 
+    #[test]
+    #[cfg(debug_assertions)]
+    fn test_invariants_under_churn() {
+        let mut set = SparseSet::new(SPARSE_SET_TEST_SIZE);
+        let mut live = std::collections::HashSet::new();
+
+        let mut rng = Lcg::new(0x2545F4914F6CDD1D);
+        let mut next = || rng.next_u31() as usize;
+
+        for _ in 0..5000 {
+            let element = ElementHandle(next() % SPARSE_SET_TEST_SIZE);
+            match next() % 3 {
+                0 | 1 => {
+                    set.push(element, element.0);
+                    live.insert(element);
+                },
+                _ => {
+                    let (_, removed) = set.remove(element);
+                    assert_eq!(removed.is_some(), live.remove(&element));
+                }
+            }
+
+            if next() % 50 == 0 {
+                if let Some((removed, _)) = set.remove_at_dense(next() % (set.len() + 1)) {
+                    live.remove(&removed);
+                }
+            }
+
+            set.assert_invariants();
+            assert_eq!(set.len(), live.len());
+        }
+
+        for element in &live {
+            assert_eq!(set.get(*element), Some(&element.0));
+        }
+    }
+
     #[test]
     fn test_push() {
         let mut set = SparseSet::new(SPARSE_SET_TEST_SIZE);
@@ -146,6 +368,120 @@ mod tests {
         assert_eq!(set.remove(ElementHandle(SPARSE_SET_TEST_SIZE + 1)), (set.tombstone, None));
     }
 
+    #[test]
+    fn test_get_all_elements() {
+        let mut set = SparseSet::new(SPARSE_SET_TEST_SIZE);
+        set.push(ElementHandle(5), 'a');
+        set.push(ElementHandle(2), 'b');
+        set.push(ElementHandle(9), 'c');
+        assert_eq!(set.get_all_elements(), vec![ElementHandle(5), ElementHandle(2), ElementHandle(9)]);
+
+        set.remove(ElementHandle(5));
+        let mut elements = set.get_all_elements();
+        elements.sort();
+        assert_eq!(elements, vec![ElementHandle(2), ElementHandle(9)]);
+    }
+
+    #[test]
+    fn test_remove_at_dense() {
+        let mut set = SparseSet::new(SPARSE_SET_TEST_SIZE);
+        for i in 0..10 {
+            set.push(ElementHandle(i), 5 * i);
+        }
+
+        assert_eq!(set.remove_at_dense(4), Some((ElementHandle(4), 20)));
+        assert_eq!(set.remove_at_dense(2), Some((ElementHandle(2), 10)));
+        assert_eq!(set.remove_at_dense(set.len()), None);
+        assert_eq!(set.len(), 8);
+
+        assert!(!set.contains(ElementHandle(4)));
+        assert!(!set.contains(ElementHandle(2)));
+        for i in [0, 1, 3, 5, 6, 7, 8, 9] {
+            assert_eq!(*set.get(ElementHandle(i)).unwrap(), 5 * i);
+        }
+    }
+
+    #[test]
+    fn test_get_many_mut() {
+        let mut set = SparseSet::new(SPARSE_SET_TEST_SIZE);
+        for i in 0..10 {
+            set.push(ElementHandle(i), i);
+        }
+
+        let [a, b, c] = set.get_many_mut([ElementHandle(1), ElementHandle(5), ElementHandle(8)]).unwrap();
+        *a += 10;
+        *b += 20;
+        *c += 30;
+        assert_eq!(*set.get(ElementHandle(1)).unwrap(), 11);
+        assert_eq!(*set.get(ElementHandle(5)).unwrap(), 25);
+        assert_eq!(*set.get(ElementHandle(8)).unwrap(), 38);
+
+        assert!(set.get_many_mut([ElementHandle(2), ElementHandle(2)]).is_none());
+        assert!(set.get_many_mut([ElementHandle(2), ElementHandle(50)]).is_none());
+    }
+
+    #[test]
+    fn test_extend() {
+        let mut set = SparseSet::new(SPARSE_SET_TEST_SIZE);
+        set.extend((0..5).map(|i| (ElementHandle(i), i)));
+        assert_eq!(set.len(), 5);
+        for i in 0..5 {
+            assert_eq!(*set.get(ElementHandle(i)).unwrap(), i);
+        }
+
+        set.extend((3..8).map(|i| (ElementHandle(i), 10 * i)));
+        assert_eq!(set.len(), 8);
+        assert_eq!(*set.get(ElementHandle(3)).unwrap(), 3);
+        assert_eq!(*set.get(ElementHandle(4)).unwrap(), 4);
+        assert_eq!(*set.get(ElementHandle(7)).unwrap(), 70);
+    }
+
+    #[test]
+    fn test_grow_moves_tombstone() {
+        let mut set = SparseSet::new(4);
+        for i in 0..4 {
+            set.push(ElementHandle(i), i);
+        }
+        set.remove(ElementHandle(2));
+        assert_eq!(set.tombstone(), ElementHandle(4));
+
+        set.push(ElementHandle(10), 10);
+        assert!(set.tombstone() > ElementHandle(10));
+        assert!(set.iter().all(|(handle, _)| handle < set.tombstone()));
+
+        // Slots that were empty before growing are still empty afterwards
+        assert!(!set.contains(ElementHandle(2)));
+        assert!(!set.contains(ElementHandle(4)));
+        assert_eq!(set.get(ElementHandle(10)), Some(&10));
+        assert_eq!(set.get(ElementHandle(3)), Some(&3));
+        assert_eq!(set.len(), 4);
+
+        let tombstone = set.tombstone();
+        assert!(!set.contains(tombstone));
+        assert_eq!(set.remove(tombstone), (tombstone, None));
+    }
+
+    #[test]
+    fn test_clear_keeps_capacity() {
+        let mut set = SparseSet::new(SPARSE_SET_TEST_SIZE);
+        for i in 0..SPARSE_SET_TEST_SIZE {
+            set.push(ElementHandle(i), i);
+        }
+
+        let sparse_capacity = set.sparse.capacity();
+        let dense_capacity = set.dense.capacity();
+        set.clear();
+
+        assert_eq!(set.sparse.capacity(), sparse_capacity);
+        assert_eq!(set.dense.capacity(), dense_capacity);
+        assert_eq!(set.len(), 0);
+        assert!((0..SPARSE_SET_TEST_SIZE).all(|i| !set.contains(ElementHandle(i))));
+
+        set.push(ElementHandle(3), 30);
+        assert_eq!(set.get(ElementHandle(3)), Some(&30));
+        assert_eq!(set.len(), 1);
+    }
+
     #[test]
     fn test_contains() {
         let mut set = SparseSet::new(SPARSE_SET_TEST_SIZE);
@@ -177,4 +513,24 @@ mod tests {
             assert_eq!(*set.get(ElementHandle(i)).unwrap(), i * 6);
         }
     }
+
+    #[test]
+    fn test_ordered_remove_keeps_order() {
+        let mut set = OrderedSparseSet::new(SPARSE_SET_TEST_SIZE);
+        for i in [5, 1, 9, 3, 7] {
+            set.push(ElementHandle(i), i * 10);
+        }
+
+        assert_eq!(set.remove(ElementHandle(9)), Some(90));
+        assert_eq!(set.remove(ElementHandle(9)), None);
+
+        let remaining: Vec<usize> = set.iter().map(|(handle, _)| handle.0).collect();
+        assert_eq!(remaining, vec![5, 1, 3, 7]);
+        assert_eq!(set.get(ElementHandle(7)), Some(&70));
+        assert!(!set.contains(ElementHandle(9)));
+
+        set.push(ElementHandle(9), 91);
+        assert_eq!(set.iter().last(), Some((ElementHandle(9), &91)));
+        assert_eq!(set.len(), 5);
+    }
 }
@@ -0,0 +1,131 @@
+use cgmath::{ Vector2, InnerSpace };
+use std::collections::HashMap;
+use std::hash::Hash;
+use crate::grid::{ self, SpatialGrid };
+
+/// Moving agents bucketed by the cell they are in, for finding nearby agents without
+/// checking every one. Cells are laid out as in a `SpatialGrid` but extend without bound
+pub struct SpatialHash<H> {
+    origin: Vector2<f64>,
+    cell_side_length: f64,
+    cells: HashMap<(i64, i64), Vec<H>>,
+    positions: HashMap<H, Vector2<f64>>
+}
+
+impl<H: Copy + Eq + Hash> SpatialHash<H> {
+    pub fn new(cell_side_length: f64) -> SpatialHash<H> {
+        assert!(cell_side_length > 0.0 && cell_side_length.is_finite(), "cell side length must be positive, got {}", cell_side_length);
+        SpatialHash {
+            origin: Vector2::new(0.0, 0.0),
+            cell_side_length,
+            cells: HashMap::new(),
+            positions: HashMap::new()
+        }
+    }
+
+    /// A hash whose cells line up with the voxels of `grid`
+    pub fn for_grid(grid: &SpatialGrid) -> SpatialHash<H> {
+        SpatialHash {
+            origin: grid.origin,
+            ..SpatialHash::new(grid.voxel_side_length)
+        }
+    }
+
+    fn cell_at(&self, position: Vector2<f64>) -> (i64, i64) {
+        grid::cell_at(self.origin, self.cell_side_length, position)
+    }
+
+    /// Adds the agent at `position`, moving it if it is already present
+    pub fn insert(&mut self, handle: H, position: Vector2<f64>) {
+        self.remove(handle);
+        self.cells.entry(self.cell_at(position)).or_default().push(handle);
+        self.positions.insert(handle, position);
+    }
+
+    /// Removes the agent, returning where it was
+    pub fn remove(&mut self, handle: H) -> Option<Vector2<f64>> {
+        let position = self.positions.remove(&handle)?;
+        let cell = self.cell_at(position);
+        let agents = self.cells.get_mut(&cell).unwrap();
+        agents.retain(|agent| *agent != handle);
+        if agents.is_empty() {
+            self.cells.remove(&cell);
+        }
+        Some(position)
+    }
+
+    pub fn position(&self, handle: H) -> Option<Vector2<f64>> {
+        self.positions.get(&handle).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Every agent within `radius` of `position`, including on its edge. Only the cells
+    /// the radius reaches are checked
+    pub fn neighbors(&self, position: Vector2<f64>, radius: f64) -> Vec<H> {
+        let reach = Vector2::new(radius, radius);
+        let (min_x, min_y) = self.cell_at(position - reach);
+        let (max_x, max_y) = self.cell_at(position + reach);
+
+        (min_y..=max_y)
+            .flat_map(|y| (min_x..=max_x).map(move |x| (x, y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .filter(|agent| (self.positions[agent] - position).magnitude2() <= radius * radius)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(mut handles: Vec<u32>) -> Vec<u32> {
+        handles.sort();
+        handles
+    }
+
+    #[test]
+    fn test_clustered_agents() {
+        let mut hash = SpatialHash::new(4.0);
+        for (handle, offset) in [(1, 0.5), (2, 1.0), (3, 1.5), (4, 3.5)] {
+            hash.insert(handle, Vector2::new(offset, offset));
+        }
+        assert_eq!(hash.cells.len(), 1);
+
+        assert_eq!(sorted(hash.neighbors(Vector2::new(1.0, 1.0), 1.0)), vec![1, 2, 3]);
+        assert_eq!(sorted(hash.neighbors(Vector2::new(1.0, 1.0), 5.0)), vec![1, 2, 3, 4]);
+
+        assert_eq!(hash.remove(2), Some(Vector2::new(1.0, 1.0)));
+        assert_eq!(hash.remove(2), None);
+        assert_eq!(sorted(hash.neighbors(Vector2::new(1.0, 1.0), 1.0)), vec![1, 3]);
+        assert_eq!(hash.len(), 3);
+    }
+
+    #[test]
+    fn test_spread_agents() {
+        let mut grid = SpatialGrid::new(2.0);
+        grid.set_origin(Vector2::new(-10.0, -10.0));
+        let mut hash = SpatialHash::for_grid(&grid);
+
+        hash.insert(1, Vector2::new(0.5, 0.5));
+        hash.insert(2, Vector2::new(-0.5, 0.5));
+        hash.insert(3, Vector2::new(2.5, -1.5));
+        hash.insert(4, Vector2::new(40.0, 40.0));
+        hash.insert(5, Vector2::new(-3.0, 0.0));
+        assert_eq!(hash.cells.len(), 5);
+
+        assert_eq!(sorted(hash.neighbors(Vector2::new(0.0, 0.0), 3.0)), vec![1, 2, 3, 5]);
+        assert_eq!(sorted(hash.neighbors(Vector2::new(0.0, 0.0), 1.0)), vec![1, 2]);
+        assert_eq!(hash.neighbors(Vector2::new(40.0, 41.0), 1.0), vec![4]);
+
+        // Moving an agent takes it out of its old cell
+        hash.insert(4, Vector2::new(1.0, 0.0));
+        assert_eq!(hash.cells.len(), 4);
+        assert_eq!(sorted(hash.neighbors(Vector2::new(0.0, 0.0), 1.0)), vec![1, 2, 4]);
+        assert!(hash.neighbors(Vector2::new(40.0, 40.0), 1.0).is_empty());
+    }
+}
@@ -0,0 +1,62 @@
+/// Seeded linear congruential generator, so randomised tests see the same sequence on
+/// every run
+pub struct Lcg {
+    state: u64
+}
+
+impl Lcg {
+    pub fn new(seed: u64) -> Lcg {
+        Lcg {
+            state: seed
+        }
+    }
+
+    fn step(&mut self) -> u64 {
+        self.state = self.state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        self.state
+    }
+
+    /// The high 31 bits of the state, which have the longest period, so always below
+    /// `1 << 31`
+    pub fn next_u31(&mut self) -> u64 {
+        self.step() >> 33
+    }
+
+    /// Uniform in `[0, 1)`
+    pub fn next_f64(&mut self) -> f64 {
+        (self.step() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Device on whichever adapter is available without a window, such as a software
+/// rasteriser. `None` when there is no adapter at all, so GPU tests can skip themselves
+pub fn headless_device() -> Option<(wgpu::Device, wgpu::Queue)> {
+    let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+    let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions::default()))?;
+    pollster::block_on(adapter.request_device(
+        &wgpu::DeviceDescriptor {
+            label: Some("Test Device"),
+            features: wgpu::Features::empty(),
+            limits: adapter.limits()
+        },
+        None
+    )).ok()
+}
+
+/// A `width` by `height` texture a graph can draw to
+pub fn render_target(device: &wgpu::Device, format: wgpu::TextureFormat, width: u32, height: u32) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Test Render Target"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+        view_formats: &[]
+    })
+}
@@ -1,7 +1,60 @@
 
-
-#[derive(Copy, Clone, Default)]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
 pub struct Voxel {
-    pub element_id: u16
+    pub element_id: u16,
+    pub colour: [u8; 4]
+}
+
+impl Voxel {
+    pub fn new(element_id: u16) -> Voxel {
+        VoxelBuilder::voxel().element(element_id).build()
+    }
 }
 
+#[derive(Debug, Copy, Clone, Default)]
+pub struct VoxelBuilder {
+    element_id: u16,
+    colour: [u8; 4]
+}
+
+impl VoxelBuilder {
+    pub fn voxel() -> Self {
+        VoxelBuilder::default()
+    }
+
+    pub fn element(mut self, element_id: u16) -> Self {
+        self.element_id = element_id;
+        self
+    }
+
+    pub fn colour(mut self, colour: [u8; 4]) -> Self {
+        self.colour = colour;
+        self
+    }
+
+    pub fn build(self) -> Voxel {
+        Voxel {
+            element_id: self.element_id,
+            colour: self.colour
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builder() {
+        assert_eq!(VoxelBuilder::voxel().build(), Voxel::default());
+        assert_eq!(Voxel::new(0), Voxel::default());
+
+        let voxel = VoxelBuilder::voxel()
+            .element(4)
+            .colour([255, 128, 0, 255])
+            .build();
+        assert_eq!(voxel.element_id, 4);
+        assert_eq!(voxel.colour, [255, 128, 0, 255]);
+        assert_eq!(Voxel::new(4).element_id, 4);
+    }
+}
@@ -5,6 +5,8 @@ use winit::{
 };
 
 use crate::render;
+use crate::frame_timing::{ self, Redraw };
+use crate::render_engine::{ self, AdapterOptions, SurfaceOptions, RenderEngineError };
 use crate::render_graph::resource::Resource;
 use crate::render_graph::shader_builder::{ ShaderHandle, ShaderStage, ShaderRepresentation, ShaderBuilder, WgslBuilder };
 use crate::render_graph::pipeline_builder::PipelineLayoutBuilder;
@@ -14,6 +16,7 @@ use crate::render_graph::CompiledGraph;
 use petgraph::dot::Dot;
 
 use std::collections::HashMap;
+use std::time::{ Instant, Duration };
 
 struct State<'s> {
     surface: wgpu::Surface,
@@ -22,30 +25,28 @@ struct State<'s> {
     config: wgpu::SurfaceConfiguration,
     shader_handle: ShaderHandle,
     shader: ShaderBuilder<'s, WgslBuilder<'s>>,
-    render_graph: RenderGraph<'s>
+    render_graph: RenderGraph<'s>,
+    /// How far between the last fixed update and the next the frame being drawn is, for
+    /// rendering to interpolate by
+    interpolation: f64
 }
 
 impl State<'_> {
-    async fn new(window: &window::Window) -> State {
-        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
-            backends: wgpu::Backends::all(),
-            dx12_shader_compiler: Default::default()
-        });
+    async fn new<'w>(
+        window: &'w window::Window,
+        options: &AdapterOptions,
+        surface_options: &SurfaceOptions
+    ) -> Result<State<'w>, RenderEngineError> {
+        let instance = options.create_instance();
 
         /* # Safety
          *
          * The  surface only needs to live as long as the window, and the window owns the
          * state so this will remain valid
          */
-        let surface = unsafe { instance.create_surface(window) }.unwrap();
+        let surface = unsafe { instance.create_surface(window) }?;
 
-        let adapter = instance.request_adapter(
-            &wgpu::RequestAdapterOptions {
-                power_preference: wgpu::PowerPreference::default(),
-                compatible_surface: Some(&surface),
-                force_fallback_adapter: false,
-            },
-        ).await.unwrap();
+        let adapter = options.request_adapter(&instance, Some(&surface)).await?;
 
         let (device, queue) = adapter.request_device(
             &wgpu::DeviceDescriptor {
@@ -54,24 +55,19 @@ impl State<'_> {
                 label: None,
             },
             None
-        ).await.unwrap();
+        ).await?;
 
         let surface_caps = surface.get_capabilities(&adapter);
-        let surface_format = surface_caps.formats.iter()
-            .copied()
-            .find(|f| f.is_srgb())
-            .unwrap_or(surface_caps.formats[0]);
+        let surface_format = render_engine::surface_format(&surface_caps.formats)?;
 
         let size = window.inner_size();
-        let config = wgpu::SurfaceConfiguration {
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
-            format: surface_format,
-            width: size.width,
-            height: size.height,
-            present_mode: surface_caps.present_modes[0],
-            alpha_mode: surface_caps.alpha_modes[0],
-            view_formats: vec![]
-        };
+        let config = render_engine::surface_configuration(
+            &surface_caps,
+            surface_format,
+            size.width,
+            size.height,
+            surface_options
+        )?;
         surface.configure(&device, &config);
 
         let shader = ShaderBuilder::shader(WgslBuilder::from_file("triangle.wgsl"))
@@ -112,15 +108,16 @@ impl State<'_> {
             std::fs::write("test.graph", format!("{:?}", dot)).unwrap();
         };
 
-        State {
+        Ok(State {
             surface,
             device,
             queue: render::Queue::Render(queue),
             config,
             shader_handle,
             shader,
-            render_graph
-        }
+            render_graph,
+            interpolation: 0.0
+        })
     }
 
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
@@ -150,25 +147,76 @@ impl State<'_> {
     }
 }
 
+/// A simulation callback run at a fixed rate, independent of the frame rate
+struct FixedUpdate {
+    step: Duration,
+    accumulator: Duration,
+    update: Box<dyn FnMut(Duration)>
+}
+
 pub struct Window<'s> {
     state: State<'s>,
     size: winit::dpi::PhysicalSize<u32>,
     event_loop: Option<EventLoop<()>>,
-    window: window::Window
+    window: window::Window,
+    target_fps: Option<u32>,
+    last_redraw: Instant,
+    fixed_update: Option<FixedUpdate>
 }
 
 impl Window<'_> {
-    pub async fn new<'w>() -> Window<'w> {
+    const DEFAULT_TARGET_FPS: u32 = 60;
+
+    pub async fn new<'w>(options: &AdapterOptions, surface_options: &SurfaceOptions) -> Result<Window<'w>, RenderEngineError> {
         let event_loop = EventLoop::new();
-        let window = WindowBuilder::new().build(&event_loop).unwrap();
+        let window = WindowBuilder::new().build(&event_loop)?;
         let size = window.inner_size();
 
-        Window {
-            state: State::new(&window).await,
+        Ok(Window {
+            state: State::new(&window, options, surface_options).await?,
             size,
             event_loop: Some(event_loop),
-            window
+            window,
+            target_fps: None,
+            last_redraw: Instant::now(),
+            fixed_update: None
+        })
+    }
+
+    /// Caps how often frames are drawn. `None` draws as fast as possible
+    pub fn set_target_fps(&mut self, target_fps: Option<u32>) {
+        self.target_fps = target_fps;
+    }
+
+    /// Calls `update` with a fixed timestep `updates_per_second` times a second of
+    /// elapsed time, running zero or more updates before each frame is drawn
+    pub fn set_fixed_update(&mut self, updates_per_second: u32, update: impl FnMut(Duration) + 'static) {
+        self.fixed_update = Some(FixedUpdate {
+            step: frame_timing::frame_interval(updates_per_second),
+            accumulator: Duration::ZERO,
+            update: Box::new(update)
+        });
+    }
+
+    /// Runs every fixed update that has come due since the last frame
+    fn run_fixed_updates(&mut self, elapsed: Duration) {
+        let Some(fixed_update) = &mut self.fixed_update else {
+            return
+        };
+
+        let updates = frame_timing::fixed_updates(fixed_update.accumulator, elapsed, fixed_update.step);
+        for _ in 0..updates.steps {
+            (fixed_update.update)(fixed_update.step);
         }
+        fixed_update.accumulator = updates.accumulator;
+        self.state.interpolation = updates.alpha;
+    }
+
+    fn toggle_frame_cap(&mut self) {
+        self.target_fps = match self.target_fps {
+            Some(_) => None,
+            None => Some(Self::DEFAULT_TARGET_FPS)
+        };
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -183,6 +231,14 @@ impl Window<'_> {
             WindowEvent::ScaleFactorChanged{ new_inner_size, .. } => {
                 self.resize(**new_inner_size)
             },
+            WindowEvent::KeyboardInput {
+                input: KeyboardInput {
+                    state: ElementState::Pressed,
+                    virtual_keycode: Some(VirtualKeyCode::F),
+                    ..
+                },
+                ..
+            } => self.toggle_frame_cap(),
             _ => ()
         }
     }
@@ -198,6 +254,9 @@ impl Window<'_> {
                 _ => self.handle_window_event(event)
             },
             Event::RedrawRequested(window_id) if window_id == self.window.id() => {
+                let now = Instant::now();
+                self.run_fixed_updates(now - self.last_redraw);
+                self.last_redraw = now;
                 match self.state.render() {
                     Ok(_) => {},
                     Err(wgpu::SurfaceError::Lost) => self.state.resize(self.size),
@@ -205,7 +264,15 @@ impl Window<'_> {
                     Err(e) => eprintln!("{:?}", e)
                 }
             }
-            Event::MainEventsCleared => self.window.request_redraw(),
+            Event::MainEventsCleared => {
+                match frame_timing::next_redraw(self.last_redraw, Instant::now(), self.target_fps) {
+                    Redraw::Now => {
+                        *control_flow = ControlFlow::Poll;
+                        self.window.request_redraw()
+                    },
+                    Redraw::WaitUntil(due) => *control_flow = ControlFlow::WaitUntil(due)
+                }
+            },
             _ => ()
         });
     }